@@ -0,0 +1,194 @@
+//! Measures the cpu time of the prepare systems with many grass chunks
+//!
+//! The bind groups of the chunks are cached and only created again if a chunk changes.
+//! The benchmark renders the same chunks with the cache and with the bind groups of every chunk rebuilt in every frame,
+//! alternating between both in several rounds.
+//! Only the time spent in the systems of the `prepare` module is measured, which is read from their trace spans.
+//! Run it with `cargo bench --features bench --bench prepare_chunks`, which requires a gpu
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use bevy::{
+    log::LogPlugin,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        primitives::Aabb,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+    },
+    utils::tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id},
+        Subscriber,
+    },
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+use tracing_subscriber::{
+    layer::{Context, Layer},
+    prelude::*,
+    registry::LookupSpan,
+};
+use warbler_grass::{diagnostic::DISABLE_CHUNK_CACHE, prelude::*};
+
+const CHUNKS: u32 = 2000;
+const WARMUP_FRAMES: u32 = 20;
+const ROUNDS: u32 = 5;
+/// The frames measured per round with and without the cache
+const FRAMES: u32 = 20;
+/// The systems whose time is measured
+const PREPARE_MODULE: &str = "warbler_grass::render::prepare::";
+
+/// The time spent in the measured systems since the last reset, in nanoseconds
+static PREPARE_NANOS: AtomicU64 = AtomicU64::new(0);
+
+fn main() {
+    // replaces the subscriber of the LogPlugin
+    tracing_subscriber::registry().with(PrepareTimer).init();
+    let mut app = App::new();
+    app.add_plugins((
+        DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                close_when_requested: false,
+            })
+            .build()
+            .disable::<WinitPlugin>()
+            .disable::<LogPlugin>(),
+        WarblersPlugin,
+    ))
+    // multisampling doesn't change the cpu time, but isn't supported by all headless gpus
+    .insert_resource(Msaa::Off)
+    .add_systems(Startup, (spawn_camera, spawn_chunks));
+    // the renderer is initialized in the background
+    while !app.ready() {
+        bevy::tasks::tick_global_task_pools_on_main_thread();
+    }
+    app.finish();
+    app.cleanup();
+    // the chunks are dithered in the background
+    for _ in 0..WARMUP_FRAMES {
+        app.update();
+    }
+
+    let (mut cached, mut rebuilt) = (Duration::ZERO, Duration::ZERO);
+    for _ in 0..ROUNDS {
+        cached += measure(&mut app, false);
+        rebuilt += measure(&mut app, true);
+    }
+
+    println!(
+        "{CHUNKS} chunks, average over {} frames each",
+        ROUNDS * FRAMES
+    );
+    println!(
+        "cached bind groups:    {:>10.3?} per frame",
+        cached / ROUNDS
+    );
+    println!(
+        "rebuilt bind groups:   {:>10.3?} per frame",
+        rebuilt / ROUNDS
+    );
+}
+
+/// Returns the average time of the prepare systems per frame
+fn measure(app: &mut App, disable_cache: bool) -> Duration {
+    DISABLE_CHUNK_CACHE.store(disable_cache, Ordering::Relaxed);
+    // the cache is filled again after frames without it
+    app.update();
+    PREPARE_NANOS.store(0, Ordering::Relaxed);
+    for _ in 0..FRAMES {
+        app.update();
+    }
+    Duration::from_nanos(PREPARE_NANOS.load(Ordering::Relaxed)) / FRAMES
+}
+
+/// Adds the time spent in the spans of the measured systems to [`PREPARE_NANOS`]
+struct PrepareTimer;
+
+/// Marks the span of a measured system, holding the time it was entered
+struct Timed(Option<Instant>);
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for PrepareTimer {
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        // the executors create a `system` span with the name of the system around each run
+        if attributes.metadata().name() != "system" {
+            return;
+        }
+        let mut visitor = PrepareSystem(false);
+        attributes.record(&mut visitor);
+        if let (true, Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(Timed(None));
+        }
+    }
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timed) = span.extensions_mut().get_mut::<Timed>() {
+                timed.0 = Some(Instant::now());
+            }
+        }
+    }
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(start) = span
+                .extensions_mut()
+                .get_mut::<Timed>()
+                .and_then(|timed| timed.0.take())
+            {
+                PREPARE_NANOS.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Checks if the `name` field of a span is one of the measured systems
+struct PrepareSystem(bool);
+
+impl Visit for PrepareSystem {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "name" {
+            self.0 = value.starts_with(PREPARE_MODULE);
+        }
+    }
+    // the name of the system is always recorded as a str
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+/// Spawns a camera overlooking all chunks, rendering into an image as there is no window
+fn spawn_camera(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let size = Extent3d {
+        width: 512,
+        height: 512,
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image::new_fill(
+        size,
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Bgra8UnormSrgb,
+    );
+    image.texture_descriptor.usage =
+        TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT;
+    commands.spawn(Camera3dBundle {
+        camera: Camera {
+            target: RenderTarget::Image(images.add(image)),
+            ..default()
+        },
+        transform: Transform::from_xyz(250., 400., -100.)
+            .looking_at(Vec3::new(250., 0., 200.), Vec3::Y),
+        ..default()
+    });
+}
+
+fn spawn_chunks(mut commands: Commands) {
+    let size = Vec3::new(10., 2., 10.);
+    for chunk in 0..CHUNKS {
+        let offset = Vec3::new((chunk % 50) as f32, 0., (chunk / 50) as f32) * size;
+        commands.spawn(WarblersBundle {
+            aabb: Aabb::from_min_max(Vec3::ZERO, size),
+            spatial: SpatialBundle::from_transform(Transform::from_translation(offset)),
+            ..default()
+        });
+    }
+}
@@ -44,6 +44,7 @@ fn setup_grass(
     let density_map = DensityMap {
         density_map: density_map_texture.clone(),
         density: 2.,
+        strategy: DitheringStrategy::default(),
     };
     let quad_handle = meshes.add(Mesh::from(shape::Quad::new(Vec2::new(10., 10.))));
     let material_handle = materials.add(StandardMaterial {
@@ -63,7 +64,11 @@ fn setup_grass(
     commands.spawn(WarblersBundle {
         density_map,
         y_map,
-        height: warbler_grass::prelude::WarblerHeight::Texture(heights_map_texture.clone()),
+        height: warbler_grass::prelude::WarblerHeight::Texture {
+            texture: heights_map_texture.clone(),
+            min: 1.,
+            max: 2.,
+        },
         aabb: Aabb::from_min_max(Vec3::ZERO, Vec3::new(100., 5., 100.)),
         spatial: SpatialBundle {
             transform: Transform::from_xyz(0., 1., 0.),
@@ -104,7 +109,11 @@ fn refresh_texture_view(
                 mat.base_color_texture = Some(density_map.density_map.clone())
             }
             SelectedMap::HeightsMap => {
-                if let WarblerHeight::Texture(heights_map) = heights {
+                if let WarblerHeight::Texture {
+                    texture: heights_map,
+                    ..
+                } = heights
+                {
                     mat.base_color_texture = Some(heights_map.clone())
                 } else {
                     mat.base_color_texture = Some(density_map.density_map.clone())
@@ -24,6 +24,7 @@ fn setup_grass(mut commands: Commands, asset_server: Res<AssetServer>) {
     let density_map = DensityMap {
         density_map,
         density: 2.,
+        strategy: DitheringStrategy::default(),
     };
     commands.spawn((WarblersBundle {
         density_map,
@@ -51,6 +51,7 @@ fn setup_grass(
     let density_map = DensityMap {
         density_map,
         density: 1.,
+        strategy: DitheringStrategy::default(),
     };
     // simple add the grass mesh in the bundle, instead of using the default
     commands.spawn(WarblersBundle {
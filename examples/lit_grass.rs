@@ -0,0 +1,93 @@
+//! Shows grass which is lit by the lights of the scene
+use bevy::{prelude::*, render::primitives::Aabb};
+use warbler_grass::prelude::*;
+mod helper;
+fn main() {
+    App::new()
+        .add_plugins((
+            DefaultPlugins,
+            // This plugin is needed to initialize everything for the grass render pipeline
+            WarblersPlugin,
+            // Just a helper plugin for spawning a camera
+            // As in all examples, you can use the wasd keys for movement and qe for rotation
+            helper::SimpleCamera,
+        ))
+        // A dim ambient light, so the side of the blades facing away from the sun isn't black
+        .insert_resource(AmbientLight {
+            color: Color::WHITE,
+            brightness: 0.3,
+        })
+        .add_systems(Startup, (setup_grass, setup_lights, setup_pillar))
+        .add_systems(Update, move_sun)
+        .run();
+}
+fn setup_grass(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let y_map_image = asset_server.load("grass_y_map.png");
+    let y_map = YMap { y_map: y_map_image };
+
+    let normal_map_image = asset_server.load("grass_normal_map.png");
+    let normal_map = NormalMap {
+        normal_map: normal_map_image,
+    };
+
+    let density_map = asset_server.load("grass_density_map.png");
+    let density_map = DensityMap {
+        density_map,
+        density: 2.,
+        strategy: DitheringStrategy::BlueNoise { seed: 0 },
+    };
+    commands.spawn(WarblersBundle {
+        y_map,
+        normal_map,
+        density_map,
+        height: WarblerHeight::Uniform(2.),
+        aabb: Aabb::from_min_max(Vec3::ZERO, Vec3::new(100., 5., 100.)),
+        // The grass is now shaded by the lights of the scene.
+        // Rough blades with soft highlights look the most natural
+        lighting: GrassLighting::LIT,
+        ..default()
+    });
+}
+fn setup_lights(mut commands: Commands) {
+    commands.spawn(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            illuminance: 20000.,
+            // The grass receives the shadows of other objects
+            shadows_enabled: true,
+            ..default()
+        },
+        ..default()
+    });
+    // A warm point light in the middle of the field
+    commands.spawn(PointLightBundle {
+        point_light: PointLight {
+            color: Color::ORANGE,
+            intensity: 20000.,
+            range: 40.,
+            shadows_enabled: true,
+            ..default()
+        },
+        transform: Transform::from_xyz(50., 8., 50.),
+        ..default()
+    });
+}
+// A pillar casting its shadow onto the grass
+fn setup_pillar(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(shape::Box::new(2., 15., 2.).into()),
+        material: materials.add(Color::GRAY.into()),
+        transform: Transform::from_xyz(40., 7.5, 40.),
+        ..default()
+    });
+}
+// The sun rises and sets, so the grass gets dark at night
+fn move_sun(mut suns: Query<&mut Transform, With<DirectionalLight>>, time: Res<Time>) {
+    let angle = time.elapsed_seconds() / 4.;
+    for mut transform in &mut suns {
+        *transform = Transform::from_rotation(Quat::from_rotation_x(-angle));
+    }
+}
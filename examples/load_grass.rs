@@ -35,6 +35,9 @@ fn setup_grass(mut commands: Commands, asset_server: Res<AssetServer>) {
         // The density corresponds to how dense a dense area is supposed to be
         // Be careful with this parameter since the blade count grows fast
         density: 2.,
+        // The strategy defines how the blades are placed in the dense areas.
+        // Blue noise avoids the visible rows of the default bayer dithering
+        strategy: DitheringStrategy::BlueNoise { seed: 0 },
     };
     // spawns the "chunk" entity
     commands.spawn(WarblersBundle {
@@ -45,6 +48,9 @@ fn setup_grass(mut commands: Commands, asset_server: Res<AssetServer>) {
         height: WarblerHeight::Uniform(2.),
         // The aabb defines the area in which the chunk lives in
         aabb: Aabb::from_min_max(Vec3::ZERO, Vec3::new(100., 5., 100.)),
+        // Interpolates between the pixels of the y-map and normal map,
+        // so the small maps don't result in a staircase
+        map_sampling: MapSampling::Linear,
         ..default()
     });
 }
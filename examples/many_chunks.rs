@@ -26,10 +26,19 @@ fn setup_grass_chunks(mut commands: Commands, asset_server: Res<AssetServer>) {
     let density_map = DensityMap {
         density_map: density_map_handle.clone(),
         density: 2.,
+        strategy: DitheringStrategy::default(),
     };
     let y_map_image = asset_server.load("grass_y_map.png");
 
     let y_map = YMap { y_map: y_map_image };
+    // distant chunks only draw a fraction of their blades
+    let lod = GrassLod::new(vec![
+        LodLevel::new(100., 0.5),
+        LodLevel::new(250., 0.2),
+        LodLevel::new(500., 0.05),
+    ])
+    // the dropped blades shrink over 20 units
+    .with_transition(20.);
     // each chunk is 50x50
     let (chunk_width, chunk_height) = (50., 50.);
     // spawns a 20x20 grid of chunks
@@ -45,24 +54,37 @@ fn setup_grass_chunks(mut commands: Commands, asset_server: Res<AssetServer>) {
             ((chunk % chunk_width as i32) as f32 / chunk_width) + 0.5,
             0.,
         );
-        commands.spawn(WarblersBundle {
-            // we could use seperate density maps for each one
-            density_map: density_map.clone(),
-            // or seperate height maps if we wanted to
-            y_map: y_map.clone(),
-            height: WarblerHeight::Texture(density_map_handle.clone()),
-            // the aabb defined the dimensions of the box the chunk lives in
-            aabb: Aabb::from_min_max(Vec3::ZERO, Vec3::new(chunk_width, 2., chunk_height)),
-            grass_color: GrassColor {
-                main_color: color.clone(),
-                bottom_color: color * 0.4,
-            },
+        commands.spawn((
+            WarblersBundle {
+                // we could use seperate density maps for each one
+                density_map: density_map.clone(),
+                // or seperate height maps if we wanted to
+                y_map: y_map.clone(),
+                // dense areas have higher grass
+                height: WarblerHeight::Texture {
+                    texture: density_map_handle.clone(),
+                    min: 1.,
+                    max: 2.,
+                },
+                // the aabb defined the dimensions of the box the chunk lives in
+                aabb: Aabb::from_min_max(Vec3::ZERO, Vec3::new(chunk_width, 2., chunk_height)),
+                grass_color: GrassColor {
+                    main_color: color.clone(),
+                    bottom_color: color * 0.4,
+                },
+                lod: lod.clone(),
 
-            spatial: SpatialBundle {
-                transform: Transform::from_translation(offset),
+                spatial: SpatialBundle {
+                    transform: Transform::from_translation(offset),
+                    ..default()
+                },
                 ..default()
             },
-            ..default()
-        });
+            // the blades shrink into the distance instead of ending abruptly
+            GrassFade {
+                start: 600.,
+                end: 800.,
+            },
+        ));
     }
 }
@@ -16,15 +16,18 @@ fn main() {
             helper::SimpleCamera,
             // Let's also log the amount of blades rendered
             // Since we spawn all grass in one huge chunk all blades get rendered
-            // as long as one is on the screen (normally you'd devide the area into chunks)
+            // as long as one is on the screen (normally you'd devide the area into chunks).
+            // The `GpuCulling` of the chunk still skips the blades outside of the screen on the gpu
             WarblerDiagnosticsPlugin,
             LogDiagnosticsPlugin::default(),
         ))
         // creates our grass
         .add_systems(Startup, setup_grass)
-        // more wind
+        // a stormy wind with strong gusts and small swirls
         .insert_resource(GrassConfiguration {
             wind: Vec2::new(2., 2.),
+            gust_amplitude: 0.8,
+            turbulence: 0.2,
             ..default()
         })
         .run();
@@ -41,20 +44,25 @@ fn setup_grass(mut commands: Commands, asset_server: Res<AssetServer>) {
         density_map: density_map_image,
         // The density defines how many blades in a dense area spawns.
         density: 4.,
+        strategy: DitheringStrategy::default(),
     };
     // spawn the entity rendering out large grass chunk
-    commands.spawn(WarblersBundle {
-        density_map,
-        y_map,
-        height: WarblerHeight::Uniform(5.),
-        // Let's make a large chunk
-        // With our density map we spawn around 10 million blades on this area
-        aabb: Aabb::from_min_max(Vec3::ZERO, Vec3::new(1000., 0., 1000.)),
-        spatial: SpatialBundle {
-            // translate the chunk so we are in a nice middle place
-            transform: Transform::from_xyz(-480., -5., -480.),
+    commands.spawn((
+        WarblersBundle {
+            density_map,
+            y_map,
+            height: WarblerHeight::Uniform(5.),
+            // Let's make a large chunk
+            // With our density map we spawn around 10 million blades on this area
+            aabb: Aabb::from_min_max(Vec3::ZERO, Vec3::new(1000., 0., 1000.)),
+            spatial: SpatialBundle {
+                // translate the chunk so we are in a nice middle place
+                transform: Transform::from_xyz(-480., -5., -480.),
+                ..default()
+            },
             ..default()
         },
-        ..default()
-    });
+        // only the blades on the screen are drawn, instead of the whole chunk
+        GpuCulling::default(),
+    ));
 }
@@ -12,7 +12,7 @@ use crate::{
     map::DensityMap,
     map::NormalMap,
     map::YMap,
-    warblers_plugin::{GRASS_MESH_HANDLE, DEFAULT_NORMAL_MAP_HANDLE},
+    warblers_plugin::{DEFAULT_NORMAL_MAP_HANDLE, GRASS_MESH_HANDLE},
 };
 
 /// This [`Bundle`] spawns a grass chunk in the world.
@@ -37,12 +37,32 @@ pub struct WarblersBundle {
     pub density_map: DensityMap,
     /// An [`WarblerHeight`] component
     pub height: WarblerHeight,
+    /// An [`BladeSizeVariation`] component
+    ///
+    /// Defaults to no variation.
+    pub size_variation: BladeSizeVariation,
     /// An [`GrassColor`] component
     pub grass_color: GrassColor,
     /// An [`Aabb`] component
     ///
     /// Note that the Aabb is used to define the world dimensions of the [`DensityMap`] and [`YMap`].
     pub aabb: Aabb,
+    /// An [`DitheringSpace`] component
+    ///
+    /// Defaults to [`DitheringSpace::Local`].
+    pub dithering_space: DitheringSpace,
+    /// An [`MapSampling`] component
+    ///
+    /// Defaults to [`MapSampling::Nearest`].
+    pub map_sampling: MapSampling,
+    /// An [`GrassLighting`] component
+    ///
+    /// Defaults to [`GrassLighting::Unlit`].
+    pub lighting: GrassLighting,
+    /// A [`GrassLod`] component
+    ///
+    /// Defaults to no level of detail, meaning all blades are drawn at any distance.
+    pub lod: GrassLod,
     pub spatial: SpatialBundle,
 }
 impl Default for WarblersBundle {
@@ -53,8 +73,13 @@ impl Default for WarblersBundle {
             normal_map: DEFAULT_NORMAL_MAP_HANDLE.typed().into(),
             density_map: DEFAULT_IMAGE_HANDLE.typed().into(),
             height: WarblerHeight::Uniform(1.),
+            size_variation: BladeSizeVariation::default(),
             grass_color: GrassColor::default(),
             aabb: Aabb::default(),
+            dithering_space: DitheringSpace::default(),
+            map_sampling: MapSampling::default(),
+            lighting: GrassLighting::default(),
+            lod: GrassLod::default(),
             spatial: SpatialBundle::default(),
         }
     }
@@ -68,8 +93,42 @@ pub enum WarblerHeight {
     Uniform(f32),
     /// Samples the height from an [`Image`]
     ///
-    /// The [`Image`] will be scaled over the plane defined by the [`Aabb`]
-    Texture(Handle<Image>),
+    /// The [`Image`] will be scaled over the plane defined by the [`Aabb`].
+    /// 16 bit pngs and float textures are supported as well
+    Texture {
+        /// The [`Image`] containing the heights of the blades in its red channel
+        texture: Handle<Image>,
+        /// The height of the blades where the texture is black
+        min: f32,
+        /// The height of the blades where the texture is white
+        max: f32,
+    },
+}
+/// Randomly scales the size of each grass blade
+///
+/// The height and width of a blade are multiplied with random factors between the given minimum and maximum,
+/// which are derived from the position of the blade.
+/// Can be used in Combination with the [`WarblersBundle`] to spawn grass chunks
+#[derive(Component, Clone, Copy, Debug, PartialEq, ExtractComponent)]
+pub struct BladeSizeVariation {
+    /// The minimum factor applied to the height of a blade
+    pub min_height: f32,
+    /// The maximum factor applied to the height of a blade
+    pub max_height: f32,
+    /// The minimum factor applied to the width of a blade
+    pub min_width: f32,
+    /// The maximum factor applied to the width of a blade
+    pub max_width: f32,
+}
+impl Default for BladeSizeVariation {
+    fn default() -> Self {
+        BladeSizeVariation {
+            min_height: 1.,
+            max_height: 1.,
+            min_width: 1.,
+            max_width: 1.,
+        }
+    }
 }
 /// Defines the color of the grass blades
 #[derive(Component, Clone, ExtractComponent)]
@@ -89,6 +148,205 @@ impl Default for GrassColor {
         }
     }
 }
+/// Defines in which space the grass blades of a chunk are placed
+///
+/// Can be used in Combination with the [`WarblersBundle`] to spawn grass chunks
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, ExtractComponent)]
+pub enum DitheringSpace {
+    /// The blades are placed relative to the chunk.
+    ///
+    /// Neighbouring chunks show the same pattern, which might be visible at their borders.
+    #[default]
+    Local,
+    /// The blades are placed on a grid aligned to the world, using the [`GlobalTransform`](bevy::prelude::GlobalTransform) of the chunk.
+    ///
+    /// This way a terrain split into many chunks looks like one continuous field.
+    /// Only the translation of the chunk is taken into account and moving the chunk dithers it again.
+    World,
+}
+/// Defines how the [`YMap`], [`NormalMap`] and the height texture of a chunk are sampled
+///
+/// Can be used in Combination with the [`WarblersBundle`] to spawn grass chunks
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, ExtractComponent)]
+pub enum MapSampling {
+    /// Uses the nearest pixel of the maps.
+    ///
+    /// Small maps covering large areas result in a visible staircase.
+    #[default]
+    Nearest,
+    /// Interpolates bilinearly between the pixels of the maps.
+    ///
+    /// This way small maps can cover large areas smoothly.
+    /// Maps with a format which can't be filtered, like `R32Float` or 16 bit pngs, fall back to [`MapSampling::Nearest`].
+    Linear,
+}
+/// Defines how the grass blades of a chunk are shaded
+///
+/// Can be used in Combination with the [`WarblersBundle`] to spawn grass chunks
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, ExtractComponent)]
+pub enum GrassLighting {
+    /// The blades only show the colors of their [`GrassColor`] and ignore all lights of the scene.
+    #[default]
+    Unlit,
+    /// The blades are lit by the directional, point, spot and ambient lights of the scene and covered by its fog.
+    ///
+    /// The colors of the [`GrassColor`] are used as base color of the blades.
+    /// The normals of the blades are derived from the orientation of their faces.
+    ///
+    /// The blades receive the shadows of directional, point and spot lights with enabled shadows.
+    /// Insert a [`NotShadowReceiver`](bevy::pbr::NotShadowReceiver) component into a chunk to disable this.
+    Lit {
+        /// The perceptual roughness of the blades, between 0.089 and 1
+        perceptual_roughness: f32,
+        /// The specular intensity of the blades, between 0 and 1
+        ///
+        /// A reflectance of 0 removes all highlights, which results in a purely diffuse shading.
+        reflectance: f32,
+    },
+}
+impl GrassLighting {
+    /// Lit grass with a rough surface and soft highlights
+    pub const LIT: GrassLighting = GrassLighting::Lit {
+        perceptual_roughness: 0.8,
+        reflectance: 0.3,
+    };
+}
+/// Excludes a grass chunk from the depth, normal and motion vector prepass of the cameras
+///
+/// The prepass is needed by effects like screen space ambient occlusion and temporal anti aliasing.
+/// Excluding big chunks of grass from it can improve the performance, if these effects don't need to take the grass into account.
+///
+/// To exclude a chunk from the shadow maps, use the [`NotShadowCaster`](bevy::pbr::NotShadowCaster) component.
+#[derive(Component, Clone, Copy, Debug, Default, ExtractComponent)]
+pub struct NoPrepass;
+/// Reduces the detail of a grass chunk with increasing distance to the camera
+///
+/// The distance is measured from each camera to the closest point of the [`Aabb`] of the chunk.
+/// The [`LodLevel`] with the greatest distance not exceeding it is used.
+/// Closer than all levels, the chunk is drawn in full detail.
+///
+/// Shadows use the most detailed level of the chunk over all cameras.
+/// Can be used in Combination with the [`WarblersBundle`] to spawn grass chunks
+///
+/// # Example
+/// ```rust
+/// use warbler_grass::prelude::*;
+///
+/// let lod = GrassLod::new(vec![
+///     // half of the blades from 50 units on
+///     LodLevel::new(50., 0.5),
+///     // a tenth of the blades from 150 units on
+///     LodLevel::new(150., 0.1),
+///     // no blades from 300 units on
+///     LodLevel::new(300., 0.),
+/// ]);
+/// ```
+#[derive(Component, Clone, Debug)]
+pub struct GrassLod {
+    /// The levels of the chunk, in any order
+    ///
+    /// Only the transitions of the four closest levels are smoothed.
+    pub levels: Vec<LodLevel>,
+    /// The distance over which the blades dropped by a level shrink before reaching it
+    ///
+    /// This hides the transitions between the levels. A transition of 0 removes the blades abruptly.
+    /// Swapping the mesh of the blades can't be smoothed.
+    pub transition: f32,
+}
+impl Default for GrassLod {
+    fn default() -> Self {
+        GrassLod::new(Vec::new())
+    }
+}
+impl GrassLod {
+    /// The maximum amount of levels whose transitions are smoothed
+    pub(crate) const SMOOTH_LEVELS: usize = 4;
+    /// Creates a level of detail with the given levels and a transition of 10 units
+    pub fn new(levels: Vec<LodLevel>) -> Self {
+        GrassLod {
+            levels,
+            transition: 10.,
+        }
+    }
+    /// Replaces the distance over which the blades dropped by a level shrink
+    pub fn with_transition(mut self, transition: f32) -> Self {
+        self.transition = transition;
+        self
+    }
+    /// Returns the level used at the given `distance`
+    ///
+    /// Returns `None` if the chunk is drawn in full detail
+    pub fn level(&self, distance: f32) -> Option<&LodLevel> {
+        self.levels
+            .iter()
+            .filter(|level| level.distance <= distance)
+            .max_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
+/// Fades out the blades of a grass chunk with increasing distance to the camera
+///
+/// The blades shrink from their full size at the `start` distance to nothing at the `end` distance,
+/// measured from the camera to each blade.
+/// From the `end` distance on the chunk isn't drawn at all.
+/// Placing the `end` before the far plane of the camera hides the edge of the grass.
+///
+/// Shadows are cast by the blades in their full size.
+/// Can be inserted into a chunk spawned with the [`WarblersBundle`]
+#[derive(Component, Clone, Copy, Debug, PartialEq, ExtractComponent)]
+pub struct GrassFade {
+    /// The distance at which the blades start to shrink
+    pub start: f32,
+    /// The distance at which the blades are gone
+    pub end: f32,
+}
+/// Culls the blades of a grass chunk individually against the view frustum of the cameras
+///
+/// Without it, all blades of a chunk are drawn as soon as its [`Aabb`] is visible.
+/// This helps big chunks, which are only partly on the screen most of the time.
+/// Every frame a compute shader writes the visible blades into a separate buffer for each camera,
+/// which also leaves out the blades hidden by the [`GrassLod`] and [`GrassFade`] of the chunk.
+///
+/// The blades are culled conservatively, assuming that the blade mesh fits into a unit cube scaled by the size of the blades.
+/// Raise the `margin` if the blades of a bigger mesh disappear at the border of the screen.
+/// The shadows of the chunk aren't culled.
+///
+/// Has no effect on platforms without compute shaders, like WebGL2.
+/// Can be inserted into a chunk spawned with the [`WarblersBundle`]
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, ExtractComponent)]
+pub struct GpuCulling {
+    /// An additional distance the blades can reach from their position, in the local space of the chunk
+    pub margin: f32,
+}
+/// A distance band of a [`GrassLod`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct LodLevel {
+    /// The distance from which on the level is used
+    pub distance: f32,
+    /// The fraction of the blades drawn, between 0 and 1
+    ///
+    /// The blades are ordered so that any fraction of them is spread evenly over the chunk.
+    /// A density of 0 hides the chunk.
+    pub density: f32,
+    /// A simpler [`Mesh`] used for the blades, for example with less triangles
+    ///
+    /// Uses the mesh of the chunk if `None`
+    pub mesh: Option<Handle<Mesh>>,
+}
+impl LodLevel {
+    /// Creates a level drawing a fraction of the blades from `distance` on
+    pub fn new(distance: f32, density: f32) -> Self {
+        LodLevel {
+            distance,
+            density,
+            mesh: None,
+        }
+    }
+    /// Replaces the blade mesh of the level
+    pub fn with_mesh(mut self, mesh: Handle<Mesh>) -> Self {
+        self.mesh = Some(mesh);
+        self
+    }
+}
 impl ExtractComponent for WarblerHeight {
     type Query = &'static Self;
 
@@ -99,7 +357,36 @@ impl ExtractComponent for WarblerHeight {
     fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self::Out> {
         match item {
             WarblerHeight::Uniform(_) => Some(item.clone()),
-            WarblerHeight::Texture(handle) => Some(WarblerHeight::Texture(handle.clone_weak())),
+            WarblerHeight::Texture { texture, min, max } => Some(WarblerHeight::Texture {
+                texture: texture.clone_weak(),
+                min: *min,
+                max: *max,
+            }),
+        }
+    }
+}
+impl ExtractComponent for GrassLod {
+    type Query = &'static Self;
+
+    type Filter = ();
+
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self::Out> {
+        if item.levels.is_empty() {
+            return None;
         }
+        let levels = item
+            .levels
+            .iter()
+            .map(|level| LodLevel {
+                mesh: level.mesh.as_ref().map(Handle::clone_weak),
+                ..*level
+            })
+            .collect();
+        Some(GrassLod {
+            levels,
+            transition: item.transition,
+        })
     }
 }
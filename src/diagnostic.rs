@@ -53,3 +53,12 @@ impl WarblerDiagnosticsPlugin {
         diagnostics.add_measurement(Self::GRASS_BLADE_COUNT, || count as f64);
     }
 }
+
+/// Drops the cached bind groups of the grass chunks in every frame while it is set.
+///
+/// All bind groups are created again in every frame, like before they were cached.
+/// It only exists to measure the benefit of the cache in the `prepare_chunks` benchmark.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub static DISABLE_CHUNK_CACHE: std::sync::atomic::AtomicBool =
+    std::sync::atomic::AtomicBool::new(false);
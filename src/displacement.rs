@@ -0,0 +1,366 @@
+//! Contains the [`GrassDisplacer`] component, which bends the grass away from its entity,
+//! and the [`GrassTrample`] component, which keeps the grass of a chunk bent after a displacer passed it
+use bevy::{
+    asset::Assets,
+    ecs::prelude::*,
+    math::{UVec2, Vec2, Vec3, Vec3Swizzles, Vec4, Vec4Swizzles},
+    render::{primitives::Aabb, texture::Image},
+    time::Time,
+    transform::components::GlobalTransform,
+};
+
+use crate::{bundle::MapSampling, map::YMap, render::lod::chunk_distance, sampler::sample_map};
+
+/// The maximum amount of [`GrassDisplacer`]s bending the blades of a single grass chunk.
+///
+/// The displacers closest to the chunk are used
+pub const MAX_CHUNK_DISPLACERS: usize = 8;
+
+/// A [`Component`] bending the grass blades away from its entity, like a character walking through the grass.
+///
+/// Only the blades rooted in the sphere with the `radius` around the entity are bent,
+/// so displacers flying over the grass don't affect it.
+/// At most [`MAX_CHUNK_DISPLACERS`] displacers bend the blades of each chunk.
+///
+/// Chunks bent by a displacer aren't drawn in a batch with other chunks.
+///
+/// # Example
+/// ```rust
+/// use bevy::prelude::*;
+/// use warbler_grass::prelude::*;
+///
+/// fn spawn_player(mut commands: Commands) {
+///     commands.spawn((
+///         GrassDisplacer::default(),
+///         TransformBundle::from_transform(Transform::from_xyz(10., 0., 10.)),
+///     ));
+/// }
+/// # bevy::ecs::system::assert_is_system(spawn_player);
+/// ```
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct GrassDisplacer {
+    /// The distance from the entity up to which the blades are bent
+    pub radius: f32,
+    /// The distance the tips of the blades next to the entity are moved.
+    ///
+    /// The tips are the vertices at a height of 1 in the grass mesh, like in the default mesh
+    pub strength: f32,
+    /// The exponent of the curve weakening the displacement with the distance to the entity.
+    ///
+    /// A falloff of 0 bends all blades in the radius equally, higher values only bend the closest blades strongly
+    pub falloff: f32,
+}
+impl Default for GrassDisplacer {
+    fn default() -> Self {
+        GrassDisplacer {
+            radius: 1.,
+            strength: 1.,
+            falloff: 1.,
+        }
+    }
+}
+
+/// A [`Component`] keeping the blades of a grass chunk bent after a [`GrassDisplacer`] walked through them.
+///
+/// The trails of the displacers are stored in a texture covering the chunk, which recovers over time.
+/// Displacers trample the blades rooted in their `radius`, like they bend them while they are close.
+///
+/// Trampled chunks aren't drawn in a batch with other chunks.
+///
+/// # Example
+/// ```rust
+/// use bevy::prelude::*;
+/// use warbler_grass::prelude::*;
+///
+/// fn keep_trails(mut commands: Commands, chunks: Query<Entity, With<DensityMap>>) {
+///     for chunk in &chunks {
+///         commands.entity(chunk).insert(GrassTrample {
+///             recovery_rate: 0.1,
+///             ..default()
+///         });
+///     }
+/// }
+/// # bevy::ecs::system::assert_is_system(keep_trails);
+/// ```
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct GrassTrample {
+    /// The amount of texels of the trample texture along the x and z axis of the chunk
+    pub resolution: UVec2,
+    /// How fast the trampled blades straighten up again.
+    ///
+    /// The bend of the blades decays exponentially, after `1 / recovery_rate` seconds about a third of it is left.
+    /// A recovery rate of 0 keeps the trails forever
+    pub recovery_rate: f32,
+}
+impl Default for GrassTrample {
+    fn default() -> Self {
+        GrassTrample {
+            resolution: UVec2::splat(64),
+            recovery_rate: 0.25,
+        }
+    }
+}
+
+/// The offset of the blades on the x,z plane in world space below which they count as recovered
+const RECOVERED_OFFSET: f32 = 1e-3;
+
+/// The trails of the [`GrassDisplacer`]s in a chunk with a [`GrassTrample`] component
+///
+/// Each texel stores how far the blades rooted in it were bent on the x,z plane in world space in `x` and `y`,
+/// the time they were bent at in `z` and their recovery rate in `w`.
+/// The blades recover in the shader, so a texel only changes when a displacer tramples it.
+#[derive(Component, Clone, Debug, PartialEq)]
+pub(crate) struct TrampleMap {
+    pub resolution: UVec2,
+    pub texels: Vec<Vec4>,
+    /// The minimum and maximum (exclusive) texel changed in this frame
+    pub changed: Option<(UVec2, UVec2)>,
+    /// The wrapped elapsed time of this frame, like the time of the shader
+    pub time: f32,
+    pub recovery_rate: f32,
+}
+impl TrampleMap {
+    fn new(resolution: UVec2, time: f32, recovery_rate: f32) -> Self {
+        TrampleMap {
+            resolution,
+            texels: vec![Vec4::ZERO; (resolution.x * resolution.y) as usize],
+            changed: Some((UVec2::ZERO, resolution)),
+            time,
+            recovery_rate,
+        }
+    }
+    /// The offset of the blades of the `texel` in this frame
+    pub(crate) fn offset(&self, texel: Vec4) -> Vec2 {
+        trail_offset(texel, self.time)
+    }
+    /// The furthest distance the trails move the blades in this frame
+    pub(crate) fn max_offset(&self) -> f32 {
+        self.texels
+            .iter()
+            .map(|texel| self.offset(*texel).length())
+            .fold(0., f32::max)
+    }
+    /// Starts a new frame at the wrapped elapsed `time`
+    ///
+    /// The trails are stored again from the new time on if the time wrapped around or the `recovery_rate` changed,
+    /// which changes all texels
+    fn next_frame(&mut self, time: f32, recovery_rate: f32) {
+        self.changed = None;
+        if time < self.time || recovery_rate != self.recovery_rate {
+            let previous = self.time;
+            for texel in self.texels.iter_mut() {
+                let offset = trail_offset(*texel, previous);
+                *texel = if offset.length() < RECOVERED_OFFSET {
+                    Vec4::ZERO
+                } else {
+                    offset.extend(time).extend(recovery_rate)
+                };
+            }
+            self.changed = Some((UVec2::ZERO, self.resolution));
+        }
+        self.time = time;
+        self.recovery_rate = recovery_rate;
+    }
+    /// Bends the blades around the `displacer` at the world `position` away from it,
+    /// if they aren't bent further already
+    ///
+    /// `ground` returns the local y position of the blades rooted at a local x,z position of the chunk.
+    /// Returns true if any texel changed
+    fn trample(
+        &mut self,
+        displacer: &GrassDisplacer,
+        position: Vec3,
+        transform: &GlobalTransform,
+        aabb: &Aabb,
+        ground: impl Fn(Vec2) -> f32,
+    ) -> bool {
+        let radius = displacer.radius;
+        if radius <= 0. || chunk_distance(position, transform.compute_matrix(), aabb) >= radius {
+            return false;
+        }
+        let affine = transform.affine();
+        let size = aabb.half_extents.xz() * 2.;
+        let texel_size = size / self.resolution.as_vec2();
+        // the texels covered by the radius of the displacer, which is scaled on each axis of the chunk
+        let local = affine.inverse().transform_point3(position).xz();
+        let local_radius = Vec2::new(
+            radius / affine.matrix3.x_axis.length(),
+            radius / affine.matrix3.z_axis.length(),
+        );
+        let min = ((local - local_radius) / texel_size)
+            .floor()
+            .max(Vec2::ZERO);
+        let max = ((local + local_radius) / texel_size)
+            .ceil()
+            .min(self.resolution.as_vec2());
+
+        let mut changed = false;
+        for y in min.y as u32..max.y as u32 {
+            for x in min.x as u32..max.x as u32 {
+                let center = (Vec2::new(x as f32, y as f32) + 0.5) * texel_size;
+                let root = affine.transform_point3(Vec3::new(center.x, ground(center), center.y));
+                // the same distance as the live displacement in the shader
+                let distance = root.distance(position);
+                let away = root.xz() - position.xz();
+                // blades right below the displacer have no direction to bend in
+                if distance >= radius || away.length() < 1e-4 {
+                    continue;
+                }
+                let weight = (1. - distance / radius).powf(displacer.falloff);
+                let offset = away.normalize() * displacer.strength * weight;
+                let index = (y * self.resolution.x + x) as usize;
+                if offset.length_squared() > self.offset(self.texels[index]).length_squared() {
+                    self.texels[index] = offset.extend(self.time).extend(self.recovery_rate);
+                    let (min, max) = self
+                        .changed
+                        .get_or_insert((UVec2::new(x, y), UVec2::new(x, y)));
+                    *min = min.min(UVec2::new(x, y));
+                    *max = max.max(UVec2::new(x + 1, y + 1));
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// The offset of the blades of a `texel` of a [`TrampleMap`] at the wrapped elapsed `time`, like in the shader
+fn trail_offset(texel: Vec4, time: f32) -> Vec2 {
+    texel.xy() * (-texel.w * (time - texel.z).max(0.)).exp()
+}
+
+/// Tramples the grass of the chunks with a [`GrassTrample`] component around the [`GrassDisplacer`]s
+///
+/// The chunks are only trampled once their [`YMap`] is loaded.
+/// Only marks the [`TrampleMap`] of a chunk as changed if a trail is left in it, the blades recover in the shader
+#[allow(clippy::type_complexity)]
+pub(crate) fn trample_grass(
+    mut commands: Commands,
+    time: Res<Time>,
+    images: Res<Assets<Image>>,
+    displacers: Query<(&GrassDisplacer, &GlobalTransform)>,
+    mut chunks: Query<(
+        Entity,
+        &GrassTrample,
+        &Aabb,
+        &GlobalTransform,
+        &YMap,
+        Option<&MapSampling>,
+        Option<&mut TrampleMap>,
+    )>,
+) {
+    // the same time as in the shader
+    let time = time.elapsed_seconds_wrapped();
+    for (entity, trample, aabb, transform, y_map, sampling, map) in &mut chunks {
+        let resolution = trample.resolution.max(UVec2::ONE);
+        let Some(mut map) = map.filter(|map| map.resolution == resolution) else {
+            let map = TrampleMap::new(resolution, time, trample.recovery_rate);
+            commands.entity(entity).insert(map);
+            continue;
+        };
+        // the changed texels of the last frame are already extracted
+        let texels = map.bypass_change_detection();
+        texels.next_frame(time, trample.recovery_rate);
+        if let Some(y_map) = images.get(&y_map.y_map) {
+            // the blades are rooted on the y-map like in the shader
+            let sampling = sampling.copied().unwrap_or_default();
+            let size = aabb.half_extents.xz() * 2.;
+            let ground = |position| {
+                sample_map(y_map, position, size, sampling).map_or(0., |value| value.x)
+                    * aabb.half_extents.y
+                    * 2.
+            };
+            for (displacer, displacer_transform) in &displacers {
+                let position = displacer_transform.translation();
+                texels.trample(displacer, position, transform, aabb, ground);
+            }
+        }
+        if texels.changed.is_some() {
+            map.set_changed();
+        }
+    }
+}
+
+/// Removes the [`TrampleMap`] of a chunk if its [`GrassTrample`] component is removed
+pub(crate) fn remove_trample_map(
+    mut commands: Commands,
+    mut removed: RemovedComponents<GrassTrample>,
+    trampled: Query<(), With<GrassTrample>>,
+) {
+    for e in removed.iter() {
+        // the entity might have been despawned or got a new trample component
+        if trampled.contains(e) {
+            continue;
+        }
+        if let Some(mut entity) = commands.get_entity(e) {
+            entity.remove::<TrampleMap>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{UVec2, Vec2, Vec3, Vec4, Vec4Swizzles};
+    use bevy::render::primitives::Aabb;
+    use bevy::transform::components::GlobalTransform;
+
+    use super::{GrassDisplacer, TrampleMap};
+
+    #[test]
+    fn trample_map() {
+        let aabb = Aabb::from_min_max(Vec3::ZERO, Vec3::new(10., 1., 10.));
+        let transform = GlobalTransform::from_xyz(100., 0., 0.);
+        let displacer = GrassDisplacer {
+            radius: 2.,
+            strength: 1.,
+            falloff: 1.,
+        };
+        let mut map = TrampleMap::new(UVec2::splat(10), 0., 1.);
+        let flat = |_| 0.;
+        // new maps are uploaded completely
+        assert_eq!(map.changed, Some((UVec2::ZERO, UVec2::splat(10))));
+        map.next_frame(0., 1.);
+        assert_eq!(map.changed, None);
+
+        // displacers far away from the chunk leave no trail
+        let far = Vec3::new(100., 0., 20.);
+        assert!(!map.trample(&displacer, far, &transform, &aabb, flat));
+
+        // the blades are bent away from the displacer
+        let position = Vec3::new(105., 0., 5.);
+        assert!(map.trample(&displacer, position, &transform, &aabb, flat));
+        let right = map.texels[5 * 10 + 5].xy();
+        assert!((right - Vec2::ONE.normalize() * (1. - 0.5_f32.sqrt() / 2.)).length() < 1e-4);
+        assert_eq!(map.texels[4 * 10 + 4].xy(), -right);
+        // texels outside of the radius stay untouched and aren't uploaded
+        assert_eq!(map.texels[5 * 10 + 7], Vec4::ZERO);
+        assert_eq!(map.changed, Some((UVec2::splat(3), UVec2::splat(7))));
+        assert!(map.max_offset() > 0.5);
+
+        // a weaker displacer doesn't undo the stronger trail
+        let weak = GrassDisplacer {
+            strength: 0.1,
+            ..displacer
+        };
+        assert!(!map.trample(&weak, position, &transform, &aabb, flat));
+
+        // the trail recovers over time without changing the texels
+        map.next_frame(1., 1.);
+        assert_eq!(map.changed, None);
+        let recovered = map.offset(map.texels[5 * 10 + 5]);
+        assert!((recovered - right * (-1_f32).exp()).length() < 1e-4);
+
+        // the trails are stored again from the new time on if the time wraps around
+        map.next_frame(0.5, 1.);
+        assert_eq!(map.changed, Some((UVec2::ZERO, UVec2::splat(10))));
+        assert_eq!(map.texels[5 * 10 + 5].z, 0.5);
+        assert!((map.offset(map.texels[5 * 10 + 5]) - recovered).length() < 1e-4);
+        map.next_frame(10.5, 1.);
+        assert!(map.max_offset() < 1e-3);
+
+        // the distance to the roots of the blades is measured in 3d, like for the live displacement
+        let hovering = Vec3::new(105., 1.9, 5.);
+        assert!(!map.trample(&displacer, hovering, &transform, &aabb, flat));
+        assert!(map.trample(&displacer, hovering, &transform, &aabb, |_| 1.));
+    }
+}
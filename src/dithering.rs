@@ -1,23 +1,26 @@
 use bevy::math::Vec3Swizzles;
 use bevy::{
-    asset::Assets,
-    ecs::{
-        prelude::*,
-        system::{lifetimeless::SRes, SystemParamItem},
-    },
+    asset::{AssetEvent, Assets, Handle, HandleId},
+    ecs::{prelude::*, query::Has},
     log::warn,
-    math::Vec2,
+    math::{IVec2, Rect, UVec2, Vec2},
     reflect::{Reflect, TypeUuid},
     render::{
         primitives::Aabb,
-        render_asset::{PrepareAssetError, RenderAsset},
-        render_resource::{Buffer, BufferInitDescriptor, BufferUsages},
-        renderer::RenderDevice,
+        render_resource::{Buffer, BufferInitDescriptor, BufferUsages, TextureFormat},
+        renderer::{RenderDevice, RenderQueue},
         texture::Image,
+        Extract,
     },
+    tasks::{AsyncComputeTaskPool, Task},
+    transform::components::GlobalTransform,
+    utils::{HashMap, HashSet},
 };
+use futures_lite::future;
+use std::ops::{Deref, DerefMut, Range};
+use std::sync::{Arc, Mutex};
 
-use crate::map::DensityMap;
+use crate::{bundle::DitheringSpace, map::DensityMap};
 
 // see https://surma.dev/things/ditherpunk/ for a good resource regarding ordered dithering
 const BAYER_DITHER: [[u8; 8]; 8] = [
@@ -30,11 +33,161 @@ const BAYER_DITHER: [[u8; 8]; 8] = [
     [15, 47, 7, 39, 13, 45, 5, 37],
     [61, 31, 55, 23, 61, 29, 53, 21],
 ];
+/// The side length of the blue noise tile generated for [`DitheringStrategy::BlueNoise`]
+const BLUE_NOISE_SIZE: usize = 32;
+/// The normalized region covering the whole density map
+const FULL_MAP: Rect = Rect {
+    min: Vec2::ZERO,
+    max: Vec2::ONE,
+};
+
+/// Defines how the grass blades are placed inside of a [`DensityMap`].
+///
+/// All strategies honor the density image and are deterministic,
+/// meaning the same inputs always result in the same blade positions.
+#[derive(Reflect, Clone, Debug, Default, PartialEq)]
+pub enum DitheringStrategy {
+    /// Ordered dithering using a 8x8 bayer matrix.
+    ///
+    /// This is the fastest strategy, but the grid can be visible when looking at the grass from close by.
+    #[default]
+    Bayer,
+    /// Ordered dithering using a tileable blue noise texture generated from the `seed`.
+    ///
+    /// The blades look naturally scattered while still being evenly distributed.
+    BlueNoise { seed: u64 },
+    /// Places the blades randomly while keeping at least `min_distance` between any two blades.
+    ///
+    /// The distance is given in world units.
+    /// Note that a high `min_distance` can result in less blades than the density would allow.
+    PoissonDisk { seed: u64, min_distance: f32 },
+}
+
+/// Dithers the density map over a field of the size `field_size`.
+///
+/// If an `origin` is given, the blades are placed on a grid aligned to the world
+/// and `origin` is the world position of the field.
+/// Otherwise the grid is aligned to the field itself.
 pub(crate) fn dither_density_map(
     image: &Image,
     density: f32,
     field_size: Vec2,
+    origin: Option<Vec2>,
+    strategy: &DitheringStrategy,
 ) -> Option<DitheredBuffer> {
+    let mut positions =
+        dither_density_map_region(image, density, field_size, origin, strategy, FULL_MAP, &[])?;
+    let grid = DitherGrid::new(density, field_size, origin);
+    order_for_lod(&mut positions, strategy, &grid);
+    Some(DitheredBuffer { positions })
+}
+
+/// Replaces the blades of the `buffer` inside of the normalized `region` of the density map
+/// with newly dithered ones.
+///
+/// Returns the range of the blades which changed,
+/// or `None` if the density map couldn't be dithered, in which case the `buffer` stays unchanged.
+pub(crate) fn redither_region(
+    buffer: &mut DitheredBuffer,
+    image: &Image,
+    density: f32,
+    field_size: Vec2,
+    origin: Option<Vec2>,
+    strategy: &DitheringStrategy,
+    region: Rect,
+) -> Option<Range<usize>> {
+    let grid = DitherGrid::new(density, field_size, origin);
+    let cells = grid.cells(region);
+    // the blades outside of the region keep their order
+    let kept: Vec<Vec2> = buffer
+        .positions
+        .iter()
+        .filter(|position| {
+            let (i, j) = strategy.grid_cell(**position, &grid);
+            !cells.contains(i, j)
+        })
+        .copied()
+        .collect();
+    let mut new_positions =
+        dither_density_map_region(image, density, field_size, origin, strategy, region, &kept)?;
+    order_for_lod(&mut new_positions, strategy, &grid);
+
+    // merging the new blades into the ordered ones keeps the order of the whole buffer
+    let mut positions = Vec::with_capacity(kept.len() + new_positions.len());
+    let mut new_positions = new_positions.into_iter().peekable();
+    for position in kept {
+        let rank = lod_key(position, strategy, &grid);
+        while let Some(new) = new_positions.next_if(|new| lod_key(*new, strategy, &grid) < rank) {
+            positions.push(new);
+        }
+        positions.push(position);
+    }
+    positions.extend(new_positions);
+
+    let changed = changed_range(&buffer.positions, &positions);
+    buffer.positions = positions;
+    Some(changed)
+}
+
+/// Returns the range of the `new` blades differing from the `old` ones
+///
+/// If the amount of blades changed, all blades after the first difference are moved
+fn changed_range(old: &[Vec2], new: &[Vec2]) -> Range<usize> {
+    let start = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    if old.len() != new.len() {
+        return start..new.len();
+    }
+    let end = new.len()
+        - old[start..]
+            .iter()
+            .rev()
+            .zip(new[start..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+    start..end
+}
+
+/// Sorts the blades so that every prefix of them is spread evenly over the field.
+///
+/// A [`GrassLod`](crate::bundle::GrassLod) only draws a prefix of the blades of a chunk with increasing distance.
+/// The blades are ordered by the bit reversed morton code of their grid cell,
+/// which halves the blades of every 2x2 block of cells before thinning out any block further.
+fn order_for_lod(positions: &mut [Vec2], strategy: &DitheringStrategy, grid: &DitherGrid) {
+    positions.sort_by_cached_key(|position| lod_key(*position, strategy, grid));
+}
+
+/// The key the blade at `position` is sorted by in [`order_for_lod`]
+fn lod_key(position: Vec2, strategy: &DitheringStrategy, grid: &DitherGrid) -> u32 {
+    let (i, j) = strategy.grid_cell(position, grid);
+    let (i, j) = grid.index(i, j);
+    lod_rank(i, j)
+}
+
+/// The rank of the cell `(i, j)` in the order of [`order_for_lod`]
+fn lod_rank(i: i64, j: i64) -> u32 {
+    // spreads the lower 16 bits of a index to the even bits
+    let spread = |index: i64| {
+        let mut x = index as u32 & 0xffff;
+        x = (x | (x << 8)) & 0x00ff_00ff;
+        x = (x | (x << 4)) & 0x0f0f_0f0f;
+        x = (x | (x << 2)) & 0x3333_3333;
+        (x | (x << 1)) & 0x5555_5555
+    };
+    (spread(i) | (spread(j) << 1)).reverse_bits()
+}
+
+/// Dithers the grid cells of the density map inside of the normalized `region`
+///
+/// `neighbours` are already placed blades, which are respected by the [`DitheringStrategy::PoissonDisk`]
+fn dither_density_map_region(
+    image: &Image,
+    density: f32,
+    field_size: Vec2,
+    origin: Option<Vec2>,
+    strategy: &DitheringStrategy,
+    region: Rect,
+    neighbours: &[Vec2],
+) -> Option<Vec<Vec2>> {
     if density < 0. {
         warn!("tried to dither a image with density < 0");
         return None;
@@ -42,34 +195,516 @@ pub(crate) fn dither_density_map(
     if field_size.length() < 0.0001 {
         return None;
     }
-    let Ok(dynamic_image) = image.clone().try_into_dynamic() else {
-        return None;
+    let grid = DitherGrid::new(density, field_size, origin);
+    let cells = grid.cells(region);
+    // only the pixels below the cells are converted
+    let buffer = DensityBuffer::from_image(image, grid.bounds(&cells))?;
+    let positions = match strategy {
+        DitheringStrategy::Bayer => ordered_dither(&buffer, &grid, &cells, |i, j| {
+            // the threshold is scaled to the u8 range of the pixels
+            BAYER_DITHER[i.rem_euclid(8) as usize][j.rem_euclid(8) as usize] as f32 * 4. / 255.
+        }),
+        DitheringStrategy::BlueNoise { seed } => {
+            let tile = blue_noise_tile(*seed);
+            let size = BLUE_NOISE_SIZE as i64;
+            ordered_dither(&buffer, &grid, &cells, |i, j| {
+                tile[i.rem_euclid(size) as usize][j.rem_euclid(size) as usize]
+            })
+        }
+        DitheringStrategy::PoissonDisk { seed, min_distance } => {
+            poisson_disk(&buffer, &grid, &cells, neighbours, *seed, *min_distance)
+        }
+    };
+    Some(positions)
+}
+
+/// The grid the blades of a field are placed on.
+///
+/// Each cell of the grid can hold one blade.
+struct DitherGrid {
+    i: GridAxis,
+    j: GridAxis,
+}
+impl DitherGrid {
+    fn new(density: f32, field_size: Vec2, origin: Option<Vec2>) -> Self {
+        match origin {
+            Some(origin) => DitherGrid {
+                i: GridAxis::world(density, field_size.x, origin.x),
+                j: GridAxis::world(density, field_size.y, origin.y),
+            },
+            None => DitherGrid {
+                i: GridAxis::local(density, field_size.x),
+                j: GridAxis::local(density, field_size.y),
+            },
+        }
+    }
+    /// The position of the cell `(i, j)` inside of the field.
+    ///
+    /// Fractional cells lie in between the corners of the cells
+    fn position(&self, i: f32, j: f32) -> Vec2 {
+        Vec2::new(self.i.position(i), self.j.position(j))
+    }
+    /// The position of the cell `(i, j)` normalized between 0 and 1
+    fn normalized(&self, i: f32, j: f32) -> Vec2 {
+        Vec2::new(self.i.normalized(i), self.j.normalized(j))
+    }
+    /// The index of the cell `(i, j)` used to look up thresholds and random values.
+    ///
+    /// If the grid is aligned to the world, neighbouring fields continue the indices of each other
+    fn index(&self, i: usize, j: usize) -> (i64, i64) {
+        (self.i.index(i), self.j.index(j))
+    }
+    /// Contains all cells overlapping the normalized `region`
+    fn cells(&self, region: Rect) -> CellRange {
+        CellRange {
+            i: self.i.cells(region.min.x, region.max.x),
+            j: self.j.cells(region.min.y, region.max.y),
+        }
+    }
+    /// The normalized region covered by the `cells`, including the blades placed inside of the last cells
+    fn bounds(&self, cells: &CellRange) -> Rect {
+        Rect::from_corners(
+            self.normalized(cells.i.start as f32, cells.j.start as f32),
+            self.normalized(cells.i.end as f32, cells.j.end as f32),
+        )
+    }
+}
+
+/// One axis of a [`DitherGrid`]
+struct GridAxis {
+    /// The amount of cells inside of the field
+    count: usize,
+    /// The amount of cells fitting into the field
+    cells: f32,
+    /// The offset of the first cell from the start of the field in cells
+    offset: f32,
+    /// The size of the field
+    size: f32,
+    /// The index of the first cell
+    first: i64,
+    /// The index difference of neighbouring cells
+    step: i64,
+}
+impl GridAxis {
+    /// An axis whose cells start at the beginning of the field and are stretched to fill it
+    fn local(density: f32, size: f32) -> Self {
+        let count = (density * size).abs() as usize;
+        GridAxis {
+            count,
+            cells: count as f32,
+            offset: 0.,
+            size,
+            first: 0,
+            step: 1,
+        }
+    }
+    /// An axis whose cells lie on multiples of `1 / density` in the world
+    fn world(density: f32, size: f32, origin: f32) -> Self {
+        let cells = (density * size).abs();
+        // the field grows into the negative direction for negative sizes
+        let (first, offset, step) = if size >= 0. {
+            let first = (origin * density).ceil();
+            (first, first - origin * density, 1)
+        } else {
+            let first = (origin * density).floor();
+            (first, origin * density - first, -1)
+        };
+        let count = (cells - offset).ceil().max(0.) as usize;
+        GridAxis {
+            count,
+            cells,
+            offset,
+            size,
+            first: first as i64,
+            step,
+        }
+    }
+    fn normalized(&self, i: f32) -> f32 {
+        (i + self.offset) / self.cells
+    }
+    fn position(&self, i: f32) -> f32 {
+        self.normalized(i) * self.size
+    }
+    fn index(&self, i: usize) -> i64 {
+        self.first + self.step * i as i64
+    }
+    /// The inverse of [`GridAxis::position`]
+    fn cell(&self, position: f32) -> f32 {
+        position / self.size * self.cells - self.offset
+    }
+    fn cells(&self, min: f32, max: f32) -> Range<usize> {
+        let cell = |normalized: f32| {
+            (normalized.clamp(0., 1.) * self.cells - self.offset).clamp(0., self.count as f32)
+        };
+        cell(min).floor() as usize..cell(max).ceil() as usize
+    }
+}
+
+impl DitheringStrategy {
+    /// Returns the grid cell the blade at `position` was placed in
+    fn grid_cell(&self, position: Vec2, grid: &DitherGrid) -> (usize, usize) {
+        let cell = Vec2::new(grid.i.cell(position.x), grid.j.cell(position.y));
+        let cell = match self {
+            // blades are placed randomly inside of their cell
+            DitheringStrategy::PoissonDisk { .. } => cell.floor(),
+            // blades are placed on the corner of their cell
+            DitheringStrategy::Bayer | DitheringStrategy::BlueNoise { .. } => cell.round(),
+        };
+        (cell.x as usize, cell.y as usize)
+    }
+}
+
+/// A rectangular range of cells of a [`DitherGrid`]
+struct CellRange {
+    i: Range<usize>,
+    j: Range<usize>,
+}
+impl CellRange {
+    fn contains(&self, i: usize, j: usize) -> bool {
+        self.i.contains(&i) && self.j.contains(&j)
+    }
+}
+
+/// The gray scale pixels of a rectangle of a density map normalized between 0 and 1
+struct DensityBuffer {
+    /// The size of the whole density map
+    width: u32,
+    height: u32,
+    /// The first pixel of the rectangle
+    min: UVec2,
+    /// The size of the rectangle
+    size: UVec2,
+    pixels: Vec<f32>,
+}
+impl DensityBuffer {
+    /// Reads the gray scale values of the pixels of the `image` covering the normalized `region`.
+    ///
+    /// 16 bit and float formats keep their full precision.
+    /// Returns `None` if the format of the `image` is not supported
+    fn from_image(image: &Image, region: Rect) -> Option<Self> {
+        let format = image.texture_descriptor.format;
+        let width = image.texture_descriptor.size.width;
+        let height = image.texture_descriptor.size.height;
+        let pixel_size = format.block_size(None)? as usize;
+        if width == 0 || height == 0 || image.data.len() < (width * height) as usize * pixel_size {
+            return None;
+        }
+        let full = UVec2::new(width, height);
+        let min = (region.min * full.as_vec2())
+            .floor()
+            .as_uvec2()
+            .min(full - 1);
+        // the pixel containing the end of the region is included as well
+        let max = ((region.max * full.as_vec2()).floor().as_uvec2() + 1)
+            .min(full)
+            .max(min + 1);
+        let pixels = (min.y..max.y)
+            .flat_map(|y| (min.x..max.x).map(move |x| (y * width + x) as usize * pixel_size))
+            .map(|index| pixel_luma(format, &image.data[index..index + pixel_size]))
+            .collect::<Option<_>>()?;
+        Some(DensityBuffer {
+            width,
+            height,
+            min,
+            size: max - min,
+            pixels,
+        })
+    }
+    /// Samples the density at the normalized position `(i, j)` in `[0, 1)`
+    ///
+    /// Positions outside of the converted rectangle are clamped to it
+    fn sample(&self, i: f32, j: f32) -> f32 {
+        let x = ((i * self.width as f32) as u32).min(self.width - 1);
+        let y = ((j * self.height as f32) as u32).min(self.height - 1);
+        let x = x.saturating_sub(self.min.x).min(self.size.x - 1);
+        let y = y.saturating_sub(self.min.y).min(self.size.y - 1);
+        self.pixels[(y * self.size.x + x) as usize]
+    }
+}
+
+/// Reads the gray scale value of a `pixel` of the `format` normalized between 0 and 1
+///
+/// Returns `None` if the format is not supported
+fn pixel_luma(format: TextureFormat, pixel: &[u8]) -> Option<f32> {
+    let luma = match format {
+        // 8 bit formats are converted like the image crate does
+        TextureFormat::R8Unorm | TextureFormat::Rg8Unorm => pixel[0] as f32 / 255.,
+        TextureFormat::Rgba8UnormSrgb => luma8(pixel[0], pixel[1], pixel[2]),
+        TextureFormat::Bgra8UnormSrgb | TextureFormat::Bgra8Unorm => {
+            luma8(pixel[2], pixel[1], pixel[0])
+        }
+        _ => {
+            let (channel_size, channel) = channel_reader(format)?;
+            let mut channels = pixel.chunks_exact(channel_size).map(channel);
+            let r = channels.next().unwrap_or_default();
+            match (channels.next(), channels.next()) {
+                (Some(g), Some(b)) => luma(r, g, b),
+                // the second channel of a two channel image is the alpha channel
+                _ => r,
+            }
+        }
+    };
+    Some(luma)
+}
+
+/// Reads one channel of a pixel normalized between 0 and 1
+pub(crate) type ChannelReader = fn(&[u8]) -> f32;
+
+/// Returns the size of a channel of the high precision `format` in bytes
+/// and a function reading one normalized channel.
+///
+/// Unsigned integer formats are expected to contain 16 bit values, which is what bevy loads 16 bit pngs as
+pub(crate) fn channel_reader(format: TextureFormat) -> Option<(usize, ChannelReader)> {
+    let reader: (usize, ChannelReader) = match format {
+        TextureFormat::R16Unorm
+        | TextureFormat::Rg16Unorm
+        | TextureFormat::Rgba16Unorm
+        | TextureFormat::R16Uint
+        | TextureFormat::Rg16Uint
+        | TextureFormat::Rgba16Uint => (2, |bytes| {
+            u16::from_le_bytes([bytes[0], bytes[1]]) as f32 / u16::MAX as f32
+        }),
+        TextureFormat::R16Float | TextureFormat::Rg16Float | TextureFormat::Rgba16Float => {
+            (2, |bytes| {
+                f16_to_f32(u16::from_le_bytes([bytes[0], bytes[1]]))
+            })
+        }
+        TextureFormat::R32Float | TextureFormat::Rg32Float | TextureFormat::Rgba32Float => {
+            (4, |bytes| {
+                f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            })
+        }
+        _ => return None,
+    };
+    Some(reader)
+}
+
+/// The luminance of a color, using the same weights as the image crate
+pub(crate) fn luma(r: f32, g: f32, b: f32) -> f32 {
+    (2126. * r + 7152. * g + 722. * b) / 10000.
+}
+
+/// The normalized luminance of an 8 bit color, rounded down to 8 bits like the image crate does
+fn luma8(r: u8, g: u8, b: u8) -> f32 {
+    let luma = (2126 * r as u32 + 7152 * g as u32 + 722 * b as u32) / 10000;
+    luma as f32 / 255.
+}
+
+/// Converts the bits of a half precision float
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 == 0 { 1. } else { -1. };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    let value = match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0. => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1. + mantissa / 1024.) * 2f32.powi(exponent - 15),
     };
+    sign * value
+}
+
+/// Places a blade on every grid point where the density exceeds the threshold given by `threshold(i, j)`
+///
+/// The threshold is looked up using the index of the grid cell
+fn ordered_dither(
+    buffer: &DensityBuffer,
+    grid: &DitherGrid,
+    cells: &CellRange,
+    threshold: impl Fn(i64, i64) -> f32,
+) -> Vec<Vec2> {
     // Capacity is not precise but should be a good estimate
-    let mut dither_buffer = Vec::with_capacity(image.size().length() as usize);
-    let buffer = dynamic_image.into_luma8();
-    let i_count = (density * field_size.x).abs() as usize;
-    let j_count = (density * field_size.y).abs() as usize;
-    for i in 0..i_count {
-        for j in 0..j_count {
-            let threshold = BAYER_DITHER[i % 8][j % 8];
+    let mut dither_buffer = Vec::with_capacity(cells.i.len() * cells.j.len() / 2);
+    for i in cells.i.clone() {
+        for j in cells.j.clone() {
+            let (index_i, index_j) = grid.index(i, j);
+            let threshold = threshold(index_i, index_j);
 
             //normalize i,j between 0,1
-            let i = i as f32 / i_count as f32;
-            let j = j as f32 / j_count as f32;
+            let normalized = grid.normalized(i as f32, j as f32);
 
-            let x = i * buffer.dimensions().0 as f32;
-            let y = j * buffer.dimensions().1 as f32;
+            if buffer.sample(normalized.x, normalized.y) > threshold {
+                dither_buffer.push(grid.position(i as f32, j as f32));
+            }
+        }
+    }
+    dither_buffer
+}
 
-            let pixel = buffer.get_pixel(x as u32, y as u32).0[0];
-            if pixel > threshold * 4 {
-                dither_buffer.push(Vec2::new(i * field_size.x, j * field_size.y));
+/// Places one candidate at a random position in every grid cell and keeps it if
+/// it passes the density test and has no accepted neighbour closer than `min_distance`
+fn poisson_disk(
+    buffer: &DensityBuffer,
+    grid: &DitherGrid,
+    cells: &CellRange,
+    neighbours: &[Vec2],
+    seed: u64,
+    min_distance: f32,
+) -> Vec<Vec2> {
+    let mut dither_buffer = Vec::with_capacity(cells.i.len() * cells.j.len() / 2);
+    // Accepted blades are sorted into cells of size `min_distance`,
+    // so only the neighbouring cells have to be checked
+    let cell_size = min_distance.max(f32::EPSILON);
+    let mut spatial_grid: HashMap<IVec2, Vec<Vec2>> = HashMap::new();
+    if min_distance > 0. {
+        for position in neighbours {
+            let cell = (*position / cell_size).floor().as_ivec2();
+            spatial_grid.entry(cell).or_default().push(*position);
+        }
+    }
+    for i in cells.i.clone() {
+        for j in cells.j.clone() {
+            let (index_i, index_j) = grid.index(i, j);
+            let candidate = (index_i as u64) << 32 | index_j as u32 as u64;
+            let i = i as f32 + random(seed, candidate, 0);
+            let j = j as f32 + random(seed, candidate, 1);
+
+            let normalized = grid.normalized(i, j);
+            if buffer.sample(normalized.x, normalized.y) <= random(seed, candidate, 2) {
+                continue;
+            }
+            let position = grid.position(i, j);
+            let cell = (position / cell_size).floor().as_ivec2();
+            if min_distance > 0. {
+                let too_close = (-1..=1)
+                    .flat_map(|x| (-1..=1).map(move |y| cell + IVec2::new(x, y)))
+                    .filter_map(|cell| spatial_grid.get(&cell))
+                    .flatten()
+                    .any(|other| other.distance(position) < min_distance);
+                if too_close {
+                    continue;
+                }
+                spatial_grid.entry(cell).or_default().push(position);
             }
+            dither_buffer.push(position);
         }
     }
-    Some(DitheredBuffer {
-        positions: dither_buffer,
-    })
+    dither_buffer
+}
+
+/// A tileable threshold matrix for [`DitheringStrategy::BlueNoise`]
+type BlueNoiseTile = [[f32; BLUE_NOISE_SIZE]; BLUE_NOISE_SIZE];
+
+/// The blue noise tiles generated so far, by their seed
+static BLUE_NOISE_TILES: Mutex<Vec<(u64, Arc<BlueNoiseTile>)>> = Mutex::new(Vec::new());
+
+/// Returns the blue noise tile of the `seed`, generating it only the first time it is used
+fn blue_noise_tile(seed: u64) -> Arc<BlueNoiseTile> {
+    let cached = |tiles: &[(u64, Arc<BlueNoiseTile>)]| {
+        tiles
+            .iter()
+            .find(|(tile_seed, _)| *tile_seed == seed)
+            .map(|(_, tile)| tile.clone())
+    };
+    if let Some(tile) = cached(&BLUE_NOISE_TILES.lock().unwrap()) {
+        return tile;
+    }
+    // the lock isn't held while generating, so jobs using other seeds aren't blocked
+    let tile = Arc::new(generate_blue_noise_tile(seed));
+    let mut tiles = BLUE_NOISE_TILES.lock().unwrap();
+    if let Some(tile) = cached(&tiles) {
+        return tile;
+    }
+    tiles.push((seed, tile.clone()));
+    tile
+}
+
+/// Generates a tileable blue noise threshold matrix using the void and cluster method.
+///
+/// see http://cv.ulichney.com/papers/1993-void-cluster.pdf
+fn generate_blue_noise_tile(seed: u64) -> BlueNoiseTile {
+    const N: usize = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+    const SIGMA: f32 = 1.5;
+    // energy contribution of a pixel to another pixel, depending on the (toroidal) offset
+    let mut kernel = [0.; N];
+    for (index, value) in kernel.iter_mut().enumerate() {
+        let wrap = |d: usize| d.min(BLUE_NOISE_SIZE - d) as f32;
+        let (dx, dy) = (wrap(index % BLUE_NOISE_SIZE), wrap(index / BLUE_NOISE_SIZE));
+        *value = (-(dx * dx + dy * dy) / (2. * SIGMA * SIGMA)).exp();
+    }
+    let update = |energy: &mut [f32; N], pixel: usize, sign: f32| {
+        let (px, py) = (pixel % BLUE_NOISE_SIZE, pixel / BLUE_NOISE_SIZE);
+        for (index, e) in energy.iter_mut().enumerate() {
+            let dx = (index % BLUE_NOISE_SIZE + BLUE_NOISE_SIZE - px) % BLUE_NOISE_SIZE;
+            let dy = (index / BLUE_NOISE_SIZE + BLUE_NOISE_SIZE - py) % BLUE_NOISE_SIZE;
+            *e += sign * kernel[dy * BLUE_NOISE_SIZE + dx];
+        }
+    };
+    // the tightest cluster is the set pixel with the highest energy,
+    // the largest void is the unset pixel with the lowest energy
+    let find = |pattern: &[bool; N], energy: &[f32; N], set: bool| -> usize {
+        let candidates = (0..N).filter(|i| pattern[*i] == set);
+        if set {
+            candidates.max_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+        } else {
+            candidates.min_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+        }
+        .unwrap()
+    };
+
+    // initial random pattern with roughly 10% of the pixels set
+    let mut pattern = [false; N];
+    let mut energy = [0.; N];
+    for (index, pixel) in pattern.iter_mut().enumerate() {
+        if random(seed, index as u64, 0) < 0.1 {
+            *pixel = true;
+            update(&mut energy, index, 1.);
+        }
+    }
+    // make sure the pattern is never empty
+    if !pattern.contains(&true) {
+        pattern[0] = true;
+        update(&mut energy, 0, 1.);
+    }
+    // distribute the initial pattern evenly by moving the tightest cluster to the largest void
+    loop {
+        let cluster = find(&pattern, &energy, true);
+        pattern[cluster] = false;
+        update(&mut energy, cluster, -1.);
+        let void = find(&pattern, &energy, false);
+        pattern[void] = true;
+        update(&mut energy, void, 1.);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = [0; N];
+    let ones = pattern.iter().filter(|p| **p).count();
+    // rank the initial pattern by removing the tightest clusters
+    let (mut removing, mut removing_energy) = (pattern, energy);
+    for r in (0..ones).rev() {
+        let cluster = find(&removing, &removing_energy, true);
+        removing[cluster] = false;
+        update(&mut removing_energy, cluster, -1.);
+        rank[cluster] = r;
+    }
+    // rank the remaining pixels by filling the largest voids
+    for r in ones..N {
+        let void = find(&pattern, &energy, false);
+        pattern[void] = true;
+        update(&mut energy, void, 1.);
+        rank[void] = r;
+    }
+
+    let mut tile: BlueNoiseTile = [[0.; BLUE_NOISE_SIZE]; BLUE_NOISE_SIZE];
+    for (index, r) in rank.into_iter().enumerate() {
+        tile[index % BLUE_NOISE_SIZE][index / BLUE_NOISE_SIZE] = r as f32 / N as f32;
+    }
+    tile
+}
+
+/// Deterministic random number in `[0, 1)` derived from the inputs
+fn random(seed: u64, value: u64, stream: u64) -> f32 {
+    // splitmix64, see https://prng.di.unimi.it/splitmix64.c
+    let mut z = seed
+        .wrapping_add(value.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+        .wrapping_add(stream.wrapping_mul(0xD1B5_4A32_D192_ED03));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    // use the upper 24 bits, which can be represented exactly as f32
+    (z >> 40) as f32 / (1u64 << 24) as f32
 }
 /// A buffer containing the dithered density map
 ///
@@ -83,86 +718,480 @@ pub(crate) struct DitheredBuffer {
 pub(crate) struct GpuDitheredBuffer {
     pub buffer: Buffer,
     pub instances: usize,
+    /// Counts the writes to the `buffer`, whose id stays the same when it is written to
+    pub generation: u64,
 }
-impl RenderAsset for DitheredBuffer {
-    type ExtractedAsset = DitheredBuffer;
-
-    type PreparedAsset = GpuDitheredBuffer;
-
-    type Param = SRes<RenderDevice>;
-
-    fn extract_asset(&self) -> Self::ExtractedAsset {
-        self.clone()
-    }
-
-    fn prepare_asset(
-        extracted_asset: Self::ExtractedAsset,
-        param: &mut SystemParamItem<Self::Param>,
-    ) -> Result<Self::PreparedAsset, PrepareAssetError<Self::ExtractedAsset>> {
-        let render_device = param;
+impl GpuDitheredBuffer {
+    fn new(render_device: &RenderDevice, positions: &[Vec2], usage: BufferUsages) -> Self {
         let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
             label: "dither buffer".into(),
-            contents: bytemuck::cast_slice(extracted_asset.positions.as_slice()),
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            contents: bytemuck::cast_slice(positions),
+            usage,
         });
-        Ok(GpuDitheredBuffer {
+        GpuDitheredBuffer {
             buffer,
-            instances: extracted_asset.positions.len(),
+            instances: positions.len(),
+            generation: 0,
+        }
+    }
+}
+/// Stores the [`GpuDitheredBuffer`] of all [`DitheredBuffer`]s in the render world
+#[derive(Resource)]
+pub(crate) struct GpuDitheredBuffers {
+    buffers: HashMap<Handle<DitheredBuffer>, GpuDitheredBuffer>,
+    /// The usages of the created gpu buffers, which depend on the features of the render device
+    pub usage: BufferUsages,
+}
+impl Default for GpuDitheredBuffers {
+    fn default() -> Self {
+        GpuDitheredBuffers {
+            buffers: HashMap::default(),
+            // the blades are copied into the instance buffers of batched chunks
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+        }
+    }
+}
+impl Deref for GpuDitheredBuffers {
+    type Target = HashMap<Handle<DitheredBuffer>, GpuDitheredBuffer>;
+    fn deref(&self) -> &Self::Target {
+        &self.buffers
+    }
+}
+impl DerefMut for GpuDitheredBuffers {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buffers
+    }
+}
+
+/// The blades of the [`DitheredBuffer`]s patched by [`redither_dirty_regions`] in this frame
+///
+/// `None` marks buffers which were replaced in this frame and need to be uploaded completely.
+#[derive(Resource, Default)]
+pub(crate) struct DitherPatches(HashMap<Handle<DitheredBuffer>, Option<Range<usize>>>);
+
+/// The blades of a [`DitheredBuffer`] to upload to the gpu
+pub(crate) struct DitherUpload {
+    /// The index of the first uploaded blade
+    start: usize,
+    positions: Vec<Vec2>,
+    /// The amount of blades of the whole buffer
+    instances: usize,
+}
+
+/// The [`DitheredBuffer`]s created, modified or removed since the last frame
+#[derive(Resource, Default)]
+pub(crate) struct ExtractedDitheredBuffers {
+    extracted: Vec<(Handle<DitheredBuffer>, DitherUpload)>,
+    removed: Vec<Handle<DitheredBuffer>>,
+}
+
+/// Extracts all created or modified [`DitheredBuffer`]s into the render world
+///
+/// Only the changed blades of patched buffers are extracted
+pub(crate) fn extract_dithered_buffers(
+    mut commands: Commands,
+    mut events: Extract<EventReader<AssetEvent<DitheredBuffer>>>,
+    assets: Extract<Res<Assets<DitheredBuffer>>>,
+    patches: Extract<Res<DitherPatches>>,
+) {
+    let mut changed = HashSet::new();
+    let mut created = HashSet::new();
+    let mut removed = Vec::new();
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } => {
+                changed.insert(handle.clone_weak());
+                created.insert(handle.clone_weak());
+            }
+            AssetEvent::Modified { handle } => {
+                changed.insert(handle.clone_weak());
+            }
+            AssetEvent::Removed { handle } => {
+                changed.remove(handle);
+                removed.push(handle.clone_weak());
+            }
+        }
+    }
+    let extracted = changed
+        .into_iter()
+        .filter_map(|handle| {
+            let positions = &assets.get(&handle)?.positions;
+            let range = match patches.0.get(&handle) {
+                Some(Some(range)) if !created.contains(&handle) => range.clone(),
+                _ => 0..positions.len(),
+            };
+            let upload = DitherUpload {
+                start: range.start,
+                positions: positions[range].to_vec(),
+                instances: positions.len(),
+            };
+            Some((handle, upload))
         })
+        .collect();
+    commands.insert_resource(ExtractedDitheredBuffers { extracted, removed });
+}
+
+/// Uploads the extracted [`DitheredBuffer`]s to the gpu
+///
+/// If a [`DitheredBuffer`] was modified, the changed blades are written to the existing gpu buffer
+/// as long as the new blade positions fit into it.
+/// The gpu buffers of removed [`DitheredBuffer`]s are freed.
+pub(crate) fn prepare_dithered_buffers(
+    mut extracted: ResMut<ExtractedDitheredBuffers>,
+    mut gpu_buffers: ResMut<GpuDitheredBuffers>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let blade_size = std::mem::size_of::<Vec2>() as u64;
+    for removed in std::mem::take(&mut extracted.removed) {
+        gpu_buffers.remove(&removed);
+    }
+    for (handle, upload) in std::mem::take(&mut extracted.extracted) {
+        match gpu_buffers.get_mut(&handle) {
+            Some(gpu_buffer)
+                if upload.instances as u64 * blade_size <= gpu_buffer.buffer.size() =>
+            {
+                let contents: &[u8] = bytemuck::cast_slice(upload.positions.as_slice());
+                if !contents.is_empty() {
+                    let offset = upload.start as u64 * blade_size;
+                    render_queue.write_buffer(&gpu_buffer.buffer, offset, contents);
+                }
+                gpu_buffer.instances = upload.instances;
+                gpu_buffer.generation += 1;
+            }
+            // patches never grow a buffer, so they always fit into the existing gpu buffer
+            _ => {
+                let gpu_buffer =
+                    GpuDitheredBuffer::new(&render_device, &upload.positions, gpu_buffers.usage);
+                gpu_buffers.insert(handle, gpu_buffer);
+            }
+        }
     }
 }
 
-/// Updates the [`DitheredBuffer`] of an entity
-#[allow(clippy::type_complexity)]
+/// Removes the [`DitheredBuffer`] of an entity if its [`DensityMap`] is removed
+///
+/// The [`DitheredBuffer`] and its gpu buffer are freed once no entity uses it anymore
+pub(crate) fn remove_dithered_buffer(
+    mut commands: Commands,
+    mut removed: RemovedComponents<DensityMap>,
+    density_maps: Query<(), With<DensityMap>>,
+) {
+    for e in removed.iter() {
+        // the entity might have been despawned or got a new density map
+        if density_maps.contains(e) {
+            continue;
+        }
+        if let Some(mut entity) = commands.get_entity(e) {
+            entity.remove::<(Handle<DitheredBuffer>, DitherTask, DirtyDensityRegion)>();
+        }
+    }
+}
+
+/// Marks a region of the [`DensityMap`] image as modified.
+///
+/// Inserting this component on a grass chunk only dithers the blades inside of the region again,
+/// which is a lot cheaper than changing the [`DensityMap`] itself.
+/// The region is given in normalized coordinates of the density map, ranging from `(0, 0)` to `(1, 1)`.
+///
+/// The component is removed once the blades are updated.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct DirtyDensityRegion(pub Rect);
+impl DirtyDensityRegion {
+    /// Grows the dirty region to also contain `region`
+    pub fn extend(&mut self, region: Rect) {
+        self.0 = self.0.union(region);
+    }
+}
+
+/// Identifies the inputs of a dithering job.
+///
+/// Chunks with the same key result in the same [`DitheredBuffer`]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct DitherKey {
+    image: HandleId,
+    density: u32,
+    field_size: [u32; 2],
+    origin: Option<[u32; 2]>,
+    strategy: (u8, u64, u32),
+}
+impl DitherKey {
+    fn new(density_map: &DensityMap, field_size: Vec2, origin: Option<Vec2>) -> Self {
+        let strategy = match density_map.strategy {
+            DitheringStrategy::Bayer => (0, 0, 0),
+            DitheringStrategy::BlueNoise { seed } => (1, seed, 0),
+            DitheringStrategy::PoissonDisk { seed, min_distance } => {
+                (2, seed, min_distance.to_bits())
+            }
+        };
+        DitherKey {
+            image: density_map.density_map.id(),
+            density: density_map.density.to_bits(),
+            field_size: field_size.to_array().map(f32::to_bits),
+            origin: origin.map(|origin| origin.to_array().map(f32::to_bits)),
+            strategy,
+        }
+    }
+}
+
+/// Returns the world position of the chunk if its blades are placed in world space
+fn dither_origin(
+    space: Option<&DitheringSpace>,
+    transform: Option<&GlobalTransform>,
+) -> Option<Vec2> {
+    match space {
+        Some(DitheringSpace::World) => {
+            Some(transform.map_or(Vec2::ZERO, |transform| transform.translation().xz()))
+        }
+        Some(DitheringSpace::Local) | None => None,
+    }
+}
+
+/// Caches the [`DitheredBuffer`]s and the running dithering jobs by their [`DitherKey`].
+///
+/// This way chunks with identical inputs share the same [`DitheredBuffer`]
+/// and each buffer is only dithered once.
+#[derive(Resource, Default)]
+pub(crate) struct DitherCache {
+    /// Weak handles to the finished buffers
+    buffers: HashMap<DitherKey, Handle<DitheredBuffer>>,
+    /// The dithering jobs running on the [`AsyncComputeTaskPool`]
+    tasks: HashMap<DitherKey, Task<Option<DitheredBuffer>>>,
+}
+
+/// Marks an entity that waits for the dithering job with the given [`DitherKey`]
+///
+/// Jobs no entity waits for anymore are canceled
+#[derive(Component)]
+pub(crate) struct DitherTask(DitherKey);
+
+/// Starts dithering the [`DensityMap`] of an entity in the background
+///
+/// If a chunk with identical inputs was already dithered, its [`DitheredBuffer`] is shared instead.
+/// Otherwise the [`DitheredBuffer`] is added to the entity in [`insert_dithered_buffer`] once the job finished.
+///
+/// Chunks using [`DitheringSpace::World`] are also dithered again if they moved
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub(crate) fn add_dither_to_density(
     mut commands: Commands,
-    grasses: Query<(Entity, &DensityMap, &Aabb), Or<(Changed<DensityMap>, Changed<Aabb>)>>,
+    grasses: Query<(
+        Entity,
+        Ref<DensityMap>,
+        Ref<Aabb>,
+        Option<Ref<DitheringSpace>>,
+        Option<Ref<GlobalTransform>>,
+        Has<Handle<DitheredBuffer>>,
+    )>,
     images: Res<Assets<Image>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    dithered: Res<Assets<DitheredBuffer>>,
+    mut cache: ResMut<DitherCache>,
+    mut pending: Local<HashSet<Entity>>,
+) {
+    // buffers of modified images are outdated
+    for event in image_events.iter() {
+        if let AssetEvent::Modified { handle } | AssetEvent::Removed { handle } = event {
+            cache.buffers.retain(|key, _| key.image != handle.id());
+        }
+    }
+    // entities whose density map was removed don't need to wait for their image anymore
+    pending.retain(|e| grasses.contains(*e));
+    let task_pool = AsyncComputeTaskPool::get();
+    for (e, density_map, aabb, space, transform, is_dithered) in &grasses {
+        let origin = dither_origin(space.as_deref(), transform.as_deref());
+        let changed = density_map.is_changed()
+            || aabb.is_changed()
+            || space.is_some_and(|space| space.is_changed())
+            || (origin.is_some() && transform.is_some_and(|transform| transform.is_changed()));
+        // entities waiting for their image are checked again
+        if !pending.remove(&e) && !changed {
+            continue;
+        }
+        let xz = aabb.half_extents.xz() * 2.;
+        let key = DitherKey::new(&density_map, xz, origin);
+        // A chunk which is already dithered changed, which could also mean that the image was modified.
+        // In that case it is dithered again instead of using the cache
+        let cached = cache
+            .buffers
+            .get(&key)
+            .filter(|handle| !is_dithered && dithered.contains(handle));
+        if let Some(handle) = cached {
+            commands
+                .entity(e)
+                .insert(dithered.get_handle(handle))
+                .remove::<(DitherTask, DirtyDensityRegion)>();
+            continue;
+        }
+        let Some(image) = images.get(&density_map.density_map) else {
+            // the entity shouldn't receive the result of a job with old inputs
+            commands.entity(e).remove::<DitherTask>();
+            pending.insert(e);
+            continue;
+        };
+        if !cache.tasks.contains_key(&key) {
+            let image = image.clone();
+            let density = density_map.density;
+            let strategy = density_map.strategy.clone();
+            let task = task_pool
+                .spawn(async move { dither_density_map(&image, density, xz, origin, &strategy) });
+            cache.tasks.insert(key.clone(), task);
+        }
+        // the entity doesn't wait for jobs with old inputs anymore.
+        // Dirty regions are also covered by the new job
+        commands
+            .entity(e)
+            .insert(DitherTask(key))
+            .remove::<DirtyDensityRegion>();
+    }
+}
+
+/// Updates the [`DitheredBuffer`] of all entities waiting for a dithering job once it finished
+///
+/// If the entities were the only users of their old [`DitheredBuffer`], it is replaced instead of adding a new one
+pub(crate) fn insert_dithered_buffer(
+    mut commands: Commands,
+    waiting: Query<(Entity, &DitherTask, Option<&Handle<DitheredBuffer>>)>,
+    users: Query<(Entity, &Handle<DitheredBuffer>)>,
     mut dithered: ResMut<Assets<DitheredBuffer>>,
-    mut storage: Local<Vec<(Entity, DensityMap, Aabb)>>,
+    mut cache: ResMut<DitherCache>,
+    mut patches: ResMut<DitherPatches>,
 ) {
-    let stored = std::mem::take(&mut *storage);
-    for (e, density_map, aabb) in grasses
-        .iter()
-        .chain(stored.iter().map(|(e, map, aabb)| (*e, map, aabb)))
-    {
-        if let Some(image) = images.get(&density_map.density_map) {
-            let xz = aabb.half_extents.xz() * 2.;
-            let Some(buffer) = dither_density_map(image, density_map.density, xz) else {
-                warn!("Couldn't dither density map. Maybe the image format is not supported?");
-                continue;
-            };
-            let handle = dithered.add(buffer);
-            commands.entity(e).insert(handle);
-        } else {
-            storage.push((e, density_map.clone(), *aabb));
+    // the patches of the last frame were extracted already
+    patches.0.clear();
+    // cancel the jobs no entity waits for anymore
+    let awaited: HashSet<&DitherKey> = waiting.iter().map(|(_, task, _)| &task.0).collect();
+    cache.tasks.retain(|key, _| awaited.contains(key));
+
+    let mut finished = Vec::new();
+    for (key, task) in cache.tasks.iter_mut() {
+        if let Some(result) = future::block_on(future::poll_once(task)) {
+            finished.push((key.clone(), result));
+        }
+    }
+    for (key, result) in finished {
+        cache.tasks.remove(&key);
+        let entities: Vec<_> = waiting
+            .iter()
+            .filter(|(_, task, _)| task.0 == key)
+            .map(|(e, _, handle)| (e, handle))
+            .collect();
+        for (e, _) in &entities {
+            commands.entity(*e).remove::<DitherTask>();
+        }
+        let Some(buffer) = result else {
+            warn!("Couldn't dither density map. Maybe the image format is not supported?");
+            continue;
+        };
+        // an old buffer can only be reused if no other entity depends on it
+        let reusable = entities
+            .iter()
+            .find_map(|(_, handle)| *handle)
+            .filter(|old| {
+                users
+                    .iter()
+                    .all(|(e, handle)| handle != *old || entities.iter().any(|(w, _)| *w == e))
+            });
+        let handle = match reusable.and_then(|old| Some((old, dithered.get_mut(old)?))) {
+            Some((old, old_buffer)) => {
+                *old_buffer = buffer;
+                // the old blades aren't cached anymore
+                cache.buffers.retain(|_, cached| cached != old);
+                patches.0.insert(old.clone_weak(), None);
+                old.clone()
+            }
+            None => dithered.add(buffer),
+        };
+        cache.buffers.insert(key, handle.clone_weak());
+        for (e, _) in entities {
+            commands.entity(e).insert(handle.clone());
+        }
+    }
+    // forget the buffers which were freed
+    cache.buffers.retain(|_, handle| dithered.contains(handle));
+}
+
+/// Dithers the [`DirtyDensityRegion`] of an entity again and patches the result into its [`DitheredBuffer`]
+///
+/// Entities sharing a [`DitheredBuffer`] only patch it once
+#[allow(clippy::type_complexity)]
+pub(crate) fn redither_dirty_regions(
+    mut commands: Commands,
+    grasses: Query<
+        (
+            Entity,
+            &DensityMap,
+            &Aabb,
+            Option<&DitheringSpace>,
+            Option<&GlobalTransform>,
+            &Handle<DitheredBuffer>,
+            &DirtyDensityRegion,
+        ),
+        Without<DitherTask>,
+    >,
+    images: Res<Assets<Image>>,
+    mut dithered: ResMut<Assets<DitheredBuffer>>,
+    mut patches: ResMut<DitherPatches>,
+) {
+    let mut regions: HashMap<&Handle<DitheredBuffer>, (&DensityMap, &Aabb, Option<Vec2>, Rect)> =
+        HashMap::new();
+    for (e, density_map, aabb, space, transform, handle, dirty) in &grasses {
+        if !images.contains(&density_map.density_map) {
+            continue;
         }
+        commands.entity(e).remove::<DirtyDensityRegion>();
+        let origin = dither_origin(space, transform);
+        regions
+            .entry(handle)
+            .and_modify(|(_, _, _, region)| *region = region.union(dirty.0))
+            .or_insert((density_map, aabb, origin, dirty.0));
+    }
+    for (handle, (density_map, aabb, origin, region)) in regions {
+        let (Some(buffer), Some(image)) = (
+            dithered.get_mut(handle),
+            images.get(&density_map.density_map),
+        ) else {
+            continue;
+        };
+        let xz = aabb.half_extents.xz() * 2.;
+        let density = density_map.density;
+        let strategy = &density_map.strategy;
+        let blades = buffer.positions.len();
+        let Some(changed) = redither_region(buffer, image, density, xz, origin, strategy, region)
+        else {
+            warn!("Couldn't dither density map. Maybe the image format is not supported?");
+            continue;
+        };
+        // grown buffers might not fit into their gpu buffer anymore and are uploaded completely
+        let patch = (buffer.positions.len() <= blades).then_some(changed);
+        // buffers replaced in this frame stay marked for a complete upload
+        patches.0.entry(handle.clone_weak()).or_insert(patch);
     }
 }
 #[cfg(test)]
 mod tests {
+    use super::DitheringStrategy::*;
     use bevy::math::Vec2;
     use bevy::prelude::Image;
     #[test]
     fn dither_1x1() {
         let image = Image::default(); // 1x1x1 image all white
-        let dither = super::dither_density_map(&image, 1., Vec2::new(1., 1.));
+        let dither = super::dither_density_map(&image, 1., Vec2::new(1., 1.), None, &Bayer);
         assert!(dither.is_some());
         assert_eq!(dither.unwrap().positions.len(), 1);
-        let dither = super::dither_density_map(&image, 1., Vec2::new(10., 5.));
+        let dither = super::dither_density_map(&image, 1., Vec2::new(10., 5.), None, &Bayer);
         assert!(dither.is_some());
         assert!(dither.unwrap().positions.len() == 10 * 5);
     }
     #[test]
     fn dither_density() {
         let image = Image::default(); // 1x1x1 image all white
-        let dither = super::dither_density_map(&image, 2., Vec2::new(1., 1.));
+        let dither = super::dither_density_map(&image, 2., Vec2::new(1., 1.), None, &Bayer);
         assert_eq!(dither.unwrap().positions.len(), (1 * 2) * (1 * 2));
-        let dither = super::dither_density_map(&image, 2., Vec2::new(10., 5.));
+        let dither = super::dither_density_map(&image, 2., Vec2::new(10., 5.), None, &Bayer);
         assert!(dither.unwrap().positions.len() == (10 * 2) * (5 * 2));
-        let dither = super::dither_density_map(&image, 5., Vec2::new(1., 1.));
+        let dither = super::dither_density_map(&image, 5., Vec2::new(1., 1.), None, &Bayer);
         assert!(dither.unwrap().positions.len() == 5 * 5);
-        let dither = super::dither_density_map(&image, 0.1, Vec2::new(10., 10.));
+        let dither = super::dither_density_map(&image, 0.1, Vec2::new(10., 10.), None, &Bayer);
         assert!(dither.unwrap().positions.len() == 1);
 
         // transform the image to be black
@@ -173,50 +1202,379 @@ mod tests {
         // this image is now black
         let image = Image::from_dynamic(luma.into(), true);
         // with a black image we expect 0 grassblades regardless of density
-        let dither = super::dither_density_map(&image, 2., Vec2::new(1., 1.));
+        let dither = super::dither_density_map(&image, 2., Vec2::new(1., 1.), None, &Bayer);
         assert!(dither.unwrap().positions.is_empty());
-        let dither = super::dither_density_map(&image, 20., Vec2::new(1., 1.));
+        let dither = super::dither_density_map(&image, 20., Vec2::new(1., 1.), None, &Bayer);
         assert!(dither.unwrap().positions.is_empty());
-        let dither = super::dither_density_map(&image, 2., Vec2::new(10., 5.));
+        let dither = super::dither_density_map(&image, 2., Vec2::new(10., 5.), None, &Bayer);
         assert!(dither.unwrap().positions.is_empty());
     }
     #[test]
     fn wrong_input() {
         let image = Image::default(); // 1x1x1 image all white
                                       // density=0 should return 0 results but still work
-        let dither = super::dither_density_map(&image, 0., Vec2::new(1., 1.));
+        let dither = super::dither_density_map(&image, 0., Vec2::new(1., 1.), None, &Bayer);
         assert!(dither.unwrap().positions.is_empty());
         // negative density should return None
-        let dither = super::dither_density_map(&image, -1., Vec2::new(1., 1.));
+        let dither = super::dither_density_map(&image, -1., Vec2::new(1., 1.), None, &Bayer);
         assert!(dither.is_none());
-        let dither = super::dither_density_map(&image, 1., Vec2::new(0., 0.));
+        let dither = super::dither_density_map(&image, 1., Vec2::new(0., 0.), None, &Bayer);
         assert!(dither.is_none());
     }
     #[test]
     fn dither_field_size() {
         let image = Image::default(); // 1x1x1 image all white
-        let dither = super::dither_density_map(&image, 1., Vec2::new(10., 1.));
+        let dither = super::dither_density_map(&image, 1., Vec2::new(10., 1.), None, &Bayer);
         assert!(dither.is_some());
-        let dither = super::dither_density_map(&image, 1., Vec2::new(10., 10.));
+        let dither = super::dither_density_map(&image, 1., Vec2::new(10., 10.), None, &Bayer);
         assert!(dither.is_some());
         assert!(dither.unwrap().positions.len() == 10 * 10);
-        let dither = super::dither_density_map(&image, 1., Vec2::new(0., 10.));
+        let dither = super::dither_density_map(&image, 1., Vec2::new(0., 10.), None, &Bayer);
         assert!(dither.is_some());
         assert!(dither.unwrap().positions.is_empty());
-        let dither = super::dither_density_map(&image, 1., Vec2::new(100., 0.));
+        let dither = super::dither_density_map(&image, 1., Vec2::new(100., 0.), None, &Bayer);
         assert!(dither.is_some());
         assert!(dither.unwrap().positions.is_empty());
-        let dither = super::dither_density_map(&image, 1., Vec2::new(-10., 0.));
+        let dither = super::dither_density_map(&image, 1., Vec2::new(-10., 0.), None, &Bayer);
         assert!(dither.is_some());
         assert!(dither.unwrap().positions.is_empty());
-        let dither = super::dither_density_map(&image, 1., Vec2::new(0., -10.));
+        let dither = super::dither_density_map(&image, 1., Vec2::new(0., -10.), None, &Bayer);
         assert!(dither.is_some());
         assert!(dither.unwrap().positions.is_empty());
-        let dither = super::dither_density_map(&image, 1., Vec2::new(-10., -10.));
+        let dither = super::dither_density_map(&image, 1., Vec2::new(-10., -10.), None, &Bayer);
         assert!(dither.is_some());
         assert_eq!(dither.unwrap().positions.len(), 100);
-        let dither = super::dither_density_map(&image, 1., Vec2::new(-10., 5.));
+        let dither = super::dither_density_map(&image, 1., Vec2::new(-10., 5.), None, &Bayer);
         assert!(dither.is_some());
         assert_eq!(dither.unwrap().positions.len(), 50);
     }
+    #[test]
+    fn lod_prefix_is_stratified() {
+        let image = Image::default(); // 1x1x1 image all white
+        let positions = super::dither_density_map(&image, 1., Vec2::new(16., 16.), None, &Bayer)
+            .unwrap()
+            .positions;
+        assert_eq!(positions.len(), 16 * 16);
+        // a quarter of the blades covers all quadrants of the field evenly
+        let prefix = &positions[..positions.len() / 4];
+        for quadrant in [(0., 0.), (8., 0.), (0., 8.), (8., 8.)] {
+            let min = Vec2::from(quadrant);
+            let count = prefix
+                .iter()
+                .filter(|p| p.cmpge(min).all() && p.cmplt(min + 8.).all())
+                .count();
+            assert_eq!(count, 16);
+        }
+        // every blade is kept
+        let mut sorted = positions.clone();
+        sorted.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+        sorted.dedup();
+        assert_eq!(sorted.len(), 16 * 16);
+    }
+    #[test]
+    fn dither_blue_noise() {
+        let image = Image::default(); // 1x1x1 image all white
+        let strategy = BlueNoise { seed: 42 };
+        let dither = super::dither_density_map(&image, 1., Vec2::new(10., 5.), None, &strategy);
+        assert_eq!(dither.unwrap().positions.len(), 10 * 5);
+        let dither = super::dither_density_map(&image, 2., Vec2::new(10., 5.), None, &strategy);
+        assert_eq!(dither.unwrap().positions.len(), (10 * 2) * (5 * 2));
+
+        // the tile contains every threshold exactly once
+        let tile = super::blue_noise_tile(42);
+        let mut thresholds: Vec<f32> = tile.iter().flatten().copied().collect();
+        thresholds.sort_by(f32::total_cmp);
+        thresholds.dedup();
+        assert_eq!(
+            thresholds.len(),
+            super::BLUE_NOISE_SIZE * super::BLUE_NOISE_SIZE
+        );
+
+        // the same seed always results in the same tile, which is only generated once
+        assert_eq!(*tile, super::generate_blue_noise_tile(42));
+        assert!(std::sync::Arc::ptr_eq(&tile, &super::blue_noise_tile(42)));
+        assert_ne!(tile, super::blue_noise_tile(7));
+
+        let image = gray_image(128);
+        let positions = super::dither_density_map(&image, 1., Vec2::new(32., 32.), None, &strategy)
+            .unwrap()
+            .positions;
+        // half of the blades should be placed
+        assert!((500..530).contains(&positions.len()));
+        let again = super::dither_density_map(&image, 1., Vec2::new(32., 32.), None, &strategy);
+        assert_eq!(positions, again.unwrap().positions);
+
+        let image = gray_image(0);
+        let dither = super::dither_density_map(&image, 20., Vec2::new(10., 5.), None, &strategy);
+        assert!(dither.unwrap().positions.is_empty());
+    }
+    #[test]
+    fn dither_poisson_disk() {
+        let image = Image::default(); // 1x1x1 image all white
+        let strategy = PoissonDisk {
+            seed: 42,
+            min_distance: 0.,
+        };
+        // without a minimum distance every candidate is placed
+        let dither = super::dither_density_map(&image, 2., Vec2::new(10., 5.), None, &strategy);
+        assert_eq!(dither.unwrap().positions.len(), (10 * 2) * (5 * 2));
+
+        let strategy = PoissonDisk {
+            seed: 42,
+            min_distance: 0.4,
+        };
+        let positions = super::dither_density_map(&image, 4., Vec2::new(10., 10.), None, &strategy)
+            .unwrap()
+            .positions;
+        assert!(!positions.is_empty());
+        assert!(positions.len() < 40 * 40);
+        for (index, a) in positions.iter().enumerate() {
+            for b in &positions[index + 1..] {
+                assert!(a.distance(*b) >= 0.4);
+            }
+        }
+        // the same seed always results in the same positions
+        let again = super::dither_density_map(&image, 4., Vec2::new(10., 10.), None, &strategy);
+        assert_eq!(positions, again.unwrap().positions);
+        let other_seed = PoissonDisk {
+            seed: 7,
+            min_distance: 0.4,
+        };
+        let other = super::dither_density_map(&image, 4., Vec2::new(10., 10.), None, &other_seed);
+        assert_ne!(positions, other.unwrap().positions);
+
+        let image = gray_image(0);
+        let dither = super::dither_density_map(&image, 20., Vec2::new(10., 5.), None, &strategy);
+        assert!(dither.unwrap().positions.is_empty());
+    }
+    /// Creates a 1x1 image with the given gray value
+    fn gray_image(value: u8) -> Image {
+        let dynamic = Image::default().try_into_dynamic().unwrap();
+        let mut luma = dynamic.to_luma8();
+        luma.get_pixel_mut(0, 0).0 = [value];
+        Image::from_dynamic(luma.into(), true)
+    }
+    #[test]
+    fn redither_region() {
+        use bevy::math::Rect;
+        use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+        let size = Extent3d {
+            width: 4,
+            height: 4,
+            depth_or_array_layers: 1,
+        };
+        let white = [255, 255, 255, 255];
+        let mut image = Image::new_fill(
+            size,
+            TextureDimension::D2,
+            &white,
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        let field_size = Vec2::new(8., 8.);
+        for strategy in [
+            Bayer,
+            BlueNoise { seed: 1 },
+            PoissonDisk {
+                seed: 1,
+                min_distance: 0.,
+            },
+        ] {
+            let mut buffer =
+                super::dither_density_map(&image, 4., field_size, None, &strategy).unwrap();
+            // paint the pixel (1, 2) black
+            let changed = (2 * size.width as usize + 1) * 4;
+            image.data[changed..changed + 3].copy_from_slice(&[0, 0, 0]);
+            let region = Rect::new(0.25, 0.5, 0.5, 0.75);
+            let old = buffer.positions.clone();
+            let patched = super::redither_region(
+                &mut buffer,
+                &image,
+                4.,
+                field_size,
+                None,
+                &strategy,
+                region,
+            )
+            .unwrap();
+
+            // the patched buffer keeps the order for the level of detail
+            let expected = super::dither_density_map(&image, 4., field_size, None, &strategy)
+                .unwrap()
+                .positions;
+            assert_eq!(buffer.positions, expected);
+            // only the blades in the changed range differ
+            assert_eq!(old[..patched.start], expected[..patched.start]);
+            assert_eq!(patched.end, expected.len());
+            // patching an unchanged region changes nothing
+            let unchanged = super::redither_region(
+                &mut buffer,
+                &image,
+                4.,
+                field_size,
+                None,
+                &strategy,
+                region,
+            )
+            .unwrap();
+            assert!(unchanged.is_empty());
+            assert_eq!(buffer.positions, expected);
+            // the black pixel covers a sixteenth of the field
+            assert_eq!(expected.len(), 32 * 32 - 8 * 8);
+            image.data[changed..changed + 3].copy_from_slice(&white[..3]);
+        }
+    }
+    #[test]
+    fn dither_key() {
+        use super::DitherKey;
+        use crate::map::DensityMap;
+        let density_map = DensityMap::new(Default::default(), 2.);
+        let field_size = Vec2::new(50., 50.);
+        let key = DitherKey::new(&density_map, field_size, None);
+        assert_eq!(key, DitherKey::new(&density_map.clone(), field_size, None));
+        // every input changes the key
+        assert_ne!(key, DitherKey::new(&density_map, Vec2::new(50., 20.), None));
+        let denser = DensityMap::new(Default::default(), 3.);
+        assert_ne!(key, DitherKey::new(&denser, field_size, None));
+        let blue_noise = density_map.clone().with_strategy(BlueNoise { seed: 0 });
+        assert_ne!(key, DitherKey::new(&blue_noise, field_size, None));
+        let other_seed = density_map.clone().with_strategy(BlueNoise { seed: 1 });
+        assert_ne!(
+            DitherKey::new(&blue_noise, field_size, None),
+            DitherKey::new(&other_seed, field_size, None)
+        );
+        let world = DitherKey::new(&density_map, field_size, Some(Vec2::ZERO));
+        assert_ne!(key, world);
+        assert_ne!(
+            world,
+            DitherKey::new(&density_map, field_size, Some(Vec2::new(50., 0.)))
+        );
+    }
+    #[test]
+    fn dither_world_space() {
+        // a chunk split into two halves has to result in the same blades
+        let image = gray_image(128);
+        let density = 3.;
+        for strategy in [
+            Bayer,
+            BlueNoise { seed: 2 },
+            PoissonDisk {
+                seed: 2,
+                min_distance: 0.,
+            },
+        ] {
+            let origin = Vec2::new(-4.3, 1.7);
+            let whole = super::dither_density_map(
+                &image,
+                density,
+                Vec2::new(10., 5.),
+                Some(origin),
+                &strategy,
+            )
+            .unwrap();
+            let mut halves = Vec::new();
+            for (offset, size) in [(0., 3.9), (3.9, 6.1)] {
+                let half_origin = origin + Vec2::new(offset, 0.);
+                let half = super::dither_density_map(
+                    &image,
+                    density,
+                    Vec2::new(size, 5.),
+                    Some(half_origin),
+                    &strategy,
+                )
+                .unwrap();
+                halves.extend(half.positions.iter().map(|p| *p + half_origin));
+            }
+            let mut whole: Vec<Vec2> = whole.positions.iter().map(|p| *p + origin).collect();
+            let sort = |positions: &mut Vec<Vec2>| {
+                positions.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+            };
+            sort(&mut whole);
+            sort(&mut halves);
+            assert!(!whole.is_empty());
+            assert_eq!(whole.len(), halves.len());
+            for (a, b) in whole.iter().zip(&halves) {
+                assert!(a.distance(*b) < 0.001, "{a} != {b}");
+            }
+        }
+        // the blades lie on the world grid
+        let dither = super::dither_density_map(
+            &Image::default(),
+            2.,
+            Vec2::new(3., 3.),
+            Some(Vec2::new(0.2, -0.2)),
+            &Bayer,
+        )
+        .unwrap();
+        for position in dither.positions {
+            let world = (position + Vec2::new(0.2, -0.2)) * 2.;
+            assert!(world.distance(world.round()) < 0.001);
+        }
+    }
+    #[test]
+    fn high_precision_formats() {
+        use super::{DensityBuffer, FULL_MAP};
+        use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+        let image = |data: Vec<u8>, format| {
+            let size = Extent3d {
+                width: 2,
+                height: 1,
+                depth_or_array_layers: 1,
+            };
+            Image::new(size, TextureDimension::D2, data, format)
+        };
+        // values in between two 8 bit levels keep their precision
+        let low = 0x8000_u16;
+        let high = 0x8040_u16;
+        let data: Vec<u8> = [low, high].iter().flat_map(|v| v.to_le_bytes()).collect();
+        for format in [TextureFormat::R16Unorm, TextureFormat::R16Uint] {
+            let buffer = DensityBuffer::from_image(&image(data.clone(), format), FULL_MAP).unwrap();
+            assert_eq!(buffer.sample(0., 0.), low as f32 / u16::MAX as f32);
+            assert_eq!(buffer.sample(0.5, 0.), high as f32 / u16::MAX as f32);
+        }
+        let data = [0.25_f32, 0.2501]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let buffer =
+            DensityBuffer::from_image(&image(data, TextureFormat::R32Float), FULL_MAP).unwrap();
+        assert_eq!(buffer.sample(0., 0.), 0.25);
+        assert_eq!(buffer.sample(0.5, 0.), 0.2501);
+        // 0.5 and 0.75 as half precision floats
+        let data = [0x3800_u16, 0x3a00]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let buffer =
+            DensityBuffer::from_image(&image(data, TextureFormat::R16Float), FULL_MAP).unwrap();
+        assert_eq!(buffer.sample(0., 0.), 0.5);
+        assert_eq!(buffer.sample(0.5, 0.), 0.75);
+        // only the pixels in the region are converted
+        let data = [0.25_f32, 0.5, 0.75, 1.]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let size = Extent3d {
+            width: 4,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        let row = Image::new(size, TextureDimension::D2, data, TextureFormat::R32Float);
+        let region = bevy::math::Rect::new(0.5, 0., 0.6, 1.);
+        let buffer = DensityBuffer::from_image(&row, region).unwrap();
+        assert_eq!(buffer.pixels, [0.75]);
+        assert_eq!(buffer.sample(0.55, 0.5), 0.75);
+        // the alpha channel is ignored
+        let data = [[1_f32, 1., 1., 0.], [0., 0., 0., 1.]]
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let rgba = image(data, TextureFormat::Rgba32Float);
+        let buffer = DensityBuffer::from_image(&rgba, FULL_MAP).unwrap();
+        assert!((buffer.sample(0., 0.) - 1.).abs() < 0.0001);
+        assert_eq!(buffer.sample(0.5, 0.), 0.);
+        // a float density map can be dithered
+        let dither = super::dither_density_map(&rgba, 1., Vec2::new(10., 10.), None, &Bayer);
+        assert_eq!(dither.unwrap().positions.len(), 5 * 10);
+    }
 }
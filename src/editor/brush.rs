@@ -77,7 +77,7 @@ impl BrushBehavior for Airbrush {
 
 fn pixel_positions(brush_size: u32, image_dimensions: Vec2, position: Vec2) -> Vec<(u32, u32)> {
     let position = (image_dimensions * position).as_ivec2();
-    let range = brush_size as i32 * (image_dimensions.x + image_dimensions.y) as i32 / 100;
+    let range = brush_range(brush_size, image_dimensions);
     (-range..range)
         .flat_map(|i| (-range..range).map(move |j| (i, j)))
         .filter(|(x, y)| {
@@ -90,6 +90,21 @@ fn pixel_positions(brush_size: u32, image_dimensions: Vec2, position: Vec2) -> V
         .map(|(x, y)| (x as u32, y as u32))
         .collect()
 }
+/// Returns the area a brush draws on, in normalized image coordinates between 0 and 1
+pub fn brush_area(brush_size: u32, image_dimensions: Vec2, position: Vec2) -> Rect {
+    let position = (image_dimensions * position).as_ivec2();
+    let range = IVec2::splat(brush_range(brush_size, image_dimensions));
+    let min = (position - range).as_vec2() / image_dimensions;
+    let max = (position + range).as_vec2() / image_dimensions;
+    Rect::from_corners(
+        min.clamp(Vec2::ZERO, Vec2::ONE),
+        max.clamp(Vec2::ZERO, Vec2::ONE),
+    )
+}
+/// The distance in pixels a brush reaches from its center
+fn brush_range(brush_size: u32, image_dimensions: Vec2) -> i32 {
+    brush_size as i32 * (image_dimensions.x + image_dimensions.y) as i32 / 100
+}
 fn paint_gray(pixel: &mut [u8; 4], strength: f32) {
     if strength >= 0. {
         let strength = strength as u8;
@@ -1,32 +1,78 @@
-use bevy::prelude::*;
+use bevy::{ecs::query::Has, prelude::*, utils::HashMap};
 
-use crate::{map::DensityMap, map::YMap, prelude::WarblerHeight};
+use crate::{
+    dithering::{DirtyDensityRegion, DitheredBuffer},
+    map::DensityMap,
+    map::YMap,
+    prelude::WarblerHeight,
+};
 
-use super::draw_event::DrawEvent;
+use super::{draw_event::DrawEvent, ui::ActiveTool};
 
 /// Sets the `DensityMap` or `HeightMap` to changed if their internal image get's changed
 /// This is importend for extracting the entity again to the render world
+///
+/// If only a part of an already dithered density map is drawn on,
+/// a [`DirtyDensityRegion`] is used instead so only the blades in that region are updated
+#[allow(clippy::type_complexity)]
 pub fn notify_image_change(
+    mut commands: Commands,
     mut ev_asset: EventReader<DrawEvent>,
-    mut q: Query<(&mut YMap, &mut DensityMap, &mut WarblerHeight)>,
+    mut q: Query<(
+        Entity,
+        &mut YMap,
+        &mut DensityMap,
+        &mut WarblerHeight,
+        Option<&mut DirtyDensityRegion>,
+        Has<Handle<DitheredBuffer>>,
+    )>,
+    active_tool: Res<ActiveTool>,
+    images: Res<Assets<Image>>,
 ) {
+    let mut dirty_regions: HashMap<Entity, Rect> = HashMap::new();
     for ev in ev_asset.iter() {
         let Some(image) = ev.image_handle() else {
             continue;
         };
+        let area = match (ev, images.get(image)) {
+            (DrawEvent::Draw { positions, .. }, Some(img)) => {
+                active_tool.affected_area(img, *positions)
+            }
+            _ => None,
+        };
 
-        for (mut y_map, mut density_map, mut heights) in &mut q {
+        for (entity, mut y_map, mut density_map, mut heights, _, is_dithered) in &mut q {
             if y_map.y_map == *image {
                 y_map.as_mut();
             }
             if density_map.density_map == *image {
-                density_map.as_mut();
+                match area {
+                    Some(area) if is_dithered => {
+                        dirty_regions
+                            .entry(entity)
+                            .and_modify(|region| *region = region.union(area))
+                            .or_insert(area);
+                    }
+                    _ => {
+                        density_map.as_mut();
+                    }
+                }
             }
-            if let WarblerHeight::Texture(texture) = heights.as_ref() {
+            if let WarblerHeight::Texture { texture, .. } = heights.as_ref() {
                 if texture == image {
                     heights.as_mut();
                 }
             }
         }
     }
+    for (entity, region) in dirty_regions {
+        let Ok((.., dirty, _)) = q.get_mut(entity) else {
+            continue;
+        };
+        if let Some(mut dirty) = dirty {
+            dirty.extend(region);
+        } else {
+            commands.entity(entity).insert(DirtyDensityRegion(region));
+        }
+    }
 }
@@ -75,7 +75,7 @@ fn check_collision_on_click(
                 SelectedMap::YMap => y_map.y_map.clone(),
                 SelectedMap::DensityMap => density_map.density_map.clone(),
                 SelectedMap::HeightsMap => {
-                    if let WarblerHeight::Texture(image) = heights {
+                    if let WarblerHeight::Texture { texture: image, .. } = heights {
                         image.clone()
                     } else {
                         warn!("No heights texture found. Using density map instead");
@@ -38,7 +38,7 @@ pub fn check_for_save_files(
                     let Some(warbler_height) = warbler_height else {
                         return;
                     };
-                    let WarblerHeight::Texture(tex) = warbler_height else {
+                    let WarblerHeight::Texture { texture: tex, .. } = warbler_height else {
                         return;
                     };
                     tex
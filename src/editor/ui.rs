@@ -1,10 +1,11 @@
 use super::{
-    brush::{Airbrush, BrushBehavior, Stencil},
+    brush::{brush_area, Airbrush, BrushBehavior, Stencil},
     ray_cast::SelectedMap,
     save::ImageSaver,
     tools::{Eraser, Filler},
     ActiveEditorChunk,
 };
+use crate::GrassConfiguration;
 use bevy::{prelude::*, window::PrimaryWindow};
 use bevy_inspector_egui::{
     bevy_egui::EguiContext,
@@ -65,6 +66,16 @@ pub fn run_ui(world: &mut World) {
             }
             ui.add_space(section_distance);
         });
+        ui.separator();
+        ui.push_id(4, |ui| {
+            ui.label(
+                RichText::new("Grass Configuration")
+                    .font(FontId::proportional(20.0))
+                    .underline(),
+            );
+            bevy_inspector::ui_for_resource::<GrassConfiguration>(world, ui);
+            ui.add_space(section_distance);
+        });
     });
     egui::Window::new("Active Chunk Entity").show(egui_context.get_mut(), |ui| {
         ui.separator();
@@ -101,6 +112,15 @@ impl ActiveTool {
             ActiveTool::Filler => Filler::fill(image),
         }
     }
+    /// The normalized area of the image changed when applying the tool at `position`
+    ///
+    /// Returns `None` if the whole image is changed
+    pub fn affected_area(&self, image: &Image, position: Vec2) -> Option<Rect> {
+        match self {
+            ActiveTool::Brush(brush) => Some(brush_area(brush.brush_size, image.size(), position)),
+            ActiveTool::Eraser | ActiveTool::Filler => None,
+        }
+    }
 }
 
 /// The brush which can be used to draw on a
@@ -19,17 +19,24 @@ pub mod bundle;
 pub mod dithering;
 
 pub mod diagnostic;
+pub mod displacement;
 #[cfg(feature = "editor")]
 pub mod editor;
 
 pub mod map;
 
 mod render;
+pub mod sampler;
 pub mod warblers_plugin;
+pub mod wind;
 pub mod prelude {
     pub use crate::bundle::*;
+    pub use crate::displacement::{GrassDisplacer, GrassTrample};
+    pub use crate::dithering::{DirtyDensityRegion, DitheringStrategy};
     pub use crate::map::*;
+    pub use crate::sampler::{GrassSample, GrassSampler};
     pub use crate::warblers_plugin::WarblersPlugin;
+    pub use crate::wind::{GrassWind, WindZone};
     pub use crate::GrassConfiguration;
 }
 
@@ -48,13 +55,53 @@ pub struct GrassConfiguration {
     ///
     /// If you want to change the generel look of the wind and not only the wind direction/ speed
     /// you can also change the noise texture used for the wind that is stored in the
-    /// [`GrassNoiseTexture`] resource
+    /// [`GrassNoiseTexture`] resource.
+    ///
+    /// The wind is sampled in world space, so the gusts move on over the borders of the chunks.
+    /// The [`GrassWind`](crate::wind::GrassWind) system parameter calculates the same displacement on the cpu
     pub wind: Vec2,
+    /// The pixels of the wind noise texture covered by one world unit.
+    ///
+    /// Lower values stretch the noise, which results in wider gusts
+    pub wind_noise_scale: f32,
+    /// The speed the wind noise texture moves in the direction of the wind,
+    /// in pixels per second and unit of wind strength
+    pub wind_scroll_speed: f32,
+    /// How often gusts strengthen the wind at a position, in gusts per second.
+    ///
+    /// The gusts travel over the grass in the direction of the wind
+    pub gust_frequency: f32,
+    /// How much the gusts strengthen the wind.
+    ///
+    /// A value of 1 doubles the strength of the wind in the peak of a gust, 0 disables the gusts
+    pub gust_amplitude: f32,
+    /// The strength of small swirls added to the wind, relative to the strength of the wind.
+    ///
+    /// Unlike the wind itself they move the blades in all directions
+    pub turbulence: f32,
+    /// The exponent of the curve bending the blades along their height.
+    ///
+    /// Higher values keep the bottom of the blades stiff and only let the tips sway,
+    /// lower values bend the whole blade. Should be greater than 0
+    pub sway_stiffness: f32,
+    /// The range of the random rotation of the grass blades around the y axis in radians.
+    ///
+    /// The rotation of a blade is derived from its position, so it stays the same over time.
+    /// Defaults to 0, which lets all blades face the same direction.
+    /// Set it to [`TAU`](std::f32::consts::TAU) to rotate the blades in any direction
+    pub yaw_variation: f32,
 }
 impl Default for GrassConfiguration {
     fn default() -> Self {
         GrassConfiguration {
             wind: Vec2::new(1.0, 1.0),
+            wind_noise_scale: 35.,
+            wind_scroll_speed: 50.,
+            gust_frequency: 0.2,
+            gust_amplitude: 0.,
+            turbulence: 0.,
+            sway_stiffness: 1.,
+            yaw_variation: 0.,
         }
     }
 }
@@ -1,12 +1,15 @@
 //! Contains the [`YMap`](crate::maps::YMap) and [`DensityMap`](crate::maps::DensityMap) component
 
 use bevy::{
-    asset::Handle,
-    ecs::{component::Component, query::QueryItem},
+    asset::{AssetEvent, Assets, Handle},
+    ecs::{component::Component, prelude::*, query::QueryItem},
     reflect::Reflect,
-    render::{extract_component::ExtractComponent, texture::Image},
+    render::{extract_component::ExtractComponent, render_resource::TextureUsages, texture::Image},
+    utils::HashSet,
 };
 
+use crate::{bundle::WarblerHeight, dithering::DitheringStrategy};
+
 /// The y-map defining the y position of the grass blades.
 ///
 /// A [`YMap`] is usually called a heightmap in game dev.
@@ -15,6 +18,7 @@ use bevy::{
 ///
 /// The maximum height of the y-map is controlled by the height of the [`Aabb`](bevy::render::primitives::Aabb).
 /// The y-map texture will be scaled over all grass blades.
+/// Besides 8 bit images, 16 bit pngs and float textures like `R32Float` are supported to avoid terraced grass on slopes.
 ///
 /// For a simple example, take a look at the [`load_grass`](https://github.com/emiongit/warbler_grass/latest/example/load_grass.rs) example
 #[derive(Reflect, Clone, Component)]
@@ -93,6 +97,7 @@ pub struct DensityMap {
     /// The density map of the grass chunk.
     ///
     /// Should be ideally gray scale image for memory efficency.
+    /// 16 bit pngs and float textures keep their full precision when dithering.
     /// White pixels corresponds to dense areas.
     /// Black pixels correspond to sparse areas.
     pub density_map: Handle<Image>,
@@ -101,6 +106,10 @@ pub struct DensityMap {
     /// If the density is high, more grass is spawned in a dense area.
     /// The density should always be positiv
     pub density: f32,
+    /// The [`DitheringStrategy`] used to place the grass blades.
+    ///
+    /// Defaults to [`DitheringStrategy::Bayer`].
+    pub strategy: DitheringStrategy,
 }
 impl DensityMap {
     /// Creates a new `DensityMap`
@@ -111,8 +120,14 @@ impl DensityMap {
         DensityMap {
             density_map,
             density,
+            strategy: DitheringStrategy::default(),
         }
     }
+    /// Sets the [`DitheringStrategy`] used to place the grass blades
+    pub fn with_strategy(mut self, strategy: DitheringStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
 }
 /// A density map can be created from the image alone
 ///
@@ -122,6 +137,53 @@ impl From<Handle<Image>> for DensityMap {
         DensityMap {
             density_map: value,
             density: 1.,
+            strategy: DitheringStrategy::default(),
+        }
+    }
+}
+
+/// Adds the `COPY_SRC` usage to the y-maps, normal maps and height textures of the chunks once they are loaded
+///
+/// Chunks with different maps are drawn in one batch by copying their maps into the layers of a texture array.
+/// Only added if batching is supported
+pub(crate) fn allow_map_copies(
+    mut images: ResMut<Assets<Image>>,
+    mut events: EventReader<AssetEvent<Image>>,
+    chunks: Query<(Ref<YMap>, Ref<NormalMap>, Ref<WarblerHeight>)>,
+) {
+    let loaded: HashSet<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => Some(handle.id()),
+            AssetEvent::Removed { .. } => None,
+        })
+        .collect();
+    for (y_map, normal_map, height) in &chunks {
+        let height_texture = match height.as_ref() {
+            WarblerHeight::Texture { texture, .. } => Some((texture, height.is_changed())),
+            WarblerHeight::Uniform(_) => None,
+        };
+        let maps = [
+            Some((&y_map.y_map, y_map.is_changed())),
+            Some((&normal_map.normal_map, normal_map.is_changed())),
+            height_texture,
+        ];
+        for (handle, changed) in maps.into_iter().flatten() {
+            if !changed && !loaded.contains(&handle.id()) {
+                continue;
+            }
+            let copyable = images.get(handle).map(|image| {
+                image
+                    .texture_descriptor
+                    .usage
+                    .contains(TextureUsages::COPY_SRC)
+            });
+            // the texture is only created again if the usage was missing
+            if copyable == Some(false) {
+                if let Some(image) = images.get_mut(handle) {
+                    image.texture_descriptor.usage |= TextureUsages::COPY_SRC;
+                }
+            }
         }
     }
 }
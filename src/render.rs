@@ -1,17 +1,22 @@
 use bevy::{
-    pbr::{SetMeshBindGroup, SetMeshViewBindGroup},
+    pbr::{SetMeshBindGroup, SetMeshViewBindGroup, SetPrepassViewBindGroup},
     render::render_phase::SetItemPipeline,
 };
 
+use self::batch::{DrawGrassBatch, SetBatchBindGroup, SetBatchMapBindGroups};
 use self::draw::{
     SetColorBindGroup, SetHeightBindGroup, SetNormalBindGroup, SetUniformBindGroup,
     SetVertexBuffer, SetYBindGroup,
 };
 
+pub(crate) mod batch;
 pub(crate) mod cache;
+pub(crate) mod culling;
+pub(crate) mod displacement;
 mod draw;
 pub(crate) mod extract;
 pub(crate) mod grass_pipeline;
+pub(crate) mod lod;
 pub(crate) mod prepare;
 pub(crate) mod queue;
 
@@ -36,3 +41,31 @@ pub(crate) type GrassDrawCall = (
     // Binds the xz position of the grass instances to the vertex buffer
     SetVertexBuffer,
 );
+
+// The render call drawing all chunks of a batch in the main pass
+pub(crate) type GrassBatchDrawCall = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    // Binds the transform, color, height and aabb of all chunks of the batch
+    SetBatchBindGroup<1>,
+    SetUniformBindGroup<2>,
+    SetColorBindGroup<3>,
+    // Binds the texture arrays containing the maps of all chunks of the batch
+    SetBatchMapBindGroups<4>,
+    // Binds the blades of all chunks and draws them at once
+    DrawGrassBatch,
+);
+
+// The render call used for the shadow and prepass pipeline of the grass
+pub(crate) type GrassPrepassDrawCall = (
+    SetItemPipeline,
+    // The view bind group of bevy's prepass, which is also used for the shadow maps
+    SetPrepassViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    SetUniformBindGroup<2>,
+    SetColorBindGroup<3>,
+    SetYBindGroup<4>,
+    SetHeightBindGroup<5>,
+    SetNormalBindGroup<6>,
+    SetVertexBuffer,
+);
@@ -0,0 +1,94 @@
+// Culls the blades of a grass chunk against the frustum of a view
+// and writes the visible ones into a compacted instance buffer drawn indirectly
+
+struct Culling {
+    // the left, right, bottom, top and near plane of the view in world space
+    planes: array<vec4<f32>, 5>,
+    model: mat4x4<f32>,
+    view_position: vec3<f32>,
+    blade_count: f32,
+    size: vec3<f32>,
+    margin: f32,
+    lod_distances: vec4<f32>,
+    lod_densities: vec4<f32>,
+    fade_end: f32,
+    count: u32,
+    _wasm_padding: vec2<f32>,
+}
+// The start of the indirect draw arguments, which are the same for indexed and non indexed meshes
+struct DrawArgs {
+    vertex_count: u32,
+    instance_count: atomic<u32>,
+}
+
+@group(0) @binding(0)
+var<uniform> culling: Culling;
+
+@group(0) @binding(1)
+var<storage, read> blades: array<vec2<f32>>;
+
+// the position and rank of each visible blade, tightly packed
+@group(0) @binding(2)
+var<storage, read_write> visible_blades: array<f32>;
+
+@group(0) @binding(3)
+var<storage, read_write> draw_args: DrawArgs;
+
+const WORKGROUP_SIZE: u32 = 64u;
+
+// Returns false if the level of detail at the distance drops the blade with the given rank
+fn lod_visible(distance: f32, rank: f32) -> bool {
+    var level_distance = -1.;
+    var visible = true;
+    for (var i = 0; i < 4; i++) {
+        let lod_distance = culling.lod_distances[i];
+        if lod_distance <= distance && lod_distance >= level_distance {
+            level_distance = lod_distance;
+            visible = rank < culling.lod_densities[i];
+        }
+    }
+    return visible;
+}
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) workgroups: vec3<u32>) {
+    let index = id.x + id.y * workgroups.x * WORKGROUP_SIZE;
+    if index >= culling.count {
+        return;
+    }
+    let position = blades[index];
+
+    // the box the blade stays inside of with all its displacements
+    let local_min = vec3<f32>(position.x, 0., position.y) - culling.margin;
+    let local_max = vec3<f32>(position.x, culling.size.y, position.y) + culling.margin;
+    let half_extents = (local_max - local_min) * 0.5;
+    let center = (culling.model * vec4<f32>(local_min + half_extents, 1.)).xyz;
+    let x_axis = culling.model[0].xyz;
+    let y_axis = culling.model[1].xyz;
+    let z_axis = culling.model[2].xyz;
+
+    // ---FRUSTUM---
+    for (var i = 0; i < 5; i++) {
+        let plane = culling.planes[i];
+        let relative_radius = dot(abs(vec3<f32>(dot(plane.xyz, x_axis), dot(plane.xyz, y_axis), dot(plane.xyz, z_axis))), half_extents);
+        if dot(plane.xyz, center) + plane.w + relative_radius <= 0. {
+            return;
+        }
+    }
+
+    // ---DISTANCE---
+    let world_half_extents = abs(x_axis) * half_extents.x + abs(y_axis) * half_extents.y + abs(z_axis) * half_extents.z;
+    let distance = max(length(center - culling.view_position) - length(world_half_extents), 0.);
+    if distance >= culling.fade_end {
+        return;
+    }
+    let rank = f32(index) / culling.blade_count;
+    if !lod_visible(distance, rank) {
+        return;
+    }
+
+    let visible_index = atomicAdd(&draw_args.instance_count, 1u) * 3u;
+    visible_blades[visible_index] = position.x;
+    visible_blades[visible_index + 1u] = position.y;
+    visible_blades[visible_index + 2u] = rank;
+}
@@ -1,75 +1,352 @@
-#import bevy_pbr::mesh_functions mesh_position_local_to_clip
 #import bevy_pbr::mesh_types Mesh
-#import bevy_pbr::mesh_view_bindings globals
+#ifdef PREPASS
+    #import bevy_pbr::prepass_bindings view, globals
+    #ifdef MOTION_VECTOR_PREPASS
+        #import bevy_pbr::prepass_bindings previous_view_proj
+    #endif
+#else
+    #import bevy_pbr::mesh_view_bindings view, globals
+#endif
+#ifdef LIT
+    #import bevy_pbr::mesh_view_bindings as view_bindings
+    #import bevy_pbr::mesh_view_types as mesh_view_types
+    #import bevy_pbr::lighting as lighting
+    #import bevy_pbr::clustered_forward as clustering
+    #import bevy_pbr::ambient as ambient
+    #import bevy_pbr::fog as fog
+    #import bevy_pbr::shadows as shadows
+    #import bevy_pbr::mesh_types MESH_FLAGS_SHADOW_RECEIVER_BIT
+#endif
 
 struct ShaderRegionConfiguration {
     wind: vec2<f32>,
-    _wasm_padding: vec2<f32>,
+    yaw_variation: f32,
+    wind_noise_scale: f32,
+    wind_scroll_speed: f32,
+    gust_frequency: f32,
+    gust_amplitude: f32,
+    turbulence: f32,
+    sway_stiffness: f32,
+    _wasm_padding: f32,
+    _wasm_padding_2: vec2<f32>,
 };
 struct Vertex {
     @location(0) vertex_position: vec3<f32>,
     @location(3) xz_position: vec2<f32>,
+#ifdef GPU_CULLING
+    // the rank of the blade, since the culled blades aren't in their original order
+    @location(4) rank: f32,
+#endif
 }
 struct Color {
     main_color: vec4<f32>,
     bottom_color: vec4<f32>,
+    perceptual_roughness: f32,
+    reflectance: f32,
+    _wasm_padding: vec2<f32>,
 }
-@group(1) @binding(0)
-var<uniform> mesh: Mesh;
-
 @group(2) @binding(0)
 var<uniform> config: ShaderRegionConfiguration;
 
 @group(2) @binding(1)
 var noise_texture: texture_2d<f32>;
 
-@group(3) @binding(0)
-var<uniform> color: Color;
+struct WindZone {
+    // transforms world positions into the space of a directional zone
+    inverse_model: mat4x4<f32>,
+    center: vec3<f32>,
+    kind: u32,
+    wind: vec2<f32>,
+    strength: f32,
+    radius: f32,
+    half_extents: vec3<f32>,
+    // the blend of a directional zone, the falloff of a radial zone or the width of a ring
+    falloff: f32,
+}
+struct WindZones {
+    count: u32,
+    _wasm_padding: u32,
+    _wasm_padding_2: vec2<u32>,
+    zones: array<WindZone, 16>,
+}
+@group(2) @binding(2)
+var<uniform> wind_zones: WindZones;
 
-@group(4) @binding(0)
-var y_texture: texture_2d<f32>;
+struct Displacer {
+    position: vec3<f32>,
+    radius: f32,
+    strength: f32,
+    falloff: f32,
+    _wasm_padding: vec2<f32>,
+}
+// the displacers closest to the chunk
+struct Displacers {
+    count: u32,
+    _wasm_padding: u32,
+    _wasm_padding_2: vec2<u32>,
+    displacers: array<Displacer, 8>,
+}
+@group(2) @binding(3)
+var<uniform> displacers: Displacers;
+
+
+// the maps of batched chunks are in the layer of a texture array at the index of the chunk
+#ifdef BATCHED
+    #ifdef Y_MAP_UINT
+        @group(4) @binding(0)
+        var y_texture: texture_2d_array<u32>;
+    #else
+        @group(4) @binding(0)
+        var y_texture: texture_2d_array<f32>;
+    #endif
+#else ifdef Y_MAP_UINT
+    @group(4) @binding(0)
+    var y_texture: texture_2d<u32>;
+#else
+    @group(4) @binding(0)
+    var y_texture: texture_2d<f32>;
+#endif
 
 struct ShaderAabb {
     vect: vec3<f32>,
     _wasm_padding: f32,
 }
 
-@group(4) @binding(1)
-var<uniform> aabb: ShaderAabb;
+// the trails of the displacers, next to the y-map. Trampled chunks are never batched
+#ifndef BATCHED
+    @group(4) @binding(2)
+    var trample_texture: texture_2d<f32>;
+#endif
 
-#ifdef HEIGHT_TEXTURE
-    @group(5) @binding(0)
-    var height_texture: texture_2d<f32>;
-#else
-    struct ShaderHeightUniform {
-        height: f32,
-        _wasm_padding: vec2<f32>,
+#ifdef Y_MAP_FILTERED
+    @group(4) @binding(3)
+    var y_sampler: sampler;
+#endif
+
+struct ShaderBladeUniform {
+    height_variation: vec2<f32>,
+    width_variation: vec2<f32>,
+    height_range: vec2<f32>,
+    height: f32,
+    lod_transition: f32,
+    fade: vec2<f32>,
+    blade_count: f32,
+    _wasm_padding: f32,
+    lod_distances: vec4<f32>,
+    lod_densities: vec4<f32>,
+}
+#ifdef HEIGHT_TEXTURE_UINT
+    #ifdef BATCHED
+        @group(5) @binding(0)
+        var height_texture: texture_2d_array<u32>;
+    #else
+        @group(5) @binding(0)
+        var height_texture: texture_2d<u32>;
+    #endif
+#else ifdef HEIGHT_TEXTURE
+    #ifdef BATCHED
+        @group(5) @binding(0)
+        var height_texture: texture_2d_array<f32>;
+    #else
+        @group(5) @binding(0)
+        var height_texture: texture_2d<f32>;
+    #endif
+    #ifdef HEIGHT_TEXTURE_FILTERED
+        @group(5) @binding(2)
+        var height_sampler: sampler;
+    #endif
+#endif
+
+#ifdef BATCHED
+    // The parameters of a chunk drawn together with the other chunks of its batch
+    struct GrassChunk {
+        mesh: Mesh,
+        color: Color,
+        blade: ShaderBladeUniform,
+        aabb: ShaderAabb,
+        // the index of the first blade of the chunk in the instance buffer of the batch
+        first_instance: u32,
+    }
+    @group(1) @binding(0)
+    var<storage, read> chunks: array<GrassChunk>;
+
+    // the parameters of the chunk currently drawn
+    var<private> mesh: Mesh;
+    var<private> color: Color;
+    var<private> aabb: ShaderAabb;
+    var<private> blade: ShaderBladeUniform;
+    // the layer of the maps of the chunk currently drawn
+    var<private> map_layer: i32;
+
+    // Returns the index of the chunk containing the instance
+    fn chunk_index(instance_index: u32) -> u32 {
+        var low = 0u;
+        var high = arrayLength(&chunks) - 1u;
+        while low < high {
+            let middle = (low + high + 1u) / 2u;
+            if chunks[middle].first_instance <= instance_index {
+                low = middle;
+            } else {
+                high = middle - 1u;
+            }
+        }
+        return low;
     }
-    @group(5) @binding(0)
-    var<uniform> height_uniform: ShaderHeightUniform;
+#else
+    @group(1) @binding(0)
+    var<uniform> mesh: Mesh;
+
+    @group(3) @binding(0)
+    var<uniform> color: Color;
+
+    @group(4) @binding(1)
+    var<uniform> aabb: ShaderAabb;
+
+    #ifdef HEIGHT_TEXTURE
+        @group(5) @binding(1)
+        var<uniform> blade: ShaderBladeUniform;
+    #else
+        @group(5) @binding(0)
+        var<uniform> blade: ShaderBladeUniform;
+    #endif
 #endif
 
-@group(6) @binding(0)
-var t_normal: texture_2d<f32>;
+#ifdef BATCHED
+    #ifdef NORMAL_MAP_UINT
+        @group(6) @binding(0)
+        var t_normal: texture_2d_array<u32>;
+    #else
+        @group(6) @binding(0)
+        var t_normal: texture_2d_array<f32>;
+    #endif
+#else ifdef NORMAL_MAP_UINT
+    @group(6) @binding(0)
+    var t_normal: texture_2d<u32>;
+#else
+    @group(6) @binding(0)
+    var t_normal: texture_2d<f32>;
+#endif
+#ifdef NORMAL_MAP_FILTERED
+    @group(6) @binding(1)
+    var normal_sampler: sampler;
+#endif
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
+#ifdef PREPASS
+    #ifdef PREPASS_FRAGMENT
+        @location(1) world_position: vec4<f32>,
+        @location(2) ground_normal: vec3<f32>,
+    #endif
+    #ifdef MOTION_VECTOR_PREPASS
+        @location(3) previous_world_position: vec4<f32>,
+    #endif
+    #ifdef DEPTH_CLAMP_ORTHO
+        @location(4) clip_position_unclamped: vec4<f32>,
+    #endif
+#else
     @location(0) color: vec4<f32>,
+    #ifdef LIT
+        @location(1) world_position: vec4<f32>,
+        @location(2) ground_normal: vec3<f32>,
+    #endif
+    #ifdef BATCHED
+        @location(3) @interpolate(flat) chunk_index: u32,
+    #endif
+#endif
 };
 
-const NOISE_TEXTURE_SPEED: f32 = 50.;
-const NOISE_TEXTURE_ZOOM: f32 = 35.;
-fn wind_offset(vertex_position: vec2<f32>) -> vec2<f32> {
-    var texture_offset = config.wind.xy * globals.time * NOISE_TEXTURE_SPEED;
-    var texture_position = vec2<f32>(vertex_position.x ,vertex_position.y) * NOISE_TEXTURE_ZOOM + texture_offset;
-    
+// mirrored by `warbler_grass::wind`, keep both in sync
+const TURBULENCE_ZOOM: f32 = 4.;
+const TAU: f32 = 6.28318530718;
+// Returns the value of the noise texture, which is tiled over the whole world
+fn wind_noise(texture_position: vec2<f32>) -> vec2<f32> {
     // dimensions of noise texture in vec2<u32>
-    let dim = textureDimensions(noise_texture, 0);
+    let dim = vec2<f32>(textureDimensions(noise_texture, 0));
+    let position = texture_position - floor(texture_position / dim) * dim;
+    return textureLoad(noise_texture, vec2<i32>(i32(position.x), i32(position.y)), 0).xy;
+}
+const DIRECTIONAL_ZONE: u32 = 0u;
+const RADIAL_ZONE: u32 = 1u;
+const RING_ZONE: u32 = 2u;
+// Returns the wind of the zone at the world position
+fn zone_wind(zone: WindZone, world_position: vec3<f32>) -> vec2<f32> {
+    if zone.kind == DIRECTIONAL_ZONE {
+        let local = (zone.inverse_model * vec4<f32>(world_position, 1.)).xyz;
+        let border = zone.half_extents - abs(local);
+        return zone.wind * clamp(min(border.x, min(border.y, border.z)) / max(zone.falloff, 1e-4), 0., 1.);
+    }
+    let offset = world_position.xz - zone.center.xz;
+    let distance = length(offset);
+    var weight = 0.;
+    if zone.kind == RADIAL_ZONE && distance < zone.radius {
+        weight = pow(1. - distance / zone.radius, zone.falloff);
+    } else if zone.kind == RING_ZONE {
+        weight = max(1. - abs(distance - zone.radius) / max(zone.falloff, 1e-4), 0.);
+    }
+    if distance < 1e-4 {
+        return vec2<f32>(0.);
+    }
+    return offset / distance * zone.strength * weight;
+}
+// Returns the global wind plus the wind of all zones at the world position
+fn local_wind(world_position: vec3<f32>) -> vec2<f32> {
+    var wind = config.wind;
+    for (var i = 0u; i < wind_zones.count; i++) {
+        wind += zone_wind(wind_zones.zones[i], world_position);
+    }
+    return wind;
+}
+// Returns the offset of the blades rooted at the world position on the x,z plane
+fn wind_offset(world_position: vec3<f32>, time: f32) -> vec2<f32> {
+    let wind = local_wind(world_position);
+    // the noise moves in the direction of the global wind
+    let velocity = config.wind * config.wind_scroll_speed;
+    let texture_position = world_position.xz * config.wind_noise_scale - velocity * time;
+    var offset = wind_noise(texture_position) * wind;
 
-    // read just position in case of a over/under flow of tex. coords
-    texture_position = abs(texture_position % vec2<f32>(dim));
-    var texture_pixel = textureLoad(noise_texture, vec2<i32>(i32(texture_position.x),i32(texture_position.y)), 0);
-    return texture_pixel.xy * config.wind;
+    // the swirls move the blades in all directions
+    let swirl = wind_noise(texture_position * TURBULENCE_ZOOM) * 2. - 1.;
+    offset += swirl * config.turbulence * length(wind);
+
+    // the gusts travel over the grass with the noise
+    let travel = dot(texture_position, velocity) / max(dot(velocity, velocity), 1e-6);
+    let phase = -TAU * config.gust_frequency * travel;
+    let gust = config.gust_amplitude * (0.5 + 0.5 * sin(phase));
+    return offset * (1. + gust);
+}
+// Returns the offset of the blades rooted at the world position away from the displacers on the x,z plane
+fn displacement(world_position: vec3<f32>) -> vec2<f32> {
+    var offset = vec2<f32>(0.);
+    for (var i = 0u; i < displacers.count; i++) {
+        let displacer = displacers.displacers[i];
+        let distance = distance(world_position, displacer.position);
+        let away = world_position.xz - displacer.position.xz;
+        // blades right below the displacer have no direction to bend in
+        if distance >= displacer.radius || length(away) < 1e-4 {
+            continue;
+        }
+        let weight = pow(1. - distance / displacer.radius, displacer.falloff);
+        offset += normalize(away) * displacer.strength * weight;
+    }
+    return offset;
+}
+// Returns the offset of the blades rooted at the position in the chunk by the trails of the displacers
+//
+// Each texel stores the offset the blades were trampled to, the time they were trampled at and their recovery rate
+fn trample_offset(vertex_position: vec2<f32>, time: f32) -> vec2<f32> {
+#ifdef BATCHED
+    return vec2<f32>(0.);
+#else
+    let dim = vec2<i32>(textureDimensions(trample_texture, 0));
+    let texel = vec2<i32>(vertex_position / aabb.vect.xz * vec2<f32>(dim));
+    let trail = textureLoad(trample_texture, clamp(texel, vec2<i32>(0), dim - 1), 0);
+    return trail.xy * exp(-trail.w * max(time - trail.z, 0.));
+#endif
+}
+// Returns how strongly the wind moves a vertex at the height in the grass mesh
+fn wind_strength(vertex_height: f32) -> f32 {
+    return log(pow(max(vertex_height, 0.), config.sway_stiffness) + 1.);
 }
 const BIG_PRIME: f32 = 1302151.;
 
@@ -84,12 +361,89 @@ fn density_map_offset(vertex_position: vec2<f32>) -> vec2<f32> {
     var texture_pixel = textureLoad(noise_texture, vec2<i32>(i32(texture_position.x),i32(texture_position.y)), 0);
     return texture_pixel.xz - vec2<f32>(0.5,0.5) ;
 }
+// unsigned integer textures are loaded from 16 bit pngs
+const UINT_TEXTURE_MAX: f32 = 65535.;
+#ifdef BATCHED
+fn texture2d_offset(texture: texture_2d_array<f32>, vertex_position: vec2<f32>) -> vec3<f32> {
+    let dim = textureDimensions(texture, 0);
+    let texture_position = abs((vertex_position.xy / aabb.vect.xz ) * vec2<f32>(dim)) ;
+    var texture_rgb = textureLoad(texture, vec2<i32>(i32(texture_position.x),i32(texture_position.y)), map_layer, 0).rgb;
+    return texture_rgb;
+}
+fn texture2d_offset_uint(texture: texture_2d_array<u32>, vertex_position: vec2<f32>) -> vec3<f32> {
+    let dim = textureDimensions(texture, 0);
+    let texture_position = abs((vertex_position.xy / aabb.vect.xz ) * vec2<f32>(dim)) ;
+    var texture_rgb = textureLoad(texture, vec2<i32>(i32(texture_position.x),i32(texture_position.y)), map_layer, 0).rgb;
+    return vec3<f32>(texture_rgb) / UINT_TEXTURE_MAX;
+}
+fn texture2d_filtered(texture: texture_2d_array<f32>, texture_sampler: sampler, vertex_position: vec2<f32>) -> vec3<f32> {
+    let texture_position = abs(vertex_position.xy / aabb.vect.xz);
+    return textureSampleLevel(texture, texture_sampler, texture_position, map_layer, 0.).rgb;
+}
+#else
 fn texture2d_offset(texture: texture_2d<f32>, vertex_position: vec2<f32>) -> vec3<f32> {
     let dim = textureDimensions(texture, 0);
     let texture_position = abs((vertex_position.xy / aabb.vect.xz ) * vec2<f32>(dim)) ;
     var texture_rgb = textureLoad(texture, vec2<i32>(i32(texture_position.x),i32(texture_position.y)), 0).rgb;
     return texture_rgb;
 }
+fn texture2d_offset_uint(texture: texture_2d<u32>, vertex_position: vec2<f32>) -> vec3<f32> {
+    let dim = textureDimensions(texture, 0);
+    let texture_position = abs((vertex_position.xy / aabb.vect.xz ) * vec2<f32>(dim)) ;
+    var texture_rgb = textureLoad(texture, vec2<i32>(i32(texture_position.x),i32(texture_position.y)), 0).rgb;
+    return vec3<f32>(texture_rgb) / UINT_TEXTURE_MAX;
+}
+fn texture2d_filtered(texture: texture_2d<f32>, texture_sampler: sampler, vertex_position: vec2<f32>) -> vec3<f32> {
+    let texture_position = abs(vertex_position.xy / aabb.vect.xz);
+    return textureSampleLevel(texture, texture_sampler, texture_position, 0.).rgb;
+}
+#endif
+fn y_map_value(vertex_position: vec2<f32>) -> f32 {
+    #ifdef Y_MAP_UINT
+        return texture2d_offset_uint(y_texture, vertex_position).r;
+    #else ifdef Y_MAP_FILTERED
+        return texture2d_filtered(y_texture, y_sampler, vertex_position).r;
+    #else
+        return texture2d_offset(y_texture, vertex_position).r;
+    #endif
+}
+fn normal_map_value(vertex_position: vec2<f32>) -> vec3<f32> {
+    #ifdef NORMAL_MAP_UINT
+        return texture2d_offset_uint(t_normal, vertex_position);
+    #else ifdef NORMAL_MAP_FILTERED
+        return texture2d_filtered(t_normal, normal_sampler, vertex_position);
+    #else
+        return texture2d_offset(t_normal, vertex_position);
+    #endif
+}
+#ifdef HEIGHT_TEXTURE
+    fn height_texture_value(vertex_position: vec2<f32>) -> f32 {
+        #ifdef HEIGHT_TEXTURE_UINT
+            return texture2d_offset_uint(height_texture, vertex_position).r;
+        #else ifdef HEIGHT_TEXTURE_FILTERED
+            return texture2d_filtered(height_texture, height_sampler, vertex_position).r;
+        #else
+            return texture2d_offset(height_texture, vertex_position).r;
+        #endif
+    }
+#endif
+// Source: https://www.shadertoy.com/view/4djSRW
+// Returns a pseudo random value between 0 and 1 for the position
+fn hash(position: vec2<f32>) -> f32 {
+    var p3 = fract(vec3<f32>(position.xyx) * 0.1031);
+    p3 += dot(p3, p3.yzx + 33.33);
+    return fract((p3.x + p3.y) * p3.z);
+}
+// Returns a rotation matrix around the y axis
+fn rotate_y(angle: f32) -> mat3x3<f32> {
+    let c = cos(angle);
+    let s = sin(angle);
+    return mat3x3<f32>(
+        c, 0., -s,
+        0., 1., 0.,
+        s, 0., c,
+    );
+}
 // Source: https://gist.github.com/kevinmoran/b45980723e53edeb8a5a43c49f134724
 // Returns a rotation matrix that aligns v1 with v2
 fn rotate_align(v1: vec3<f32>, v2: vec3<f32>) -> mat3x3<f32> {
@@ -106,20 +460,69 @@ fn rotate_align(v1: vec3<f32>, v2: vec3<f32>) -> mat3x3<f32> {
 
     return result;
 }
+#ifdef DISTANCE_FADE
+    // Returns the factor the blade is scaled with at the distance to the view.
+    // The rank of a blade is the fraction of the blades of the chunk ordered before it,
+    // so a level of detail with a lower density drops the blade
+    fn distance_fade(distance: f32, rank: f32) -> f32 {
+        var level_distance = -1.;
+        var visible = true;
+        // the closest distance at which a level drops the blade
+        var drop_distance = blade.fade.y;
+        for (var i = 0; i < 4; i++) {
+            let lod_distance = blade.lod_distances[i];
+            let lod_visible = rank < blade.lod_densities[i];
+            if lod_distance <= distance {
+                if lod_distance >= level_distance {
+                    level_distance = lod_distance;
+                    visible = lod_visible;
+                }
+            } else if !lod_visible {
+                drop_distance = min(drop_distance, lod_distance);
+            }
+        }
+        if !visible {
+            return 0.;
+        }
+        // the blade shrinks before it is dropped
+        let lod_fade = (drop_distance - distance) / max(blade.lod_transition, 0.0001);
+        let fade = (blade.fade.y - distance) / max(blade.fade.y - blade.fade.x, 0.0001);
+        return clamp(min(lod_fade, fade), 0., 1.);
+    }
+#endif
 @vertex
 fn vertex(vertex: Vertex, @builtin(instance_index) instance_index: u32) -> VertexOutput {
     var out: VertexOutput;
 
+    #ifdef BATCHED
+        let chunk_index = chunk_index(instance_index);
+        let chunk = chunks[chunk_index];
+        mesh = chunk.mesh;
+        color = chunk.color;
+        aabb = chunk.aabb;
+        blade = chunk.blade;
+        map_layer = i32(chunk_index);
+        let blade_index = instance_index - chunk.first_instance;
+    #else
+        let blade_index = instance_index;
+    #endif
+
     var position_field_offset = vec3<f32>(vertex.xz_position.x, 0., vertex.xz_position.y);
 
-    let density_offset = density_map_offset(position_field_offset.xz) / 1.;
+    #ifdef WORLD_SPACE_DITHERING
+        // the jitter continues seamlessly over the borders of the chunks
+        let jitter_position = (mesh.model * vec4<f32>(position_field_offset, 1.)).xz;
+    #else
+        let jitter_position = position_field_offset.xz;
+    #endif
+    let density_offset = density_map_offset(jitter_position) / 1.;
     position_field_offset += vec3<f32>(density_offset.x, 0., density_offset.y);
 
     // ---Y_POSITIONS---
-    position_field_offset.y = texture2d_offset(y_texture, position_field_offset.xz).r * aabb.vect.y;
+    position_field_offset.y = y_map_value(position_field_offset.xz) * aabb.vect.y;
     
     // ---NORMAL---
-    var normal = sqrt(texture2d_offset(t_normal, vertex.xz_position.xy).xyz); // Get normal scaled over grass field in linear space
+    var normal = sqrt(normal_map_value(vertex.xz_position.xy)); // Get normal scaled over grass field in linear space
     normal = normal * 2. - vec3f(1.);
     normal = normalize(normal);
     let rotation_matrix = rotate_align(vec3<f32>(0.0, 1.0, 0.0), normal); // Calculate rotation matrix to align grass with normal
@@ -127,30 +530,259 @@ fn vertex(vertex: Vertex, @builtin(instance_index) instance_index: u32) -> Verte
     // ---HEIGHT---
     var height = 0.;
     #ifdef HEIGHT_TEXTURE
-        height = (texture2d_offset(height_texture, position_field_offset.xz).r + 4.) / 3.;
+        height = mix(blade.height_range.x, blade.height_range.y, height_texture_value(position_field_offset.xz));
     #else
-        height = height_uniform.height;
+        height = blade.height;
+    #endif
+    // ---YAW---
+    // the rotation is derived from the position of the blade, so it doesn't change over time
+    let yaw = (hash(jitter_position) - 0.5) * config.yaw_variation;
+    let yaw_matrix = rotate_y(yaw);
+
+    // ---SIZE VARIATION---
+    // different offsets decorrelate the random values
+    height *= mix(blade.height_variation.x, blade.height_variation.y, hash(jitter_position + vec2<f32>(17.3, 5.1)));
+    let width = mix(blade.width_variation.x, blade.width_variation.y, hash(jitter_position + vec2<f32>(3.7, 29.9)));
+
+    var size = vec3<f32>(width, height, width);
+
+    let world_root = (mesh.model * vec4<f32>(position_field_offset, 1.)).xyz;
+
+    // ---DISTANCE_FADE---
+    #ifdef DISTANCE_FADE
+        let blade_distance = distance(world_root, view.world_position.xyz);
+        #ifdef GPU_CULLING
+            let rank = vertex.rank;
+        #else
+            let rank = f32(blade_index) / blade.blade_count;
+        #endif
+        size *= distance_fade(blade_distance, rank);
     #endif
-    var position = rotation_matrix * (vertex.vertex_position * vec3<f32>(1., height, 1.)) + position_field_offset;
+
+    var position = rotation_matrix * (yaw_matrix * (vertex.vertex_position * size)) + position_field_offset;
 
     // ---WIND---
+    // the wind is sampled in world space, so the gusts move on over the borders of the chunks
     // only applies wind if the vertex is not on the bottom of the grass (or very small)
-    let offset = wind_offset(position_field_offset.xz);
-    let strength = max(0.,log(vertex.vertex_position.y + 1.));
-    position.x += offset.x * strength;
-    position.z += offset.y * strength;
+    let strength = wind_strength(vertex.vertex_position.y);
+    // the displacers bend the blades like the wind, the trails they left keep the blades bent
+    let live = displacement(world_root);
+    let trail = trample_offset(position_field_offset.xz, globals.time);
+    // the displacement is scaled to move the tips of the blades by its full length
+    let displaced = select(live, trail, length(trail) > length(live)) / log(2.);
+    let offset = (wind_offset(world_root, globals.time) + displaced) * strength;
     
     // ---CLIP_POSITION---
-    out.clip_position = mesh_position_local_to_clip(mesh.model, vec4<f32>(position, 1.0));
+    let world_position = mesh.model * vec4<f32>(position, 1.0) + vec4<f32>(offset.x, 0., offset.y, 0.);
+    out.clip_position = view.view_proj * world_position;
+
+    #ifdef PREPASS
+        #ifdef DEPTH_CLAMP_ORTHO
+            out.clip_position_unclamped = out.clip_position;
+            out.clip_position.z = min(out.clip_position.z, 1.0);
+        #endif
+        #ifdef MOTION_VECTOR_PREPASS
+            // the blades also move with the wind since the last frame, the displacers of the last frame aren't known
+            let previous_root = (mesh.previous_model * vec4<f32>(position_field_offset, 1.)).xyz;
+            let previous_offset = (wind_offset(previous_root, globals.time - globals.delta_time) + displaced) * strength;
+            out.previous_world_position = mesh.previous_model * vec4<f32>(position, 1.0) + vec4<f32>(previous_offset.x, 0., previous_offset.y, 0.);
+        #endif
+        #ifdef PREPASS_FRAGMENT
+            out.world_position = world_position;
+            out.ground_normal = ground_normal_to_world(normal);
+        #endif
+    #else
+        #ifdef LIT
+            out.world_position = world_position;
+            out.ground_normal = ground_normal_to_world(normal);
+        #endif
+        #ifdef BATCHED
+            out.chunk_index = chunk_index;
+        #endif
 
-    // ---COLOR---
-    let lambda = clamp(vertex.vertex_position.y, 0., 1.);
-    out.color = mix(color.bottom_color, color.main_color, lambda);
+        // ---COLOR---
+        let lambda = clamp(vertex.vertex_position.y, 0., 1.);
+        out.color = mix(color.bottom_color, color.main_color, lambda);
+    #endif
     return out;
 }
 
+fn ground_normal_to_world(normal: vec3<f32>) -> vec3<f32> {
+    return normalize(mat3x3<f32>(
+        mesh.inverse_transpose_model[0].xyz,
+        mesh.inverse_transpose_model[1].xyz,
+        mesh.inverse_transpose_model[2].xyz
+    ) * normal);
+}
+// How much the normal of the ground is blended into the normal of the blade faces,
+// which keeps the shading of the thin blades calm from a distance
+const GROUND_NORMAL_BLEND: f32 = 0.5;
 
-@fragment
-fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
-    return in.color;
+// Returns the normalized vector from the fragment to the view
+fn view_vector(world_position: vec4<f32>, is_orthographic: bool) -> vec3<f32> {
+    if is_orthographic {
+        return normalize(vec3<f32>(view.view_proj[0].z, view.view_proj[1].z, view.view_proj[2].z));
+    }
+    return normalize(view.world_position.xyz - world_position.xyz);
+}
+// Returns the normal of the blade face facing the view, derived from its orientation after all displacements.
+// Can only be used in the fragment shader
+fn blade_normal(world_position: vec4<f32>, ground_normal: vec3<f32>, V: vec3<f32>) -> vec3<f32> {
+    // the glsl backend also writes the function into the vertex shader, which doesn't support derivatives
+    #ifdef FRAGMENT_STAGE
+        var face_normal = normalize(cross(dpdx(world_position.xyz), dpdy(world_position.xyz)));
+        if dot(face_normal, V) < 0. {
+            face_normal = -face_normal;
+        }
+        return normalize(mix(face_normal, ground_normal, GROUND_NORMAL_BLEND));
+    #else
+        return ground_normal;
+    #endif
+}
+
+
+#ifdef LIT
+// Applies the fog of the view, like the `StandardMaterial` does
+fn apply_fog(fog_params: mesh_view_types::Fog, input_color: vec4<f32>, world_position: vec3<f32>) -> vec4<f32> {
+    let view_to_world = world_position - view_bindings::view.world_position.xyz;
+    let distance = length(view_to_world);
+
+    var scattering = vec3<f32>(0.0);
+    if fog_params.directional_light_color.a > 0.0 {
+        let view_to_world_normalized = view_to_world / distance;
+        for (var i: u32 = 0u; i < view_bindings::lights.n_directional_lights; i = i + 1u) {
+            let light = view_bindings::lights.directional_lights[i];
+            scattering += pow(max(dot(view_to_world_normalized, light.direction_to_light), 0.0), fog_params.directional_light_exponent) * light.color.rgb;
+        }
+    }
+
+    if fog_params.mode == mesh_view_types::FOG_MODE_LINEAR {
+        return fog::linear_fog(fog_params, input_color, distance, scattering);
+    } else if fog_params.mode == mesh_view_types::FOG_MODE_EXPONENTIAL {
+        return fog::exponential_fog(fog_params, input_color, distance, scattering);
+    } else if fog_params.mode == mesh_view_types::FOG_MODE_EXPONENTIAL_SQUARED {
+        return fog::exponential_squared_fog(fog_params, input_color, distance, scattering);
+    } else if fog_params.mode == mesh_view_types::FOG_MODE_ATMOSPHERIC {
+        return fog::atmospheric_fog(fog_params, input_color, distance, scattering);
+    }
+    return input_color;
 }
+// Lights the blade with the lights and shadows of the view, using the lighting functions of the `StandardMaterial`
+fn lit_color(in: VertexOutput) -> vec4<f32> {
+    let is_orthographic = view.projection[3].w == 1.0;
+    let V = view_vector(in.world_position, is_orthographic);
+    let N = blade_normal(in.world_position, in.ground_normal, V);
+
+    let perceptual_roughness = color.perceptual_roughness;
+    let roughness = lighting::perceptualRoughnessToRoughness(perceptual_roughness);
+    let NdotV = max(dot(N, V), 0.0001);
+    let F0 = vec3<f32>(0.16 * color.reflectance * color.reflectance);
+    let diffuse_color = in.color.rgb;
+    let R = reflect(-V, N);
+    let f_ab = lighting::F_AB(perceptual_roughness, NdotV);
+
+    var direct_light = vec3<f32>(0.0);
+
+    let view_z = dot(vec4<f32>(
+        view_bindings::view.inverse_view[0].z,
+        view_bindings::view.inverse_view[1].z,
+        view_bindings::view.inverse_view[2].z,
+        view_bindings::view.inverse_view[3].z
+    ), in.world_position);
+    let cluster_index = clustering::fragment_cluster_index(in.clip_position.xy, view_z, is_orthographic);
+    let offset_and_counts = clustering::unpack_offset_and_counts(cluster_index);
+
+    // chunks with a `NotShadowReceiver` component don't sample the shadow maps
+    let receives_shadows = (mesh.flags & MESH_FLAGS_SHADOW_RECEIVER_BIT) != 0u;
+
+    // point lights
+    for (var i: u32 = offset_and_counts[0]; i < offset_and_counts[0] + offset_and_counts[1]; i = i + 1u) {
+        let light_id = clustering::get_light_id(i);
+        var shadow: f32 = 1.0;
+        if receives_shadows && (view_bindings::point_lights.data[light_id].flags & mesh_view_types::POINT_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u {
+            shadow = shadows::fetch_point_shadow(light_id, in.world_position, N);
+        }
+        direct_light += lighting::point_light(in.world_position.xyz, light_id, roughness, NdotV, N, V, R, F0, f_ab, diffuse_color) * shadow;
+    }
+    // spot lights
+    for (var i: u32 = offset_and_counts[0] + offset_and_counts[1]; i < offset_and_counts[0] + offset_and_counts[1] + offset_and_counts[2]; i = i + 1u) {
+        let light_id = clustering::get_light_id(i);
+        var shadow: f32 = 1.0;
+        if receives_shadows && (view_bindings::point_lights.data[light_id].flags & mesh_view_types::POINT_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u {
+            shadow = shadows::fetch_spot_shadow(light_id, in.world_position, N);
+        }
+        direct_light += lighting::spot_light(in.world_position.xyz, light_id, roughness, NdotV, N, V, R, F0, f_ab, diffuse_color) * shadow;
+    }
+    // directional lights, using the cascaded shadow maps
+    for (var i: u32 = 0u; i < view_bindings::lights.n_directional_lights; i = i + 1u) {
+        var shadow: f32 = 1.0;
+        if receives_shadows && (view_bindings::lights.directional_lights[i].flags & mesh_view_types::DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u {
+            shadow = shadows::fetch_directional_shadow(i, in.world_position, N, view_z);
+        }
+        var light_contrib = lighting::directional_light(i, roughness, NdotV, N, V, R, F0, f_ab, diffuse_color);
+        #ifdef DIRECTIONAL_LIGHT_SHADOW_MAP_DEBUG_CASCADES
+            light_contrib = shadows::cascade_debug_visualization(light_contrib, i, view_z);
+        #endif
+        direct_light += light_contrib * shadow;
+    }
+
+    let indirect_light = ambient::ambient_light(in.world_position, N, V, NdotV, diffuse_color, F0, perceptual_roughness, vec3<f32>(1.0));
+
+    var output_color = vec4<f32>(direct_light + indirect_light, in.color.a);
+    if view_bindings::fog.mode != mesh_view_types::FOG_MODE_OFF {
+        output_color = apply_fog(view_bindings::fog, output_color, in.world_position.xyz);
+    }
+    return output_color;
+}
+#endif
+
+#ifdef PREPASS
+    #ifdef PREPASS_FRAGMENT
+        struct FragmentOutput {
+            #ifdef NORMAL_PREPASS
+                @location(0) normal: vec4<f32>,
+            #endif
+            #ifdef MOTION_VECTOR_PREPASS
+                @location(1) motion_vector: vec2<f32>,
+            #endif
+            #ifdef DEPTH_CLAMP_ORTHO
+                @builtin(frag_depth) frag_depth: f32,
+            #endif
+        }
+        // Writes the prepass textures like the prepass of the `StandardMaterial`
+        @fragment
+        fn fragment(in: VertexOutput) -> FragmentOutput {
+            var out: FragmentOutput;
+            #ifdef NORMAL_PREPASS
+                let is_orthographic = view.projection[3].w == 1.0;
+                let N = blade_normal(in.world_position, in.ground_normal, view_vector(in.world_position, is_orthographic));
+                out.normal = vec4<f32>(N * 0.5 + vec3<f32>(0.5), 1.0);
+            #endif
+            #ifdef DEPTH_CLAMP_ORTHO
+                out.frag_depth = in.clip_position_unclamped.z;
+            #endif
+            #ifdef MOTION_VECTOR_PREPASS
+                let clip_position_t = view.unjittered_view_proj * in.world_position;
+                let clip_position = clip_position_t.xy / clip_position_t.w;
+                let previous_clip_position_t = previous_view_proj * in.previous_world_position;
+                let previous_clip_position = previous_clip_position_t.xy / previous_clip_position_t.w;
+                out.motion_vector = (clip_position - previous_clip_position) * vec2<f32>(0.5, -0.5);
+            #endif
+            return out;
+        }
+    #endif
+#else
+    @fragment
+    fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+        #ifdef LIT
+            #ifdef BATCHED
+                let chunk = chunks[in.chunk_index];
+                mesh = chunk.mesh;
+                color = chunk.color;
+            #endif
+            return lit_color(in);
+        #else
+            return in.color;
+        #endif
+    }
+#endif
@@ -0,0 +1,799 @@
+use std::mem;
+
+use bevy::{
+    asset::HandleId,
+    core_pipeline::core_3d::Opaque3d,
+    ecs::system::{
+        lifetimeless::{Read, SRes},
+        SystemParamItem,
+    },
+    pbr::{MeshPipelineKey, MeshUniform},
+    prelude::*,
+    render::{
+        mesh::GpuBufferInfo,
+        primitives::Aabb,
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraphContext},
+        render_phase::{
+            PhaseItem, RenderCommand, RenderCommandResult, RenderPhase, TrackedRenderPass,
+        },
+        render_resource::{
+            BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, Buffer,
+            BufferDescriptor, BufferId, BufferInitDescriptor, BufferUsages, Extent3d,
+            ImageCopyTexture, Origin3d, Texture, TextureAspect, TextureDescriptor,
+            TextureDimension, TextureFormat, TextureId, TextureUsages, TextureViewDescriptor,
+            TextureViewDimension,
+        },
+        renderer::{RenderAdapterInfo, RenderContext, RenderDevice, RenderQueue},
+        settings::{Backends, WgpuFeatures},
+        Extract,
+    },
+    utils::{HashMap, HashSet},
+};
+use bytemuck::{Pod, Zeroable};
+
+use crate::{
+    bundle::{BladeSizeVariation, GrassColor, GrassFade, GrassLighting, GrassLod, WarblerHeight},
+    dithering::{DitheredBuffer, GpuDitheredBuffers},
+    map::{NormalMap, YMap},
+};
+
+use super::{
+    culling::GrassCulling,
+    displacement::{GpuGrassDisplacers, GpuTrampleMaps},
+    grass_pipeline::{GrassPipeline, GrassRenderKey, MapLayouts, TextureBinding},
+    lod::GrassLods,
+    prepare::{BindGroupBuffer, ShaderAabb, ShaderBladeUniform, ShaderColorUniform},
+    queue::GrassKeyQuery,
+};
+
+/// The size of a blade position in the instance buffers
+const BLADE_SIZE: u64 = mem::size_of::<Vec2>() as u64;
+/// The binding of the sampler of filtered maps in the y-map, height and normal map bind groups,
+/// which is the same as in the bind groups of single chunks
+const MAP_SAMPLER_BINDINGS: [u32; 3] = [3, 2, 1];
+
+/// Returns true if the gpu supports the storage buffers containing the parameters of batched chunks
+///
+/// The shader looks up the chunk of a blade by its instance index, which doesn't include the first instance
+/// of a draw on the OpenGL backends
+pub(crate) fn batching_supported(
+    render_device: &RenderDevice,
+    adapter_info: &RenderAdapterInfo,
+) -> bool {
+    render_device.limits().max_storage_buffers_per_shader_stage > 0
+        && Backends::from(adapter_info.backend) != Backends::GL
+}
+
+/// Returns true if all draws of a batch can be issued with one indirect multi-draw
+fn multi_draw_supported(render_device: &RenderDevice) -> bool {
+    render_device
+        .features()
+        .contains(WgpuFeatures::MULTI_DRAW_INDIRECT | WgpuFeatures::INDIRECT_FIRST_INSTANCE)
+}
+
+/// The properties chunks need to share to be drawn in the same batch
+#[derive(PartialEq, Eq, Hash, Clone)]
+struct BatchKey {
+    mesh: Handle<Mesh>,
+    /// The key of the pipeline without the view and mesh specific parts
+    grass_key: GrassRenderKey,
+    /// The format and size of the y-map, height texture and normal map,
+    /// as the maps of all chunks are copied into the layers of one texture array per map
+    maps: [Option<(TextureFormat, UVec2)>; 3],
+    /// Batches with more chunks than layers fit into a texture array are split into several parts
+    part: usize,
+}
+
+/// The texture array containing a map of each chunk of a [`GrassBatch`]
+struct MapArray {
+    texture: Texture,
+    size: UVec2,
+    bind_group: BindGroup,
+}
+impl MapArray {
+    fn new(
+        render_device: &RenderDevice,
+        pipeline: &GrassPipeline,
+        layouts: &MapLayouts,
+        binding: TextureBinding,
+        (format, size): (TextureFormat, UVec2),
+        sampler_binding: u32,
+        layers: usize,
+    ) -> Self {
+        let texture = render_device.create_texture(&TextureDescriptor {
+            label: Some("grass batch map texture"),
+            size: Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: layers as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..default()
+        });
+        let mut entries = vec![BindGroupEntry {
+            binding: 0,
+            resource: BindingResource::TextureView(&view),
+        }];
+        if binding == TextureBinding::Filtered {
+            entries.push(BindGroupEntry {
+                binding: sampler_binding,
+                resource: BindingResource::Sampler(&pipeline.map_sampler),
+            });
+        }
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("grass batch map bind group"),
+            layout: layouts.get(binding),
+            entries: &entries,
+        });
+        MapArray {
+            texture,
+            size,
+            bind_group,
+        }
+    }
+}
+
+/// The grass chunks drawn by a single draw command in the main pass
+pub(crate) struct GrassBatch {
+    /// The chunks of the batch, whose parameters are stored at the same index in the `chunk_buffer`
+    chunks: Vec<Entity>,
+    /// The index of the first blade of each chunk in the `instances`
+    first_instances: Vec<u32>,
+    /// The amount of blades of each chunk
+    blade_counts: Vec<u32>,
+    /// The positions of the blades of all chunks, one chunk after another
+    instances: Buffer,
+    /// The amount of blades fitting into the `instances`
+    capacity: u64,
+    /// The dithered buffers and their generation currently copied into the `instances`
+    sources: Vec<(BufferId, u64)>,
+    /// The copies into the `instances` which are done in this frame
+    copies: Vec<(Buffer, u64, u64)>,
+    /// The [`ShaderGrassChunk`] of each chunk
+    chunk_buffer: Buffer,
+    /// The amount of chunks fitting into the `chunk_buffer` and the layers of the `maps`
+    chunk_capacity: usize,
+    bind_group: BindGroup,
+    /// The y-map, height texture and normal map of the chunks, each chunk in the layer at its index.
+    ///
+    /// Batches with a uniform height have no height texture
+    maps: [Option<MapArray>; 3],
+    /// The map textures currently copied into each layer of the `maps`
+    map_sources: Vec<[Option<TextureId>; 3]>,
+    /// The copies of map textures into a layer of the `maps` which are done in this frame
+    map_copies: Vec<(usize, Texture, u32)>,
+}
+impl GrassBatch {
+    fn new(
+        render_device: &RenderDevice,
+        pipeline: &GrassPipeline,
+        key: &BatchKey,
+        capacity: u64,
+        chunk_capacity: usize,
+    ) -> Self {
+        let instances = render_device.create_buffer(&BufferDescriptor {
+            label: Some("grass batch instance buffer"),
+            size: capacity.max(1) * BLADE_SIZE,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let chunk_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("grass batch chunk buffer"),
+            size: (chunk_capacity * mem::size_of::<ShaderGrassChunk>()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // only called if batching is supported
+        let layouts = pipeline.batch_layouts.as_ref().unwrap();
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("grass batch bind group"),
+            layout: &layouts.chunks,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: chunk_buffer.as_entire_binding(),
+            }],
+        });
+        let grass_key = &key.grass_key;
+        let map_layouts = [
+            (&layouts.y_map, grass_key.y_map),
+            (&layouts.heights_texture, grass_key.height),
+            (&layouts.normal_map, grass_key.normal_map),
+        ];
+        let mut maps = [None, None, None];
+        for (map, ((layouts, binding), format)) in map_layouts.into_iter().zip(key.maps).enumerate()
+        {
+            maps[map] = format.map(|format| {
+                MapArray::new(
+                    render_device,
+                    pipeline,
+                    layouts,
+                    binding,
+                    format,
+                    MAP_SAMPLER_BINDINGS[map],
+                    chunk_capacity,
+                )
+            });
+        }
+        GrassBatch {
+            chunks: Vec::new(),
+            first_instances: Vec::new(),
+            blade_counts: Vec::new(),
+            instances,
+            capacity,
+            sources: Vec::new(),
+            copies: Vec::new(),
+            chunk_buffer,
+            chunk_capacity,
+            bind_group,
+            maps,
+            map_sources: Vec::new(),
+            map_copies: Vec::new(),
+        }
+    }
+}
+
+/// The draws of a [`GrassBatch`] in a view
+pub(crate) struct BatchDraw {
+    key: BatchKey,
+    /// The first instance and instance count of each chunk drawn in the view
+    instances: Vec<(u32, u32)>,
+    /// The arguments of the indirect multi-draw, if supported by the gpu
+    draw_args: Option<Buffer>,
+}
+
+/// The batches of grass chunks drawn in the main pass of each camera
+///
+/// Chunks sharing a mesh, a pipeline and the format and size of their maps are put into one [`GrassBatch`].
+/// Chunks whose maps aren't loaded yet are drawn one by one.
+/// A chunk is drawn on its own in views where it is culled on the gpu or uses the mesh of a level of detail.
+#[derive(Resource, Default)]
+pub(crate) struct GrassBatches {
+    batches: HashMap<BatchKey, GrassBatch>,
+    /// The draws of each view, keyed by the first chunk of the batch
+    draws: HashMap<Entity, HashMap<Entity, BatchDraw>>,
+    /// The buffers of the indirect draw arguments of each batch in each view, which are reused across frames
+    draw_args: HashMap<(Entity, BatchKey), Buffer>,
+    /// The chunks drawn by a batch in each view
+    batched: HashMap<Entity, HashSet<Entity>>,
+}
+impl GrassBatches {
+    /// Returns the batch drawn in the `view` with the `chunk` as its first chunk
+    fn get(&self, view: Entity, chunk: Entity) -> Option<(&GrassBatch, &BatchDraw)> {
+        let draw = self.draws.get(&view)?.get(&chunk)?;
+        let batch = self.batches.get(&draw.key)?;
+        Some((batch, draw))
+    }
+    /// Returns true if the `chunk` is drawn by a batch in the `view`
+    pub(crate) fn contains(&self, view: Entity, chunk: Entity) -> bool {
+        self.batched
+            .get(&view)
+            .is_some_and(|chunks| chunks.contains(&chunk))
+    }
+    /// Returns the first chunk, mesh and pipeline key of each batch drawn in the `view`
+    pub(crate) fn view_batches(
+        &self,
+        view: Entity,
+    ) -> impl Iterator<Item = (Entity, &Handle<Mesh>, &GrassRenderKey)> {
+        self.draws
+            .get(&view)
+            .into_iter()
+            .flatten()
+            .map(|(chunk, draw)| (*chunk, &draw.key.mesh, &draw.key.grass_key))
+    }
+}
+
+/// Groups the grass chunks into [`GrassBatch`]es and prepares their draws in each camera
+///
+/// Runs in the queue set, as the pipeline keys of the chunks are known after the prepare set
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub(crate) fn batch_grass_chunks(
+    mut batches: ResMut<GrassBatches>,
+    pipeline: Res<GrassPipeline>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    meshes: Res<RenderAssets<Mesh>>,
+    dithered: Res<GpuDitheredBuffers>,
+    lods: Res<GrassLods>,
+    culling: Res<GrassCulling>,
+    displacers: Res<GpuGrassDisplacers>,
+    trample_maps: Res<GpuTrampleMaps>,
+    images: Res<RenderAssets<Image>>,
+    copyable: Res<CopyableImages>,
+    chunks: Query<(
+        Entity,
+        &Handle<Mesh>,
+        &Handle<DitheredBuffer>,
+        (&MeshUniform, &Aabb, &YMap, &NormalMap),
+        (&GrassColor, Option<&GrassLighting>),
+        (
+            &WarblerHeight,
+            Option<&BladeSizeVariation>,
+            Option<&GrassFade>,
+            Option<&GrassLod>,
+        ),
+        GrassKeyQuery,
+    )>,
+    views: Query<Entity, With<RenderPhase<Opaque3d>>>,
+) {
+    let batches = batches.as_mut();
+    batches.draws.clear();
+    batches.batched.clear();
+    let mut previous = mem::take(&mut batches.batches);
+
+    let mut groups: HashMap<BatchKey, Vec<_>> = HashMap::new();
+    for chunk in &chunks {
+        let (entity, mesh, dither_handle, (.., y_map, normal_map), _, (height, ..), grass) = &chunk;
+        // trampled chunks have their own trample texture next to the y-map
+        if trample_maps.contains(*entity) {
+            continue;
+        }
+        let Some(gpu_dither) = dithered.get(*dither_handle) else {
+            continue;
+        };
+        let height_texture = match height {
+            WarblerHeight::Texture { texture, .. } => Some(texture),
+            WarblerHeight::Uniform(_) => None,
+        };
+        let handles = [
+            Some(&y_map.y_map),
+            height_texture,
+            Some(&normal_map.normal_map),
+        ];
+        let mut maps = [None, None, None];
+        // the maps are copied into the texture arrays of the batch, which needs them to be loaded
+        let loaded = handles.into_iter().enumerate().all(|(map, handle)| {
+            let Some(handle) = handle else {
+                return true;
+            };
+            let Some(image) = images
+                .get(handle)
+                .filter(|_| copyable.0.contains(&handle.id()))
+            else {
+                return false;
+            };
+            maps[map] = Some(image);
+            true
+        });
+        if !loaded {
+            continue;
+        }
+        let key = BatchKey {
+            mesh: (*mesh).clone_weak(),
+            grass_key: grass.key(MeshPipelineKey::NONE),
+            maps: maps
+                .map(|image| image.map(|image| (image.texture_format, image.size.as_uvec2()))),
+            part: 0,
+        };
+        groups
+            .entry(key)
+            .or_default()
+            .push((*entity, gpu_dither, maps, chunk));
+    }
+
+    let max_buffer_size = render_device.limits().max_buffer_size;
+    let max_layers = render_device.limits().max_texture_array_layers as usize;
+    let multi_draw = multi_draw_supported(&render_device);
+    for (key, mut group) in groups {
+        group.sort_by_key(|(entity, ..)| *entity);
+        for (part, group) in group.chunks(max_layers).enumerate() {
+            // a single chunk gains nothing from being batched
+            if group.len() < 2 {
+                continue;
+            }
+            let key = BatchKey {
+                part,
+                ..key.clone()
+            };
+            let blades: u64 = group
+                .iter()
+                .map(|(_, gpu_dither, ..)| gpu_dither.instances as u64)
+                .sum();
+            // batches too big for a single buffer are drawn chunk by chunk
+            if blades * BLADE_SIZE > max_buffer_size || blades > u32::MAX as u64 {
+                continue;
+            }
+            let mut batch = match previous.remove(&key) {
+                Some(batch) if batch.capacity >= blades && batch.chunk_capacity >= group.len() => {
+                    batch
+                }
+                _ => GrassBatch::new(&render_device, &pipeline, &key, blades, group.len()),
+            };
+            batch.chunks.clear();
+            batch.first_instances.clear();
+            batch.blade_counts.clear();
+            batch.copies.clear();
+            batch.map_copies.clear();
+
+            let sources: Vec<_> = group
+                .iter()
+                .map(|(_, gpu_dither, ..)| (gpu_dither.buffer.id(), gpu_dither.generation))
+                .collect();
+            // the blades only need to be copied again if a chunk was redithered or the batch changed
+            let copy = sources != batch.sources;
+            batch.map_sources.resize(group.len(), [None; 3]);
+            let mut shader_chunks = Vec::with_capacity(group.len());
+            let mut first_instance = 0;
+            for (layer, (entity, gpu_dither, maps, chunk)) in group.iter().enumerate() {
+                let (
+                    _,
+                    _,
+                    _,
+                    (mesh_uniform, aabb, ..),
+                    (color, lighting),
+                    (height, variation, fade, lod),
+                    _,
+                ) = chunk;
+                let blade_count = gpu_dither.instances as u32;
+                if copy && blade_count > 0 {
+                    batch.copies.push((
+                        gpu_dither.buffer.clone(),
+                        first_instance as u64 * BLADE_SIZE,
+                        blade_count as u64 * BLADE_SIZE,
+                    ));
+                }
+                // the maps only need to be copied again if the layer shows another texture,
+                // which includes maps whose texture was created again after being modified
+                let map_sources = maps.map(|image| image.map(|image| image.texture.id()));
+                if batch.map_sources[layer] != map_sources {
+                    for (map, image) in maps.iter().enumerate() {
+                        if let Some(image) = image {
+                            batch
+                                .map_copies
+                                .push((map, image.texture.clone(), layer as u32));
+                        }
+                    }
+                    batch.map_sources[layer] = map_sources;
+                }
+                let mut blade =
+                    ShaderBladeUniform::new(height, variation.copied().unwrap_or_default());
+                if let Some(fade) = fade {
+                    blade = blade.with_fade(fade);
+                }
+                if let Some(lod) = lod {
+                    blade = blade.with_lod(lod, gpu_dither.instances);
+                }
+                shader_chunks.push(ShaderGrassChunk {
+                    mesh: ShaderMesh::from(*mesh_uniform),
+                    color: ShaderColorUniform::new(color, lighting.copied().unwrap_or_default()),
+                    blade,
+                    aabb: ShaderAabb::from(Vec3::from(aabb.half_extents * 2.)),
+                    first_instance,
+                    _wasm_padding: [0; 3],
+                });
+                batch.chunks.push(*entity);
+                batch.first_instances.push(first_instance);
+                batch.blade_counts.push(blade_count);
+                first_instance += blade_count;
+            }
+            batch.sources = sources;
+            render_queue.write_buffer(&batch.chunk_buffer, 0, bytemuck::cast_slice(&shader_chunks));
+            batches.batches.insert(key, batch);
+        }
+    }
+
+    let mut previous_draw_args = mem::take(&mut batches.draw_args);
+    for view in &views {
+        let mut view_draws = HashMap::new();
+        let mut view_batched = HashSet::new();
+        for (key, batch) in &batches.batches {
+            let Some(gpu_mesh) = meshes.get(&key.mesh) else {
+                continue;
+            };
+            let mut instances = Vec::new();
+            let mut first_chunk = None;
+            for ((chunk, first_instance), blades) in batch
+                .chunks
+                .iter()
+                .zip(&batch.first_instances)
+                .zip(&batch.blade_counts)
+            {
+                // chunks using the mesh of a level of detail, being culled or displaced are drawn on their own
+                if culling.get(view, *chunk).is_some()
+                    || lods.mesh(view, *chunk, &key.mesh) != Some(&key.mesh)
+                    || displacers.contains(*chunk)
+                {
+                    continue;
+                }
+                view_batched.insert(*chunk);
+                first_chunk.get_or_insert(*chunk);
+                let count = lods.blade_count(view, *chunk, *blades);
+                if count > 0 {
+                    instances.push((*first_instance, count));
+                }
+            }
+            let Some(first_chunk) = first_chunk.filter(|_| !instances.is_empty()) else {
+                continue;
+            };
+            let draw_args = multi_draw.then(|| {
+                let args: Vec<u32> = match &gpu_mesh.buffer_info {
+                    GpuBufferInfo::Indexed { count, .. } => instances
+                        .iter()
+                        .flat_map(|(first, instances)| [*count, *instances, 0, 0, *first])
+                        .collect(),
+                    GpuBufferInfo::NonIndexed => instances
+                        .iter()
+                        .flat_map(|(first, instances)| {
+                            [gpu_mesh.vertex_count, *instances, 0, *first]
+                        })
+                        .collect(),
+                };
+                let contents: &[u8] = bytemuck::cast_slice(&args);
+                let buffer = match previous_draw_args.remove(&(view, key.clone())) {
+                    Some(buffer) if buffer.size() >= contents.len() as u64 => {
+                        render_queue.write_buffer(&buffer, 0, contents);
+                        buffer
+                    }
+                    _ => render_device.create_buffer_with_data(&BufferInitDescriptor {
+                        label: Some("grass batch draw args buffer"),
+                        contents,
+                        usage: BufferUsages::INDIRECT | BufferUsages::COPY_DST,
+                    }),
+                };
+                batches
+                    .draw_args
+                    .insert((view, key.clone()), buffer.clone());
+                buffer
+            });
+            // the batch is drawn with the entity of a chunk in it, which has no displacers
+            view_draws.insert(
+                first_chunk,
+                BatchDraw {
+                    key: key.clone(),
+                    instances,
+                    draw_args,
+                },
+            );
+        }
+        batches.draws.insert(view, view_draws);
+        batches.batched.insert(view, view_batched);
+    }
+}
+
+/// Copies the blades and maps of the chunks into the instance buffers and texture arrays of their [`GrassBatch`]
+///
+/// Runs before the cameras are rendered
+pub(crate) struct GrassBatchNode;
+impl GrassBatchNode {
+    pub const NAME: &'static str = "warbler_grass_batch";
+}
+impl Node for GrassBatchNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let batches = world.resource::<GrassBatches>();
+        for batch in batches.batches.values() {
+            for (source, offset, size) in &batch.copies {
+                render_context.command_encoder().copy_buffer_to_buffer(
+                    source,
+                    0,
+                    &batch.instances,
+                    *offset,
+                    *size,
+                );
+            }
+            for (map, source, layer) in &batch.map_copies {
+                let Some(array) = &batch.maps[*map] else {
+                    continue;
+                };
+                render_context.command_encoder().copy_texture_to_texture(
+                    source.as_image_copy(),
+                    ImageCopyTexture {
+                        texture: &array.texture,
+                        mip_level: 0,
+                        origin: Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: *layer,
+                        },
+                        aspect: TextureAspect::All,
+                    },
+                    Extent3d {
+                        width: array.size.x,
+                        height: array.size.y,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Binds the parameters of the chunks of a [`GrassBatch`]
+pub(crate) struct SetBatchBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetBatchBindGroup<I> {
+    type Param = SRes<GrassBatches>;
+    type ViewWorldQuery = Entity;
+    type ItemWorldQuery = Entity;
+
+    fn render<'w>(
+        _item: &P,
+        view: Entity,
+        entity: Entity,
+        batches: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some((batch, _)) = batches.into_inner().get(view, entity) else {
+            return RenderCommandResult::Failure;
+        };
+        pass.set_bind_group(I, &batch.bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+/// Binds the texture arrays of the y-maps, height textures and normal maps of a [`GrassBatch`]
+/// to the bind groups `I`, `I + 1` and `I + 2`
+///
+/// Batches with a uniform height use the height bind group of their first chunk
+pub(crate) struct SetBatchMapBindGroups<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetBatchMapBindGroups<I> {
+    type Param = SRes<GrassBatches>;
+    type ViewWorldQuery = Entity;
+    type ItemWorldQuery = Read<BindGroupBuffer<WarblerHeight>>;
+
+    fn render<'w>(
+        item: &P,
+        view: Entity,
+        height: &'w BindGroupBuffer<WarblerHeight>,
+        batches: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some((batch, _)) = batches.into_inner().get(view, item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+        for (map, array) in batch.maps.iter().enumerate() {
+            match array {
+                Some(array) => pass.set_bind_group(I + map, &array.bind_group, &[]),
+                None => pass.set_bind_group(I + map, &height.bind_group, &[]),
+            }
+        }
+        RenderCommandResult::Success
+    }
+}
+
+/// The images which can be copied into the texture arrays of a [`GrassBatch`]
+#[derive(Resource, Default)]
+pub(crate) struct CopyableImages(HashSet<HandleId>);
+
+/// Tracks which images have the `COPY_SRC` usage
+pub(crate) fn extract_copyable_images(
+    mut copyable: ResMut<CopyableImages>,
+    mut events: Extract<EventReader<AssetEvent<Image>>>,
+    images: Extract<Res<Assets<Image>>>,
+) {
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                let usage = images
+                    .get(handle)
+                    .map(|image| image.texture_descriptor.usage);
+                if usage.is_some_and(|usage| usage.contains(TextureUsages::COPY_SRC)) {
+                    copyable.0.insert(handle.id());
+                } else {
+                    copyable.0.remove(&handle.id());
+                }
+            }
+            AssetEvent::Removed { handle } => {
+                copyable.0.remove(&handle.id());
+            }
+        }
+    }
+}
+
+/// Draws all chunks of a [`GrassBatch`] visible in the view
+///
+/// Uses one indirect multi-draw if supported and one draw per chunk otherwise
+pub(crate) struct DrawGrassBatch;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawGrassBatch {
+    type Param = (SRes<RenderAssets<Mesh>>, SRes<GrassBatches>);
+    type ViewWorldQuery = Entity;
+    type ItemWorldQuery = Read<Handle<Mesh>>;
+
+    fn render<'w>(
+        item: &P,
+        view: Entity,
+        mesh_handle: &'w Handle<Mesh>,
+        (meshes, batches): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some((batch, draw)) = batches.into_inner().get(view, item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+        let Some(gpu_mesh) = meshes.into_inner().get(mesh_handle) else {
+            return RenderCommandResult::Failure;
+        };
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, batch.instances.slice(..));
+        let draw_count = draw.instances.len() as u32;
+        match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed {
+                buffer,
+                index_format,
+                count,
+            } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                if let Some(draw_args) = &draw.draw_args {
+                    pass.multi_draw_indexed_indirect(draw_args, 0, draw_count);
+                } else {
+                    for (first, instances) in &draw.instances {
+                        pass.draw_indexed(0..*count, 0, *first..*first + *instances);
+                    }
+                }
+            }
+            GpuBufferInfo::NonIndexed => {
+                if let Some(draw_args) = &draw.draw_args {
+                    pass.multi_draw_indirect(draw_args, 0, draw_count);
+                } else {
+                    for (first, instances) in &draw.instances {
+                        pass.draw(0..gpu_mesh.vertex_count, *first..*first + *instances);
+                    }
+                }
+            }
+        }
+        RenderCommandResult::Success
+    }
+}
+
+/// The `Mesh` struct of bevy's shaders
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct ShaderMesh {
+    model: Mat4,
+    previous_model: Mat4,
+    inverse_transpose_model: Mat4,
+    flags: u32,
+    /// Storage buffer structs are aligned to 16 bytes
+    _wasm_padding: [u32; 3],
+}
+impl From<&MeshUniform> for ShaderMesh {
+    fn from(mesh: &MeshUniform) -> Self {
+        Self {
+            model: mesh.transform,
+            previous_model: mesh.previous_transform,
+            inverse_transpose_model: mesh.inverse_transpose_model,
+            flags: mesh.flags,
+            _wasm_padding: [0; 3],
+        }
+    }
+}
+
+/// The parameters of a chunk in a [`GrassBatch`], matching the `GrassChunk` struct of the shader
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct ShaderGrassChunk {
+    mesh: ShaderMesh,
+    color: ShaderColorUniform,
+    blade: ShaderBladeUniform,
+    aabb: ShaderAabb,
+    first_instance: u32,
+    /// Storage buffer structs are aligned to 16 bytes
+    _wasm_padding: [u32; 3],
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use super::ShaderGrassChunk;
+
+    #[test]
+    fn chunk_layout_matches_shader() {
+        // mesh (208) + color (48) + blade (80) + aabb (16) + first instance padded to 16 bytes
+        assert_eq!(mem::size_of::<ShaderGrassChunk>(), 368);
+    }
+}
@@ -1,4 +1,7 @@
-use bevy::{prelude::*, render::render_resource::BindGroup};
+use std::hash::Hash;
+use std::mem;
+
+use bevy::{prelude::*, render::render_resource::BindGroup, utils::HashMap};
 
 #[derive(Resource, Default)]
 pub(crate) struct UniformBuffer(Option<BindGroup>);
@@ -10,3 +13,68 @@ impl UniformBuffer {
         self.0.as_ref().unwrap()
     }
 }
+
+/// Caches a value of each grass chunk, like its bind group, which is only created again if its key `K` changes
+///
+/// Used as [`Local`] of the prepare systems, which call [`ChunkCache::next_frame`] once per frame.
+/// The values of chunks which weren't prepared in the last frame are dropped.
+/// Values depending on the view are cached by a `(view, chunk)` pair as `C`.
+pub(crate) struct ChunkCache<K, V = BindGroup, C = Entity> {
+    current: HashMap<C, (K, V)>,
+    previous: HashMap<C, (K, V)>,
+}
+impl<K, V, C> Default for ChunkCache<K, V, C> {
+    fn default() -> Self {
+        ChunkCache {
+            current: HashMap::new(),
+            previous: HashMap::new(),
+        }
+    }
+}
+impl<K: PartialEq, V: Clone, C: Eq + Hash> ChunkCache<K, V, C> {
+    /// Starts a new frame, dropping the values of chunks not prepared since the last call
+    pub fn next_frame(&mut self) {
+        mem::swap(&mut self.current, &mut self.previous);
+        self.current.clear();
+        #[cfg(feature = "bench")]
+        if crate::diagnostic::DISABLE_CHUNK_CACHE.load(std::sync::atomic::Ordering::Relaxed) {
+            self.previous.clear();
+        }
+    }
+    /// Returns the value of the `chunk` if it was created with the same `key` in the last frame
+    /// and creates it with `create` otherwise
+    pub fn get_or_create(&mut self, chunk: C, key: K, create: impl FnOnce(&K) -> V) -> V {
+        let value = match self.previous.remove(&chunk) {
+            Some((cached_key, value)) if cached_key == key => value,
+            _ => create(&key),
+        };
+        self.current.insert(chunk, (key, value.clone()));
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Entity;
+
+    use super::ChunkCache;
+
+    #[test]
+    fn chunk_cache() {
+        let mut cache = ChunkCache::<u32, u32>::default();
+        let (a, b) = (Entity::from_raw(0), Entity::from_raw(1));
+        cache.next_frame();
+        assert_eq!(cache.get_or_create(a, 1, |key| key * 10), 10);
+        assert_eq!(cache.get_or_create(b, 2, |key| key * 10), 20);
+
+        // unchanged keys reuse the value of the last frame
+        cache.next_frame();
+        assert_eq!(cache.get_or_create(a, 1, |_| unreachable!()), 10);
+        assert_eq!(cache.get_or_create(b, 3, |key| key * 10), 30);
+
+        // chunks which weren't prepared in the last frame are dropped
+        cache.next_frame();
+        cache.next_frame();
+        assert_eq!(cache.get_or_create(a, 1, |key| key * 100), 100);
+    }
+}
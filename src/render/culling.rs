@@ -0,0 +1,409 @@
+use std::mem;
+
+use bevy::{
+    core_pipeline::core_3d::Opaque3d,
+    pbr::MeshUniform,
+    prelude::*,
+    render::{
+        mesh::GpuBufferInfo,
+        primitives::{Aabb, Frustum},
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraphContext},
+        render_phase::RenderPhase,
+        render_resource::{
+            BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+            BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer,
+            BufferBindingType, BufferDescriptor, BufferId, BufferUsages, CachedComputePipelineId,
+            CachedPipelineState, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+            Pipeline, PipelineCache, ShaderStages,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        view::{ExtractedView, VisibleEntities},
+    },
+    utils::HashMap,
+};
+use bytemuck::{Pod, Zeroable};
+
+use crate::{
+    bundle::{BladeSizeVariation, GpuCulling, GrassFade, GrassLod, WarblerHeight},
+    dithering::{DitheredBuffer, GpuDitheredBuffers},
+    warblers_plugin::GRASS_CULLING_SHADER_HANDLE,
+    wind::max_wind_offset,
+    GrassConfiguration,
+};
+
+use super::cache::ChunkCache;
+use super::displacement::{GpuGrassDisplacers, GpuTrampleMaps};
+use super::lod::{shader_levels, GrassLods};
+use super::prepare::GpuWindZones;
+
+/// The amount of blades culled by one workgroup of the culling shader
+const WORKGROUP_SIZE: u32 = 64;
+/// The size of a culled blade, containing its position and rank
+pub(crate) const CULLED_BLADE_SIZE: usize = mem::size_of::<Vec3>();
+/// The distance a blade can be moved from its position by the jitter in each direction
+const MAX_JITTER: f32 = 0.5;
+
+/// The culled blades of a grass chunk in a view
+pub(crate) struct CulledBlades {
+    /// The visible blades, containing the position and rank of each blade
+    pub instances: Buffer,
+    /// The arguments of the indirect draw call, whose instance count is written by the culling shader
+    pub draw_args: Buffer,
+    /// The amount of blades fitting into the `instances`
+    capacity: usize,
+    uniform: Buffer,
+    bind_group: Option<BindGroup>,
+    /// The amount of blades culled in this frame
+    count: u32,
+}
+impl CulledBlades {
+    fn new(render_device: &RenderDevice, capacity: usize) -> Self {
+        let instances = render_device.create_buffer(&BufferDescriptor {
+            label: Some("grass culled instance buffer"),
+            size: (capacity.max(1) * CULLED_BLADE_SIZE) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let draw_args = render_device.create_buffer(&BufferDescriptor {
+            label: Some("grass culling draw args buffer"),
+            size: mem::size_of::<[u32; 5]>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform = render_device.create_buffer(&BufferDescriptor {
+            label: Some("grass culling uniform buffer"),
+            size: mem::size_of::<ShaderCullingUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        CulledBlades {
+            instances,
+            draw_args,
+            capacity,
+            uniform,
+            bind_group: None,
+            count: 0,
+        }
+    }
+}
+
+/// The [`CulledBlades`] of all grass chunks with a [`GpuCulling`] component for each camera
+#[derive(Resource, Default)]
+pub(crate) struct GrassCulling {
+    blades: HashMap<(Entity, Entity), CulledBlades>,
+}
+impl GrassCulling {
+    /// Returns the culled blades of the `chunk` in the `view`
+    ///
+    /// Returns `None` if the chunk isn't culled on the gpu in the view
+    pub(crate) fn get(&self, view: Entity, chunk: Entity) -> Option<&CulledBlades> {
+        self.blades.get(&(view, chunk))
+    }
+}
+
+/// The compute pipeline culling the blades of the chunks
+#[derive(Resource)]
+pub(crate) struct GrassCullingPipeline {
+    layout: BindGroupLayout,
+    pipeline: CachedComputePipelineId,
+}
+impl FromWorld for GrassCullingPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let storage_entry = |binding: u32, read_only: bool| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("warbler_grass culling layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // the positions of all blades
+                storage_entry(1, true),
+                // the visible blades
+                storage_entry(2, false),
+                // the indirect draw arguments
+                storage_entry(3, false),
+            ],
+        });
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("Grass Culling Pipeline".into()),
+            layout: vec![layout.clone()],
+            push_constant_ranges: Vec::new(),
+            shader: GRASS_CULLING_SHADER_HANDLE.typed(),
+            shader_defs: Vec::new(),
+            entry_point: "cull".into(),
+        });
+        // queued pipelines are only added to the cache when the queue is processed,
+        // their state can't be looked up before that
+        pipeline_cache.process_queue();
+        GrassCullingPipeline { layout, pipeline }
+    }
+}
+
+/// Returns true if the gpu supports the compute shaders and indirect draws used to cull the blades
+pub(crate) fn culling_supported(render_device: &RenderDevice) -> bool {
+    let limits = render_device.limits();
+    limits.max_compute_workgroups_per_dimension > 0
+        && limits.max_storage_buffers_per_shader_stage >= 3
+}
+
+/// Returns the pipeline with the `id` if it is compiled
+fn compiled_pipeline(
+    pipeline_cache: &PipelineCache,
+    id: CachedComputePipelineId,
+) -> Option<&ComputePipeline> {
+    match pipeline_cache.get_compute_pipeline_state(id) {
+        CachedPipelineState::Ok(Pipeline::ComputePipeline(pipeline)) => Some(pipeline),
+        _ => None,
+    }
+}
+
+/// Prepares the buffers of the culling shader for the visible grass chunks with a [`GpuCulling`] component in each camera
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub(crate) fn prepare_grass_culling(
+    mut culling: ResMut<GrassCulling>,
+    culling_pipeline: Res<GrassCullingPipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    config: Res<GrassConfiguration>,
+    wind_zones: Res<GpuWindZones>,
+    displacers: Res<GpuGrassDisplacers>,
+    trample_maps: Res<GpuTrampleMaps>,
+    meshes: Res<RenderAssets<Mesh>>,
+    dithered: Res<GpuDitheredBuffers>,
+    lods: Res<GrassLods>,
+    mut bind_groups: Local<ChunkCache<(BufferId, BufferId), BindGroup, (Entity, Entity)>>,
+    chunks: Query<(
+        &GpuCulling,
+        &Handle<DitheredBuffer>,
+        &Handle<Mesh>,
+        &MeshUniform,
+        &Aabb,
+        &WarblerHeight,
+        Option<&BladeSizeVariation>,
+        Option<&GrassLod>,
+        Option<&GrassFade>,
+    )>,
+    views: Query<(Entity, &ExtractedView, &VisibleEntities), With<RenderPhase<Opaque3d>>>,
+) {
+    let mut previous = mem::take(&mut culling.blades);
+    bind_groups.next_frame();
+    // the chunks are drawn without culling until the pipeline is compiled
+    if compiled_pipeline(&pipeline_cache, culling_pipeline.pipeline).is_none() {
+        return;
+    }
+    let max_binding_size = render_device.limits().max_storage_buffer_binding_size as u64;
+    for (view_entity, view, visible_entities) in &views {
+        let view_projection = view.projection * view.transform.compute_matrix().inverse();
+        let frustum = Frustum::from_view_projection(&view_projection);
+        // the far plane is replaced by the distance culling
+        let mut planes = [Vec4::ZERO; 5];
+        for (plane, half_space) in planes.iter_mut().zip(&frustum.half_spaces) {
+            *plane = half_space.normal_d();
+        }
+        for chunk in visible_entities.entities.iter().copied() {
+            let Ok((
+                gpu_culling,
+                dither_handle,
+                mesh_handle,
+                mesh_uniform,
+                aabb,
+                height,
+                variation,
+                lod,
+                fade,
+            )) = chunks.get(chunk)
+            else {
+                continue;
+            };
+            let Some(gpu_dither) = dithered.get(dither_handle) else {
+                continue;
+            };
+            let Some(mesh_handle) = lods.mesh(view_entity, chunk, mesh_handle) else {
+                continue;
+            };
+            let Some(gpu_mesh) = meshes.get(mesh_handle) else {
+                continue;
+            };
+            // only the blades left by the level of detail of the chunk are culled
+            let count = lods.blade_count(view_entity, chunk, gpu_dither.instances as u32);
+            if count == 0 {
+                continue;
+            }
+            // chunks too big for a single storage buffer are drawn without culling
+            if (gpu_dither.instances * CULLED_BLADE_SIZE) as u64 > max_binding_size {
+                continue;
+            }
+            let mut blades = match previous.remove(&(view_entity, chunk)) {
+                Some(blades) if blades.capacity >= gpu_dither.instances => blades,
+                _ => CulledBlades::new(&render_device, gpu_dither.instances),
+            };
+
+            // the culling shader counts the visible instances from 0 on
+            let draw_args: [u32; 5] = match &gpu_mesh.buffer_info {
+                GpuBufferInfo::Indexed { count, .. } => [*count, 0, 0, 0, 0],
+                GpuBufferInfo::NonIndexed => [gpu_mesh.vertex_count, 0, 0, 0, 0],
+            };
+            render_queue.write_buffer(&blades.draw_args, 0, bytemuck::cast_slice(&draw_args));
+
+            let (lod_distances, lod_densities) = shader_levels(lod);
+            // the wind and the displacers move the blades in world space, so they reach further in small chunks
+            let scale = Mat3::from_mat4(mesh_uniform.transform);
+            let min_scale = scale.x_axis.length().min(scale.z_axis.length());
+            let max_wind = config.wind.length() + wind_zones.zones.max_wind();
+            // the blades are bent by either the displacers or their trails, whichever bends them further
+            let displaced = displacers
+                .max_strength(chunk)
+                .max(trample_maps.max_offset(chunk));
+            let wind =
+                (max_wind_offset(&config, max_wind) + displaced) / min_scale.max(f32::EPSILON);
+            let margin = blade_margin(height, variation.copied().unwrap_or_default(), wind)
+                + gpu_culling.margin;
+            let uniform = ShaderCullingUniform {
+                planes,
+                model: mesh_uniform.transform,
+                view_position: view.transform.translation(),
+                blade_count: gpu_dither.instances as f32,
+                size: Vec3::from(aabb.half_extents * 2.),
+                margin,
+                lod_distances,
+                lod_densities,
+                fade_end: fade.map_or(f32::MAX, |fade| fade.end),
+                count,
+                _wasm_padding: Vec2::ZERO,
+            };
+            render_queue.write_buffer(&blades.uniform, 0, bytemuck::bytes_of(&uniform));
+
+            // the bind group only changes if the blades of the chunk or its culled blades were moved to new buffers
+            let key = (gpu_dither.buffer.id(), blades.instances.id());
+            let bind_group = bind_groups.get_or_create((view_entity, chunk), key, |_| {
+                render_device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("grass culling bind group"),
+                    layout: &culling_pipeline.layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: blades.uniform.as_entire_binding(),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: gpu_dither.buffer.as_entire_binding(),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: blades.instances.as_entire_binding(),
+                        },
+                        BindGroupEntry {
+                            binding: 3,
+                            resource: blades.draw_args.as_entire_binding(),
+                        },
+                    ],
+                })
+            });
+            blades.bind_group = Some(bind_group);
+            blades.count = count;
+            culling.blades.insert((view_entity, chunk), blades);
+        }
+    }
+}
+
+/// The maximum distance the vertices of a blade can reach from its position in each direction
+///
+/// `wind` is the distance the wind can move the blades in the space of the chunk
+fn blade_margin(height: &WarblerHeight, variation: BladeSizeVariation, wind: f32) -> f32 {
+    let max_height = match height {
+        WarblerHeight::Uniform(height) => height.abs(),
+        WarblerHeight::Texture { min, max, .. } => min.abs().max(max.abs()),
+    } * variation.min_height.abs().max(variation.max_height.abs());
+    let max_width = variation.min_width.abs().max(variation.max_width.abs());
+    // the blade can be rotated in any direction by the normal map
+    let blade_size = (max_height * max_height + 2. * max_width * max_width).sqrt();
+    MAX_JITTER + blade_size + wind
+}
+
+/// Dispatches the culling shader for all [`CulledBlades`] of this frame
+///
+/// Runs before the cameras are rendered
+pub(crate) struct GrassCullingNode;
+impl GrassCullingNode {
+    pub const NAME: &'static str = "warbler_grass_culling";
+}
+impl Node for GrassCullingNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let culling = world.resource::<GrassCulling>();
+        if culling.blades.is_empty() {
+            return Ok(());
+        }
+        let culling_pipeline = world.resource::<GrassCullingPipeline>();
+        let Some(pipeline) =
+            compiled_pipeline(world.resource::<PipelineCache>(), culling_pipeline.pipeline)
+        else {
+            return Ok(());
+        };
+        let max_workgroups = render_context
+            .render_device()
+            .limits()
+            .max_compute_workgroups_per_dimension;
+
+        let mut pass =
+            render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("grass culling pass"),
+                });
+        pass.set_pipeline(pipeline);
+        for blades in culling.blades.values() {
+            let Some(bind_group) = &blades.bind_group else {
+                continue;
+            };
+            pass.set_bind_group(0, bind_group, &[]);
+            // big chunks need more workgroups than fit into one dimension
+            let workgroups = (blades.count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+            let x = workgroups.min(max_workgroups);
+            let y = (workgroups + x - 1) / x;
+            pass.dispatch_workgroups(x, y, 1);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct ShaderCullingUniform {
+    planes: [Vec4; 5],
+    model: Mat4,
+    view_position: Vec3,
+    blade_count: f32,
+    size: Vec3,
+    margin: f32,
+    lod_distances: Vec4,
+    lod_densities: Vec4,
+    fade_end: f32,
+    count: u32,
+    /// Uniforms are aligned to 16 bytes
+    _wasm_padding: Vec2,
+}
@@ -0,0 +1,452 @@
+use std::{mem, num::NonZeroU64};
+
+use bevy::{
+    pbr::MeshUniform,
+    prelude::*,
+    render::{
+        primitives::Aabb,
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, Extent3d, ImageCopyTexture, ImageDataLayout,
+            Origin3d, Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat,
+            TextureUsages, TextureView, TextureViewDescriptor,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+    utils::{HashMap, HashSet},
+};
+use bytemuck::{Pod, Zeroable};
+
+use super::lod::chunk_distance;
+use crate::{
+    displacement::{GrassDisplacer, TrampleMap, MAX_CHUNK_DISPLACERS},
+    dithering::DitheredBuffer,
+};
+
+/// A [`GrassDisplacer`] as used by the grass shader
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub(crate) struct ShaderDisplacer {
+    position: Vec3,
+    radius: f32,
+    strength: f32,
+    falloff: f32,
+    /// Wasm requires shader uniforms to be aligned to 16 bytes
+    _wasm_padding: Vec2,
+}
+impl ShaderDisplacer {
+    fn new(displacer: &GrassDisplacer, transform: &GlobalTransform) -> Self {
+        ShaderDisplacer {
+            position: transform.translation(),
+            radius: displacer.radius,
+            strength: displacer.strength,
+            falloff: displacer.falloff,
+            _wasm_padding: Vec2::ZERO,
+        }
+    }
+}
+
+/// The [`GrassDisplacer`]s bending the blades of a chunk as used by the grass shader
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub(crate) struct ShaderDisplacers {
+    count: u32,
+    /// Wasm requires shader uniforms to be aligned to 16 bytes
+    _wasm_padding: u32,
+    _wasm_padding_2: UVec2,
+    displacers: [ShaderDisplacer; MAX_CHUNK_DISPLACERS],
+}
+impl ShaderDisplacers {
+    /// The size of the displacers of one chunk in the uniform buffer
+    pub(crate) const SIZE: NonZeroU64 = match NonZeroU64::new(mem::size_of::<Self>() as u64) {
+        Some(size) => size,
+        None => panic!("the displacers of a chunk can't be empty"),
+    };
+    /// Collects the displacers reaching into the [`Aabb`] of a chunk, closest first
+    fn near_chunk(displacers: &[ShaderDisplacer], transform: Mat4, aabb: &Aabb) -> Self {
+        let mut near: Vec<(f32, &ShaderDisplacer)> = displacers
+            .iter()
+            .map(|displacer| {
+                (
+                    chunk_distance(displacer.position, transform, aabb),
+                    displacer,
+                )
+            })
+            .filter(|(distance, displacer)| *distance < displacer.radius)
+            .collect();
+        near.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        let mut chunk_displacers = ShaderDisplacers::zeroed();
+        for (displacer, (_, near)) in chunk_displacers.displacers.iter_mut().zip(near) {
+            *displacer = *near;
+            chunk_displacers.count += 1;
+        }
+        chunk_displacers
+    }
+    /// The furthest distance the displacers move the blades
+    fn max_strength(&self) -> f32 {
+        self.displacers[..self.count as usize]
+            .iter()
+            .map(|displacer| displacer.strength.abs())
+            .sum()
+    }
+}
+
+/// The [`GrassDisplacer`]s of the current frame
+#[derive(Resource, Default)]
+pub(crate) struct ExtractedDisplacers(Vec<ShaderDisplacer>);
+
+/// Extracts all [`GrassDisplacer`]s
+pub(crate) fn extract_grass_displacers(
+    mut extracted: ResMut<ExtractedDisplacers>,
+    displacers: Extract<Query<(&GrassDisplacer, &GlobalTransform)>>,
+) {
+    extracted.0.clear();
+    extracted.0.extend(
+        displacers
+            .iter()
+            .map(|(displacer, transform)| ShaderDisplacer::new(displacer, transform)),
+    );
+}
+
+/// The uniform buffer containing the [`ShaderDisplacers`] of all grass chunks bent by a [`GrassDisplacer`]
+///
+/// The displacers of a chunk are bound with a dynamic offset.
+/// The first entry is empty and used by all chunks without displacers and by the batches.
+#[derive(Resource)]
+pub(crate) struct GpuGrassDisplacers {
+    pub buffer: Buffer,
+    /// The distance between the displacers of two chunks in the buffer
+    stride: u64,
+    /// The offsets of the chunks with displacers and how far they move the blades
+    chunks: HashMap<Entity, (u32, f32)>,
+    /// The content of the buffer
+    written: Vec<u8>,
+}
+impl FromWorld for GpuGrassDisplacers {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let alignment = render_device.limits().min_uniform_buffer_offset_alignment as u64;
+        let stride = (ShaderDisplacers::SIZE.get() + alignment - 1) / alignment * alignment;
+        GpuGrassDisplacers {
+            buffer: Self::create_buffer(render_device, stride),
+            stride,
+            chunks: HashMap::new(),
+            written: Vec::new(),
+        }
+    }
+}
+impl GpuGrassDisplacers {
+    fn create_buffer(render_device: &RenderDevice, size: u64) -> Buffer {
+        render_device.create_buffer(&BufferDescriptor {
+            label: Some("grass displacer buffer"),
+            size,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+    /// Returns true if the blades of the `chunk` are bent by a displacer
+    pub(crate) fn contains(&self, chunk: Entity) -> bool {
+        self.chunks.contains_key(&chunk)
+    }
+    /// Returns the dynamic offset of the displacers of the `chunk`
+    pub(crate) fn offset(&self, chunk: Entity) -> u32 {
+        self.chunks.get(&chunk).map_or(0, |(offset, _)| *offset)
+    }
+    /// Returns the furthest distance the displacers of the `chunk` move its blades
+    pub(crate) fn max_strength(&self, chunk: Entity) -> f32 {
+        self.chunks
+            .get(&chunk)
+            .map_or(0., |(_, strength)| *strength)
+    }
+}
+
+/// Collects the displacers near each grass chunk and writes them to the [`GpuGrassDisplacers`]
+pub(crate) fn prepare_grass_displacers(
+    mut gpu_displacers: ResMut<GpuGrassDisplacers>,
+    extracted: Res<ExtractedDisplacers>,
+    chunks: Query<(Entity, &MeshUniform, &Aabb), With<Handle<DitheredBuffer>>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let gpu_displacers = gpu_displacers.as_mut();
+    gpu_displacers.chunks.clear();
+    let stride = gpu_displacers.stride as usize;
+    // the empty displacers of the chunks without any
+    let mut data = vec![0; stride];
+    if !extracted.0.is_empty() {
+        for (chunk, mesh_uniform, aabb) in &chunks {
+            let displacers =
+                ShaderDisplacers::near_chunk(&extracted.0, mesh_uniform.transform, aabb);
+            if displacers.count == 0 {
+                continue;
+            }
+            let offset = data.len();
+            data.extend_from_slice(bytemuck::bytes_of(&displacers));
+            data.resize(offset + stride, 0);
+            gpu_displacers
+                .chunks
+                .insert(chunk, (offset as u32, displacers.max_strength()));
+        }
+    }
+    if data == gpu_displacers.written {
+        return;
+    }
+    if data.len() as u64 > gpu_displacers.buffer.size() {
+        let size = (data.len() as u64).next_power_of_two();
+        gpu_displacers.buffer = GpuGrassDisplacers::create_buffer(&render_device, size);
+    }
+    render_queue.write_buffer(&gpu_displacers.buffer, 0, &data);
+    gpu_displacers.written = data;
+}
+
+/// The changed texels of a [`TrampleMap`]
+struct ExtractedTrampleMap {
+    resolution: UVec2,
+    /// The first changed texel
+    origin: UVec2,
+    /// The size of the rectangle of changed texels
+    size: UVec2,
+    /// The rows of the changed texels
+    texels: Vec<Vec4>,
+    recovery_rate: f32,
+    /// The furthest distance the trails move the blades in this frame
+    max_offset: f32,
+}
+
+/// The [`TrampleMap`]s of the current frame
+#[derive(Resource, Default)]
+pub(crate) struct ExtractedTrampleMaps {
+    /// The trample maps changed since the last frame
+    changed: Vec<(Entity, ExtractedTrampleMap)>,
+    /// All chunks with a trample map
+    chunks: HashSet<Entity>,
+    /// The wrapped elapsed time of this frame
+    time: f32,
+}
+
+/// Extracts the [`TrampleMap`]s of the chunks, only copying the changed texels of the changed ones
+pub(crate) fn extract_trample_maps(
+    mut extracted: ResMut<ExtractedTrampleMaps>,
+    time: Extract<Res<Time>>,
+    maps: Extract<Query<(Entity, Ref<TrampleMap>)>>,
+) {
+    extracted.changed.clear();
+    extracted.chunks.clear();
+    extracted.time = time.elapsed_seconds_wrapped();
+    for (entity, map) in &maps {
+        extracted.chunks.insert(entity);
+        let Some((min, max)) = map.changed.filter(|_| map.is_changed()) else {
+            continue;
+        };
+        let width = map.resolution.x as usize;
+        let texels = (min.y..max.y)
+            .flat_map(|y| {
+                let row = y as usize * width;
+                &map.texels[row + min.x as usize..row + max.x as usize]
+            })
+            .copied()
+            .collect();
+        extracted.changed.push((
+            entity,
+            ExtractedTrampleMap {
+                resolution: map.resolution,
+                origin: min,
+                size: max - min,
+                texels,
+                recovery_rate: map.recovery_rate,
+                max_offset: map.max_offset(),
+            },
+        ));
+    }
+}
+
+/// The texture of a [`TrampleMap`] on the gpu
+struct GpuTrampleMap {
+    texture: Texture,
+    view: TextureView,
+    resolution: UVec2,
+    recovery_rate: f32,
+    /// The furthest distance the trails moved the blades when the map last changed
+    max_offset: f32,
+    /// The time the map last changed
+    changed_at: f32,
+}
+
+/// The trample textures of all chunks with a [`GrassTrample`](crate::displacement::GrassTrample) component
+///
+/// Chunks without one are bound to an empty texture, which doesn't bend their blades.
+#[derive(Resource)]
+pub(crate) struct GpuTrampleMaps {
+    empty: TextureView,
+    maps: HashMap<Entity, GpuTrampleMap>,
+    /// The wrapped elapsed time of this frame
+    time: f32,
+}
+impl FromWorld for GpuTrampleMaps {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let render_queue = world.resource::<RenderQueue>();
+        let texture = render_device.create_texture_with_data(
+            render_queue,
+            &trample_texture_descriptor(UVec2::ONE),
+            &[0; TRAMPLE_TEXEL_SIZE as usize],
+        );
+        GpuTrampleMaps {
+            empty: texture.create_view(&TextureViewDescriptor::default()),
+            maps: HashMap::new(),
+            time: 0.,
+        }
+    }
+}
+impl GpuTrampleMaps {
+    /// Returns true if the blades of the `chunk` are trampled
+    pub(crate) fn contains(&self, chunk: Entity) -> bool {
+        self.maps.contains_key(&chunk)
+    }
+    /// Returns the trample texture of the `chunk`
+    pub(crate) fn view(&self, chunk: Entity) -> &TextureView {
+        self.maps.get(&chunk).map_or(&self.empty, |map| &map.view)
+    }
+    /// Returns the furthest distance the trails in the `chunk` move its blades
+    pub(crate) fn max_offset(&self, chunk: Entity) -> f32 {
+        self.maps.get(&chunk).map_or(0., |map| {
+            // all trails recover at the same rate since the map last changed
+            map.max_offset * (-map.recovery_rate * (self.time - map.changed_at).max(0.)).exp()
+        })
+    }
+}
+
+/// The size of a texel of the trample textures, which store the offset of the blades on the x,z plane,
+/// the time they were trampled at and their recovery rate
+const TRAMPLE_TEXEL_SIZE: u32 = mem::size_of::<Vec4>() as u32;
+
+fn trample_texture_descriptor(resolution: UVec2) -> TextureDescriptor<'static> {
+    TextureDescriptor {
+        label: Some("grass trample texture"),
+        size: Extent3d {
+            width: resolution.x,
+            height: resolution.y,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba32Float,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        view_formats: &[],
+    }
+}
+
+/// Writes the changed texels of the [`TrampleMap`]s to their textures and frees the ones of removed chunks
+///
+/// The texture of a chunk is only created again if its resolution changes
+pub(crate) fn prepare_trample_maps(
+    mut extracted: ResMut<ExtractedTrampleMaps>,
+    mut gpu_maps: ResMut<GpuTrampleMaps>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let extracted = extracted.as_mut();
+    gpu_maps.time = extracted.time;
+    gpu_maps
+        .maps
+        .retain(|chunk, _| extracted.chunks.contains(chunk));
+    for (chunk, map) in extracted.changed.drain(..) {
+        let gpu_map = match gpu_maps.maps.remove(&chunk) {
+            Some(gpu_map) if gpu_map.resolution == map.resolution => gpu_map,
+            _ => {
+                let texture =
+                    render_device.create_texture(&trample_texture_descriptor(map.resolution));
+                GpuTrampleMap {
+                    view: texture.create_view(&TextureViewDescriptor::default()),
+                    texture,
+                    resolution: map.resolution,
+                    recovery_rate: 0.,
+                    max_offset: 0.,
+                    changed_at: 0.,
+                }
+            }
+        };
+        render_queue.write_texture(
+            ImageCopyTexture {
+                texture: &gpu_map.texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: map.origin.x,
+                    y: map.origin.y,
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
+            },
+            bytemuck::cast_slice(&map.texels),
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(map.size.x * TRAMPLE_TEXEL_SIZE),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width: map.size.x,
+                height: map.size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+        gpu_maps.maps.insert(
+            chunk,
+            GpuTrampleMap {
+                recovery_rate: map.recovery_rate,
+                max_offset: map.max_offset,
+                changed_at: extracted.time,
+                ..gpu_map
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{Mat4, Vec3};
+    use bevy::render::primitives::Aabb;
+    use bevy::transform::components::GlobalTransform;
+
+    use super::{ShaderDisplacer, ShaderDisplacers};
+    use crate::displacement::{GrassDisplacer, MAX_CHUNK_DISPLACERS};
+
+    #[test]
+    fn near_chunk() {
+        let displacer = |x: f32, y: f32, radius: f32| {
+            let displacer = GrassDisplacer {
+                radius,
+                ..Default::default()
+            };
+            ShaderDisplacer::new(&displacer, &GlobalTransform::from_xyz(x, y, 5.))
+        };
+        let aabb = Aabb::from_min_max(Vec3::ZERO, Vec3::new(10., 1., 10.));
+        let transform = Mat4::from_translation(Vec3::new(100., 0., 0.));
+
+        let inside = displacer(105., 0., 1.);
+        let border = displacer(111., 0., 2.);
+        let far = displacer(120., 0., 2.);
+        let above = displacer(105., 5., 2.);
+        let displacers = [far, border, above, inside];
+        let near = ShaderDisplacers::near_chunk(&displacers, transform, &aabb);
+        // the closest displacers come first
+        assert_eq!(near.count, 2);
+        assert_eq!(&near.displacers[..2], &[inside, border]);
+        assert_eq!(near.max_strength(), 2.);
+
+        // only the closest displacers are used
+        let displacers: Vec<ShaderDisplacer> = (0..20)
+            .map(|i| displacer(130. - i as f32, 0., 25.))
+            .collect();
+        let near = ShaderDisplacers::near_chunk(&displacers, transform, &aabb);
+        assert_eq!(near.count as usize, MAX_CHUNK_DISPLACERS);
+        assert_eq!(near.displacers[0], displacers[19]);
+        assert_eq!(near.displacers[MAX_CHUNK_DISPLACERS - 1], displacers[12]);
+    }
+    #[test]
+    fn displacer_layout_matches_shader() {
+        // the size of the `Displacers` struct in the grass shader
+        assert_eq!(ShaderDisplacers::SIZE.get(), 16 + 8 * 32);
+    }
+}
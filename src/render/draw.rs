@@ -12,27 +12,32 @@ use bevy::{
 };
 
 use crate::{
-    dithering::DitheredBuffer,
+    dithering::{DitheredBuffer, GpuDitheredBuffers},
     map::YMap,
     prelude::{GrassColor, NormalMap, WarblerHeight},
 };
 
-use super::{cache::UniformBuffer, prepare::BindGroupBuffer};
+use super::{
+    cache::UniformBuffer, culling::GrassCulling, displacement::GpuGrassDisplacers, lod::GrassLods,
+    prepare::BindGroupBuffer,
+};
 pub(crate) struct SetUniformBindGroup<const I: usize>;
 
 impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetUniformBindGroup<I> {
-    type Param = SRes<UniformBuffer>;
+    type Param = (SRes<UniformBuffer>, SRes<GpuGrassDisplacers>);
     type ViewWorldQuery = ();
     type ItemWorldQuery = ();
 
     fn render<'w>(
-        _item: &P,
+        item: &P,
         _view: (),
         _entity: (),
-        cache: SystemParamItem<'w, '_, Self::Param>,
+        (cache, displacers): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        pass.set_bind_group(I, cache.into_inner().ref_unwrap(), &[]);
+        // batches never contain displaced chunks and use the empty displacers
+        let offset = displacers.offset(item.entity());
+        pass.set_bind_group(I, cache.into_inner().ref_unwrap(), &[offset]);
 
         RenderCommandResult::Success
     }
@@ -120,31 +125,66 @@ impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetHeightBindGroup<I> {
 pub(crate) struct SetVertexBuffer;
 
 impl<P: PhaseItem> RenderCommand<P> for SetVertexBuffer {
-    type Param = (SRes<RenderAssets<Mesh>>, SRes<RenderAssets<DitheredBuffer>>);
-    type ViewWorldQuery = ();
-    type ItemWorldQuery = (Read<Handle<Mesh>>, Option<Read<Handle<DitheredBuffer>>>);
+    type Param = (
+        SRes<RenderAssets<Mesh>>,
+        SRes<GpuDitheredBuffers>,
+        SRes<GrassLods>,
+        SRes<GrassCulling>,
+    );
+    type ViewWorldQuery = Entity;
+    type ItemWorldQuery = (
+        Entity,
+        Read<Handle<Mesh>>,
+        Option<Read<Handle<DitheredBuffer>>>,
+    );
 
     #[inline]
     fn render<'w>(
         _item: &P,
-        _view: (),
-        (mesh_handle, dither_handle): (
+        view: Entity,
+        (entity, mesh_handle, dither_handle): (
+            Entity,
             &'w Handle<bevy::prelude::Mesh>,
             Option<&'w Handle<DitheredBuffer>>,
         ),
-        (meshes, dither): SystemParamItem<'w, '_, Self::Param>,
+        (meshes, dither, lods, culling): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
+        let lods = lods.into_inner();
+        let Some(mesh_handle) = lods.mesh(view, entity, mesh_handle) else {
+            return RenderCommandResult::Failure;
+        };
         let Some(gpu_mesh) = meshes.into_inner().get(mesh_handle) else {
             return RenderCommandResult::Failure;
         };
 
         pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+
+        // the instance count of blades culled on the gpu is written by the culling shader
+        if let Some(culled) = culling.into_inner().get(view, entity) {
+            pass.set_vertex_buffer(1, culled.instances.slice(..));
+            match &gpu_mesh.buffer_info {
+                GpuBufferInfo::Indexed {
+                    buffer,
+                    index_format,
+                    ..
+                } => {
+                    pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                    pass.draw_indexed_indirect(&culled.draw_args, 0);
+                }
+                GpuBufferInfo::NonIndexed => {
+                    pass.draw_indirect(&culled.draw_args, 0);
+                }
+            }
+            return RenderCommandResult::Success;
+        }
+
         let blade_count;
 
         if let Some(dither_handle) = dither_handle {
             if let Some(gpu_dither) = dither.into_inner().get(dither_handle) {
-                blade_count = gpu_dither.instances as u32;
+                // distant chunks only draw a prefix of their blades
+                blade_count = lods.blade_count(view, entity, gpu_dither.instances as u32);
                 if blade_count == 0 {
                     return RenderCommandResult::Failure;
                 }
@@ -1,4 +1,9 @@
-use crate::{dithering::DitheredBuffer, map::YMap};
+use super::prepare::GpuWindZones;
+use crate::{
+    dithering::DitheredBuffer,
+    map::YMap,
+    wind::{ShaderWindZone, WindZone},
+};
 use bevy::{
     prelude::*,
     render::{primitives::Aabb, Extract},
@@ -30,3 +35,14 @@ pub(crate) fn extract_aabb(
     }
     commands.insert_or_spawn_batch(values);
 }
+
+/// Extracts the [`WindZone`]s affecting the grass
+pub(crate) fn extract_wind_zones(
+    mut wind_zones: ResMut<GpuWindZones>,
+    zones: Extract<Query<(&WindZone, &GlobalTransform)>>,
+) {
+    wind_zones.zones = zones
+        .iter()
+        .map(|(zone, transform)| ShaderWindZone::new(zone, transform))
+        .collect();
+}
@@ -1,30 +1,59 @@
 use bevy::{
-    pbr::{MeshPipeline, MeshPipelineKey},
+    core_pipeline::prepass::{
+        DEPTH_PREPASS_FORMAT, MOTION_VECTOR_PREPASS_FORMAT, NORMAL_PREPASS_FORMAT,
+    },
+    pbr::{MeshPipeline, MeshPipelineKey, PreviousViewProjection},
     prelude::*,
     render::{
+        globals::GlobalsUniform,
         mesh::MeshVertexBufferLayout,
         render_resource::{
-            BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
-            BufferBindingType, RenderPipelineDescriptor, ShaderStages, SpecializedMeshPipeline,
-            SpecializedMeshPipelineError, TextureSampleType, TextureViewDimension, VertexAttribute,
-            VertexBufferLayout, VertexFormat, VertexStepMode,
+            AddressMode, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+            BindingType, BlendState, BufferBindingType, ColorTargetState, ColorWrites,
+            CompareFunction, DepthBiasState, DepthStencilState, FilterMode, FragmentState,
+            FrontFace, MultisampleState, PolygonMode, PrimitiveState, RenderPipelineDescriptor,
+            Sampler, SamplerBindingType, SamplerDescriptor, ShaderDefVal, ShaderStages, ShaderType,
+            SpecializedMeshPipeline, SpecializedMeshPipelineError, StencilState, TextureFormat,
+            TextureSampleType, TextureViewDimension, VertexAttribute, VertexBufferLayout,
+            VertexFormat, VertexState, VertexStepMode,
         },
-        renderer::RenderDevice,
+        renderer::{RenderAdapterInfo, RenderDevice},
+        view::ViewUniform,
     },
 };
 
-use crate::warblers_plugin::GRASS_SHADER_HANDLE;
-#[derive(Resource)]
+use super::{
+    batch::batching_supported, culling::CULLED_BLADE_SIZE, displacement::ShaderDisplacers,
+};
+use crate::{bundle::MapSampling, warblers_plugin::GRASS_SHADER_HANDLE};
+#[derive(Resource, Clone)]
 pub struct GrassPipeline {
     shader: Handle<Shader>,
     mesh_pipeline: MeshPipeline,
     pub region_layout: BindGroupLayout,
-    pub y_map_layout: BindGroupLayout,
-    pub normal_map_layout: BindGroupLayout,
+    pub y_map_layouts: MapLayouts,
+    pub normal_map_layouts: MapLayouts,
     pub density_map_layout: BindGroupLayout,
-    pub heights_texture_layout: BindGroupLayout,
+    pub heights_texture_layouts: MapLayouts,
+    /// The sampler used for maps with [`TextureBinding::Filtered`]
+    pub map_sampler: Sampler,
     pub uniform_height_layout: BindGroupLayout,
     pub color_layout: BindGroupLayout,
+    /// The layouts of batched chunks, if storage buffers are supported
+    pub batch_layouts: Option<BatchLayouts>,
+}
+
+/// The bind group layouts replacing the ones of single chunks when drawing a batch
+#[derive(Clone)]
+pub struct BatchLayouts {
+    /// The parameters of the chunks, replacing the mesh bind group
+    pub chunks: BindGroupLayout,
+    /// The maps of the chunks in the layers of a texture array.
+    ///
+    /// The samplers are bound at the same binding as for single chunks
+    pub y_map: MapLayouts,
+    pub heights_texture: MapLayouts,
+    pub normal_map: MapLayouts,
 }
 
 impl FromWorld for GrassPipeline {
@@ -55,22 +84,34 @@ impl FromWorld for GrassPipeline {
                     },
                     count: None,
                 },
-            ],
-        });
-        let y_map_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("warbler_grass y map layout"),
-            entries: &[
-                // y_texture
+                // wind zones
                 BindGroupLayoutEntry {
-                    binding: 0,
+                    binding: 2,
                     visibility: ShaderStages::VERTEX,
-                    ty: BindingType::Texture {
-                        sample_type: TextureSampleType::Float { filterable: false },
-                        view_dimension: TextureViewDimension::D2,
-                        multisampled: false,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // the displacers of the drawn chunk
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(ShaderDisplacers::SIZE),
                     },
                     count: None,
                 },
+            ],
+        });
+        let y_map_layouts = MapLayouts::new(
+            render_device,
+            "warbler_grass y map layout",
+            &[
                 // aabb box
                 BindGroupLayoutEntry {
                     binding: 1,
@@ -82,25 +123,44 @@ impl FromWorld for GrassPipeline {
                     },
                     count: None,
                 },
+                // the trails of the displacers
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
             ],
+        );
+        let normal_map_layouts =
+            MapLayouts::new(render_device, "warbler_grass normal map layout", &[]);
+        let heights_texture_layouts = MapLayouts::new(
+            render_device,
+            "warbler_grass height texture layout",
+            // blade uniform
+            &[BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        );
+        let map_sampler = render_device.create_sampler(&SamplerDescriptor {
+            label: Some("warbler_grass map sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
         });
-        let normal_map_layout =
-            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("warbler_grass normal map layout"),
-                entries: &[
-                    // normal_texture_view
-                    BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::VERTEX,
-                        ty: BindingType::Texture {
-                            sample_type: TextureSampleType::Float { filterable: false },
-                            view_dimension: TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                ],
-            });
         let density_map_layout =
             render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: Some("warbler_grass density map layout"),
@@ -119,20 +179,6 @@ impl FromWorld for GrassPipeline {
                 ],
             });
 
-        let heights_texture_layout =
-            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("warbler_grass height texture layout"),
-                entries: &[BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::VERTEX,
-                    ty: BindingType::Texture {
-                        sample_type: TextureSampleType::Float { filterable: false },
-                        view_dimension: TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                }],
-            });
         let uniform_height_layout =
             render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: Some("warbler_grasss configuration layout"),
@@ -151,7 +197,7 @@ impl FromWorld for GrassPipeline {
             label: Some("warbler_grass color layout"),
             entries: &[BindGroupLayoutEntry {
                 binding: 0,
-                visibility: ShaderStages::VERTEX,
+                visibility: ShaderStages::VERTEX_FRAGMENT,
                 ty: BindingType::Buffer {
                     ty: BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -160,6 +206,33 @@ impl FromWorld for GrassPipeline {
                 count: None,
             }],
         });
+        let adapter_info = world.resource::<RenderAdapterInfo>();
+        let batch_layouts = batching_supported(render_device, adapter_info).then(|| BatchLayouts {
+            chunks: render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("warbler_grass batch layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX_FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            }),
+            y_map: MapLayouts::array(render_device, "warbler_grass batch y map layout", 3),
+            heights_texture: MapLayouts::array(
+                render_device,
+                "warbler_grass batch height texture layout",
+                2,
+            ),
+            normal_map: MapLayouts::array(
+                render_device,
+                "warbler_grass batch normal map layout",
+                1,
+            ),
+        });
         let shader = GRASS_SHADER_HANDLE.typed::<Shader>();
         let mesh_pipeline = world.resource::<MeshPipeline>();
         GrassPipeline {
@@ -167,11 +240,189 @@ impl FromWorld for GrassPipeline {
             mesh_pipeline: mesh_pipeline.clone(),
             region_layout,
             uniform_height_layout,
-            heights_texture_layout,
+            heights_texture_layouts,
+            y_map_layouts,
+            normal_map_layouts,
             density_map_layout,
-            y_map_layout,
-            normal_map_layout,
+            map_sampler,
             color_layout,
+            batch_layouts,
+        }
+    }
+}
+/// How a texture map is read in the shader
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug, Default)]
+pub enum TextureBinding {
+    /// The nearest pixel is loaded
+    #[default]
+    Float,
+    /// The nearest pixel is loaded from an unsigned integer texture.
+    ///
+    /// Bevy loads 16 bit pngs as unsigned integer textures
+    Uint,
+    /// The texture is sampled bilinearly
+    Filtered,
+}
+impl TextureBinding {
+    /// Returns how a texture with the `format` is read in the shader
+    pub fn new(format: TextureFormat, sampling: MapSampling) -> Self {
+        match format.sample_type(None) {
+            Some(TextureSampleType::Uint) => TextureBinding::Uint,
+            Some(TextureSampleType::Float { filterable: true })
+                if sampling == MapSampling::Linear =>
+            {
+                TextureBinding::Filtered
+            }
+            _ => TextureBinding::Float,
+        }
+    }
+    /// Pushes the shader def of the binding to the `shader_defs`, using the `map` as prefix
+    fn push_shader_def(self, map: &str, shader_defs: &mut Vec<ShaderDefVal>) {
+        match self {
+            TextureBinding::Float => {}
+            TextureBinding::Uint => shader_defs.push(format!("{map}_UINT").into()),
+            TextureBinding::Filtered => shader_defs.push(format!("{map}_FILTERED").into()),
+        }
+    }
+}
+/// The bind group layouts of a texture map for every [`TextureBinding`]
+#[derive(Clone)]
+pub struct MapLayouts {
+    float: BindGroupLayout,
+    uint: BindGroupLayout,
+    filtered: BindGroupLayout,
+}
+impl MapLayouts {
+    /// The texture is always bound at binding 0, followed by the `entries`.
+    /// The sampler of filtered textures is bound after the `entries`
+    fn new(render_device: &RenderDevice, label: &str, entries: &[BindGroupLayoutEntry]) -> Self {
+        let sampler_binding = entries.len() as u32 + 1;
+        Self::with_dimension(
+            render_device,
+            label,
+            TextureViewDimension::D2,
+            entries,
+            sampler_binding,
+        )
+    }
+    /// The layouts of a texture array bound at binding 0, whose sampler is bound at the `sampler_binding`
+    fn array(render_device: &RenderDevice, label: &str, sampler_binding: u32) -> Self {
+        Self::with_dimension(
+            render_device,
+            label,
+            TextureViewDimension::D2Array,
+            &[],
+            sampler_binding,
+        )
+    }
+    fn with_dimension(
+        render_device: &RenderDevice,
+        label: &str,
+        view_dimension: TextureViewDimension,
+        entries: &[BindGroupLayoutEntry],
+        sampler_binding: u32,
+    ) -> Self {
+        let layout = |sample_type, filtered: bool| {
+            let texture = BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Texture {
+                    sample_type,
+                    view_dimension,
+                    multisampled: false,
+                },
+                count: None,
+            };
+            let sampler = BindGroupLayoutEntry {
+                binding: sampler_binding,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            };
+            let mut layout_entries = vec![texture];
+            layout_entries.extend_from_slice(entries);
+            if filtered {
+                layout_entries.push(sampler);
+            }
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some(label),
+                entries: &layout_entries,
+            })
+        };
+        MapLayouts {
+            float: layout(TextureSampleType::Float { filterable: false }, false),
+            uint: layout(TextureSampleType::Uint, false),
+            filtered: layout(TextureSampleType::Float { filterable: true }, true),
+        }
+    }
+    pub fn get(&self, binding: TextureBinding) -> &BindGroupLayout {
+        match binding {
+            TextureBinding::Float => &self.float,
+            TextureBinding::Uint => &self.uint,
+            TextureBinding::Filtered => &self.filtered,
+        }
+    }
+}
+impl GrassPipeline {
+    /// Pushes the bind group layouts from group 2 onwards and the shader defs of the grass chunk,
+    /// which are shared by all passes rendering the grass
+    fn specialize_grass(
+        &self,
+        key: &GrassRenderKey,
+        layouts: &mut Vec<BindGroupLayout>,
+        shader_defs: &mut Vec<ShaderDefVal>,
+    ) {
+        layouts.push(self.region_layout.clone());
+        layouts.push(self.color_layout.clone());
+        key.y_map.push_shader_def("Y_MAP", shader_defs);
+        layouts.push(self.y_map_layouts.get(key.y_map).clone());
+        if key.uniform_height {
+            layouts.push(self.uniform_height_layout.clone());
+        } else {
+            shader_defs.push("HEIGHT_TEXTURE".into());
+            key.height.push_shader_def("HEIGHT_TEXTURE", shader_defs);
+            layouts.push(self.heights_texture_layouts.get(key.height).clone());
+        }
+        key.normal_map.push_shader_def("NORMAL_MAP", shader_defs);
+        layouts.push(self.normal_map_layouts.get(key.normal_map).clone());
+        if key.world_space {
+            shader_defs.push("WORLD_SPACE_DITHERING".into());
+        }
+        if key.distance_fade {
+            shader_defs.push("DISTANCE_FADE".into());
+        }
+        if key.gpu_culling {
+            shader_defs.push("GPU_CULLING".into());
+        }
+    }
+}
+/// The layout of the vertex buffer containing the xz positions of the blades
+///
+/// The blades culled on the gpu also contain their rank used by the level of detail
+fn instance_buffer_layout(gpu_culling: bool) -> VertexBufferLayout {
+    let position = VertexAttribute {
+        format: VertexFormat::Float32x2,
+        offset: 0,
+        shader_location: 3, // shader locations 0-2 may be taken up by Position, Normal and UV attributes
+    };
+    if gpu_culling {
+        VertexBufferLayout {
+            array_stride: CULLED_BLADE_SIZE as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![
+                position,
+                VertexAttribute {
+                    format: VertexFormat::Float32,
+                    offset: std::mem::size_of::<Vec2>() as u64,
+                    shader_location: 4,
+                },
+            ],
+        }
+    } else {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vec2>() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![position],
         }
     }
 }
@@ -185,30 +436,36 @@ impl SpecializedMeshPipeline for GrassPipeline {
     ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
         let mut descriptor = self.mesh_pipeline.specialize(key.mesh_key, layout)?;
         descriptor.label = Some("Grass Render Pipeline".into());
-        descriptor.layout.push(self.region_layout.clone());
-        descriptor.layout.push(self.color_layout.clone());
-        descriptor.vertex.buffers.push(VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vec2>() as u64,
-            step_mode: VertexStepMode::Instance,
-            attributes: vec![VertexAttribute {
-                format: VertexFormat::Float32x2,
-                offset: 0,
-                shader_location: 3, // shader locations 0-2 may be taken up by Position, Normal and UV attributes
-            }],
-        });
+        descriptor
+            .vertex
+            .buffers
+            .push(instance_buffer_layout(key.gpu_culling));
         let vertex = &mut descriptor.vertex;
 
         vertex.shader = self.shader.clone();
-        descriptor.layout.push(self.y_map_layout.clone());
-        if key.uniform_height {
-            descriptor.layout.push(self.uniform_height_layout.clone());
-        } else {
-            vertex.shader_defs.push("HEIGHT_TEXTURE".into());
-            descriptor.layout.push(self.heights_texture_layout.clone());
-        }
-        descriptor.layout.push(self.normal_map_layout.clone());
+        self.specialize_grass(&key, &mut descriptor.layout, &mut vertex.shader_defs);
 
-        descriptor.fragment.as_mut().unwrap().shader = self.shader.clone();
+        let fragment = descriptor.fragment.as_mut().unwrap();
+        fragment.shader = self.shader.clone();
+        fragment.shader_defs.push("FRAGMENT_STAGE".into());
+        if key.lit {
+            // the vertex output differs for lit grass
+            vertex.shader_defs.push("LIT".into());
+            fragment.shader_defs.push("LIT".into());
+        }
+        if key.batched {
+            // the parameters of the chunks replace the mesh bind group and the maps of the chunks are texture arrays.
+            // Batches only exist if storage buffers are supported
+            let batch_layouts = self.batch_layouts.as_ref().unwrap();
+            descriptor.layout[1] = batch_layouts.chunks.clone();
+            descriptor.layout[4] = batch_layouts.y_map.get(key.y_map).clone();
+            if !key.uniform_height {
+                descriptor.layout[5] = batch_layouts.heights_texture.get(key.height).clone();
+            }
+            descriptor.layout[6] = batch_layouts.normal_map.get(key.normal_map).clone();
+            vertex.shader_defs.push("BATCHED".into());
+            fragment.shader_defs.push("BATCHED".into());
+        }
         Ok(descriptor)
     }
 }
@@ -217,6 +474,14 @@ impl SpecializedMeshPipeline for GrassPipeline {
 pub struct GrassRenderKey {
     pub mesh_key: MeshPipelineKey,
     pub uniform_height: bool,
+    pub world_space: bool,
+    pub lit: bool,
+    pub distance_fade: bool,
+    pub gpu_culling: bool,
+    pub batched: bool,
+    pub y_map: TextureBinding,
+    pub normal_map: TextureBinding,
+    pub height: TextureBinding,
 }
 
 impl From<MeshPipelineKey> for GrassRenderKey {
@@ -224,6 +489,184 @@ impl From<MeshPipelineKey> for GrassRenderKey {
         Self {
             mesh_key,
             uniform_height: false,
+            world_space: false,
+            lit: false,
+            distance_fade: false,
+            gpu_culling: false,
+            batched: false,
+            y_map: TextureBinding::default(),
+            normal_map: TextureBinding::default(),
+            height: TextureBinding::default(),
+        }
+    }
+}
+
+/// The pipeline rendering the grass into the shadow maps of the lights and the prepass textures of the views
+///
+/// The blades are displaced exactly like in the [`GrassPipeline`],
+/// but the bind group of the view is the one of bevy's prepass.
+#[derive(Resource)]
+pub struct GrassPrepassPipeline {
+    grass_pipeline: GrassPipeline,
+    view_layout_motion_vectors: BindGroupLayout,
+    view_layout_no_motion_vectors: BindGroupLayout,
+}
+impl FromWorld for GrassPrepassPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        // the layouts need to match the ones of the `PrepassViewBindGroup`
+        let view_entry = BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: Some(ViewUniform::min_size()),
+            },
+            count: None,
+        };
+        let globals_entry = BindGroupLayoutEntry {
+            binding: 1,
+            visibility: ShaderStages::VERTEX_FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: Some(GlobalsUniform::min_size()),
+            },
+            count: None,
+        };
+        let previous_view_projection_entry = BindGroupLayoutEntry {
+            binding: 2,
+            visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: Some(PreviousViewProjection::min_size()),
+            },
+            count: None,
+        };
+        let view_layout_motion_vectors =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("warbler_grass prepass view layout motion vectors"),
+                entries: &[view_entry, globals_entry, previous_view_projection_entry],
+            });
+        let view_layout_no_motion_vectors =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("warbler_grass prepass view layout no motion vectors"),
+                entries: &[view_entry, globals_entry],
+            });
+        GrassPrepassPipeline {
+            grass_pipeline: world.resource::<GrassPipeline>().clone(),
+            view_layout_motion_vectors,
+            view_layout_no_motion_vectors,
         }
     }
 }
+impl SpecializedMeshPipeline for GrassPrepassPipeline {
+    type Key = GrassRenderKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mesh_key = key.mesh_key;
+        let view_layout = if mesh_key.contains(MeshPipelineKey::MOTION_VECTOR_PREPASS) {
+            self.view_layout_motion_vectors.clone()
+        } else {
+            self.view_layout_no_motion_vectors.clone()
+        };
+        let mut layouts = vec![
+            view_layout,
+            self.grass_pipeline
+                .mesh_pipeline
+                .mesh_layouts
+                .model_only
+                .clone(),
+        ];
+        let mut shader_defs = vec!["PREPASS".into()];
+        self.grass_pipeline
+            .specialize_grass(&key, &mut layouts, &mut shader_defs);
+
+        if mesh_key.contains(MeshPipelineKey::NORMAL_PREPASS) {
+            shader_defs.push("NORMAL_PREPASS".into());
+        }
+        if mesh_key.contains(MeshPipelineKey::MOTION_VECTOR_PREPASS) {
+            shader_defs.push("MOTION_VECTOR_PREPASS".into());
+        }
+        if mesh_key.contains(MeshPipelineKey::DEPTH_CLAMP_ORTHO) {
+            shader_defs.push("DEPTH_CLAMP_ORTHO".into());
+        }
+        // normals in slot 0 and motion vectors in slot 1, like bevy's prepass
+        let mut targets = vec![
+            mesh_key
+                .contains(MeshPipelineKey::NORMAL_PREPASS)
+                .then_some(ColorTargetState {
+                    format: NORMAL_PREPASS_FORMAT,
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                }),
+            mesh_key
+                .contains(MeshPipelineKey::MOTION_VECTOR_PREPASS)
+                .then_some(ColorTargetState {
+                    format: MOTION_VECTOR_PREPASS_FORMAT,
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                }),
+        ];
+        if targets.iter().all(Option::is_none) {
+            targets.clear();
+        }
+        // the orthographic depth of directional lights is clamped in the fragment shader
+        let fragment_required =
+            !targets.is_empty() || mesh_key.contains(MeshPipelineKey::DEPTH_CLAMP_ORTHO);
+        if fragment_required {
+            shader_defs.push("PREPASS_FRAGMENT".into());
+        }
+        let fragment = fragment_required.then(|| FragmentState {
+            shader: self.grass_pipeline.shader.clone(),
+            shader_defs: [shader_defs.clone(), vec!["FRAGMENT_STAGE".into()]].concat(),
+            entry_point: "fragment".into(),
+            targets,
+        });
+
+        let vertex_buffer_layout =
+            layout.get_layout(&[Mesh::ATTRIBUTE_POSITION.at_shader_location(0)])?;
+        Ok(RenderPipelineDescriptor {
+            label: Some("Grass Prepass Pipeline".into()),
+            layout: layouts,
+            push_constant_ranges: Vec::new(),
+            vertex: VertexState {
+                shader: self.grass_pipeline.shader.clone(),
+                shader_defs,
+                entry_point: "vertex".into(),
+                buffers: vec![
+                    vertex_buffer_layout,
+                    instance_buffer_layout(key.gpu_culling),
+                ],
+            },
+            fragment,
+            primitive: PrimitiveState {
+                topology: mesh_key.primitive_topology(),
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: DEPTH_PREPASS_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: mesh_key.msaa_samples(),
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        })
+    }
+}
@@ -0,0 +1,185 @@
+use bevy::{
+    core_pipeline::core_3d::Opaque3d,
+    pbr::MeshUniform,
+    prelude::*,
+    render::{primitives::Aabb, render_phase::RenderPhase, view::ExtractedView},
+    utils::HashMap,
+};
+
+use crate::bundle::{GrassFade, GrassLod, LodLevel};
+
+/// The [`LodLevel`]s of the grass chunks selected for the current frame
+///
+/// Chunks without a [`GrassLod`] or closer than all of its levels are drawn in full detail and have no entry.
+/// Chunks beyond the end of their [`GrassFade`] are hidden.
+#[derive(Resource, Default)]
+pub(crate) struct GrassLods {
+    /// The levels of the chunks for each camera
+    views: HashMap<Entity, HashMap<Entity, LodLevel>>,
+    /// The levels of the chunks for the shadow views, which are the most detailed ones of all cameras
+    shadows: HashMap<Entity, LodLevel>,
+}
+impl GrassLods {
+    /// Returns the level of the `chunk` in the `view`
+    fn level(&self, view: Entity, chunk: Entity) -> Option<&LodLevel> {
+        match self.views.get(&view) {
+            Some(chunks) => chunks.get(&chunk),
+            None => self.shadows.get(&chunk),
+        }
+    }
+    /// Returns the mesh of the `chunk` in the `view`
+    ///
+    /// Returns `None` if the chunk is hidden in the view
+    pub(crate) fn mesh<'a>(
+        &'a self,
+        view: Entity,
+        chunk: Entity,
+        mesh: &'a Handle<Mesh>,
+    ) -> Option<&'a Handle<Mesh>> {
+        match self.level(view, chunk) {
+            Some(level) if level.density <= 0. => None,
+            Some(level) => Some(level.mesh.as_ref().unwrap_or(mesh)),
+            None => Some(mesh),
+        }
+    }
+    /// Returns how many of the `blades` of the `chunk` are drawn in the `view`
+    pub(crate) fn blade_count(&self, view: Entity, chunk: Entity, blades: u32) -> u32 {
+        match self.level(view, chunk) {
+            Some(level) => {
+                let count = (blades as f32 * level.density.clamp(0., 1.)).ceil() as u32;
+                count.min(blades)
+            }
+            None => blades,
+        }
+    }
+}
+
+/// Selects the [`LodLevel`] of each grass chunk for every camera
+#[allow(clippy::type_complexity)]
+pub(crate) fn select_grass_lods(
+    mut lods: ResMut<GrassLods>,
+    chunks: Query<
+        (
+            Entity,
+            Option<&GrassLod>,
+            Option<&GrassFade>,
+            &MeshUniform,
+            &Aabb,
+        ),
+        Or<(With<GrassLod>, With<GrassFade>)>,
+    >,
+    views: Query<(Entity, &ExtractedView), With<RenderPhase<Opaque3d>>>,
+) {
+    lods.views.clear();
+    lods.shadows.clear();
+    if chunks.is_empty() {
+        return;
+    }
+    let mut closest: HashMap<Entity, f32> = HashMap::new();
+    for (view_entity, view) in &views {
+        let camera = view.transform.translation();
+        let mut levels = HashMap::new();
+        for (chunk, lod, fade, mesh_uniform, aabb) in &chunks {
+            let distance = chunk_distance(camera, mesh_uniform.transform, aabb);
+            closest
+                .entry(chunk)
+                .and_modify(|closest| *closest = closest.min(distance))
+                .or_insert(distance);
+            if let Some(level) = chunk_level(lod, fade, distance) {
+                levels.insert(chunk, level);
+            }
+        }
+        lods.views.insert(view_entity, levels);
+    }
+    for (chunk, lod, fade, ..) in &chunks {
+        let level = closest
+            .get(&chunk)
+            .and_then(|distance| chunk_level(lod, fade, *distance));
+        if let Some(level) = level {
+            lods.shadows.insert(chunk, level);
+        }
+    }
+}
+
+/// Returns the level of a chunk at the given `distance`
+fn chunk_level(
+    lod: Option<&GrassLod>,
+    fade: Option<&GrassFade>,
+    distance: f32,
+) -> Option<LodLevel> {
+    match fade {
+        // all blades of the chunk are faded out
+        Some(fade) if distance >= fade.end => Some(LodLevel::new(fade.end, 0.)),
+        _ => lod.and_then(|lod| lod.level(distance)).cloned(),
+    }
+}
+
+/// Returns the distances and densities of the closest levels of a [`GrassLod`] as used by the shaders
+///
+/// Unused levels never drop any blades
+pub(crate) fn shader_levels(lod: Option<&GrassLod>) -> (Vec4, Vec4) {
+    let mut distances = Vec4::splat(f32::MAX);
+    let mut densities = Vec4::ONE;
+    if let Some(lod) = lod {
+        let mut levels: Vec<&LodLevel> = lod.levels.iter().collect();
+        levels.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        for (i, level) in levels.iter().take(GrassLod::SMOOTH_LEVELS).enumerate() {
+            distances[i] = level.distance;
+            densities[i] = level.density;
+        }
+    }
+    (distances, densities)
+}
+
+/// The distance between the `camera` and the closest point of the [`Aabb`] of a chunk
+pub(crate) fn chunk_distance(camera: Vec3, transform: Mat4, aabb: &Aabb) -> f32 {
+    let local = transform.inverse().transform_point3(camera);
+    let min = Vec3::from(aabb.min());
+    let max = Vec3::from(aabb.max());
+    let closest = transform.transform_point3(local.clamp(min, max));
+    camera.distance(closest)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{Mat4, Vec3};
+    use bevy::render::primitives::Aabb;
+
+    use crate::bundle::{GrassFade, GrassLod, LodLevel};
+
+    #[test]
+    fn chunk_distance() {
+        let aabb = Aabb::from_min_max(Vec3::ZERO, Vec3::new(10., 1., 10.));
+        let transform = Mat4::from_translation(Vec3::new(100., 0., 0.));
+        // inside of the chunk
+        let distance = super::chunk_distance(Vec3::new(105., 0.5, 5.), transform, &aabb);
+        assert_eq!(distance, 0.);
+        // next to the chunk
+        let distance = super::chunk_distance(Vec3::new(90., 0.5, 5.), transform, &aabb);
+        assert!((distance - 10.).abs() < 1e-4);
+        // the scale of the chunk is taken into account
+        let transform = transform * Mat4::from_scale(Vec3::splat(2.));
+        let distance = super::chunk_distance(Vec3::new(130., 0.5, 5.), transform, &aabb);
+        assert!((distance - 10.).abs() < 1e-4);
+    }
+    #[test]
+    fn lod_level() {
+        let lod = GrassLod::new(vec![LodLevel::new(100., 0.1), LodLevel::new(20., 0.5)]);
+        assert_eq!(lod.level(10.), None);
+        assert_eq!(lod.level(20.), Some(&LodLevel::new(20., 0.5)));
+        assert_eq!(lod.level(99.), Some(&LodLevel::new(20., 0.5)));
+        assert_eq!(lod.level(1000.), Some(&LodLevel::new(100., 0.1)));
+
+        // faded out chunks are hidden
+        let fade = GrassFade {
+            start: 200.,
+            end: 300.,
+        };
+        let level = super::chunk_level(Some(&lod), Some(&fade), 250.);
+        assert_eq!(level, Some(LodLevel::new(100., 0.1)));
+        let level = super::chunk_level(Some(&lod), Some(&fade), 300.);
+        assert_eq!(level.map(|level| level.density), Some(0.));
+        let level = super::chunk_level(None, Some(&fade), 10.);
+        assert_eq!(level, None);
+    }
+}
@@ -3,20 +3,26 @@ use std::mem;
 use std::num::NonZeroU64;
 use std::ops::Mul;
 
-use super::cache::UniformBuffer;
-use super::grass_pipeline::GrassPipeline;
-use crate::bundle::WarblerHeight;
+use super::cache::{ChunkCache, UniformBuffer};
+use super::displacement::{GpuGrassDisplacers, GpuTrampleMaps, ShaderDisplacers};
+use super::grass_pipeline::{GrassPipeline, TextureBinding};
+use super::lod::shader_levels;
+use crate::bundle::{
+    BladeSizeVariation, GrassFade, GrassLighting, GrassLod, MapSampling, WarblerHeight,
+};
+use crate::dithering::{DitheredBuffer, GpuDitheredBuffers};
 use crate::map::{NormalMap, YMap};
 use crate::prelude::GrassColor;
+use crate::wind::ShaderWindZones;
 use crate::{GrassConfiguration, GrassNoiseTexture};
 use bevy::prelude::*;
 use bevy::render::primitives::Aabb;
 use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_resource::{
-    BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, BufferBinding,
-    BufferInitDescriptor, BufferUsages, TextureViewId,
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, Buffer, BufferBinding,
+    BufferDescriptor, BufferId, BufferInitDescriptor, BufferUsages, TextureViewId,
 };
-use bevy::render::renderer::RenderDevice;
+use bevy::render::renderer::{RenderDevice, RenderQueue};
 use bevy::render::texture::FallbackImage;
 use bytemuck::{Pod, Zeroable};
 #[derive(Component)]
@@ -34,7 +40,22 @@ impl<T> BindGroupBuffer<T> {
 }
 #[derive(Component)]
 pub(crate) struct UniformHeightFlag;
+/// Stores how the texture map `T` of a chunk is read in the shader
+#[derive(Component)]
+pub(crate) struct MapTextureBinding<T> {
+    pub binding: TextureBinding,
+    _inner: PhantomData<T>,
+}
+impl<T> MapTextureBinding<T> {
+    pub fn new(binding: TextureBinding) -> Self {
+        MapTextureBinding {
+            binding,
+            _inner: PhantomData,
+        }
+    }
+}
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub(crate) fn prepare_height_buffer(
     mut commands: Commands,
     pipeline: Res<GrassPipeline>,
@@ -42,58 +63,107 @@ pub(crate) fn prepare_height_buffer(
     images: Res<RenderAssets<Image>>,
 
     render_device: Res<RenderDevice>,
-    inserted_grass: Query<(Entity, &WarblerHeight)>,
+    gpu_dithered: Res<GpuDitheredBuffers>,
+    mut cache: Local<ChunkCache<(ShaderBladeUniform, Option<(TextureViewId, TextureBinding)>)>>,
+    inserted_grass: Query<(
+        Entity,
+        &WarblerHeight,
+        Option<&MapSampling>,
+        Option<&BladeSizeVariation>,
+        Option<&GrassFade>,
+        Option<(&GrassLod, &Handle<DitheredBuffer>)>,
+    )>,
 ) {
-    for (entity, height) in inserted_grass.iter() {
-        match height.clone() {
-            WarblerHeight::Uniform(height) => {
-                let layout = pipeline.uniform_height_layout.clone();
+    cache.next_frame();
+    for (entity, height, sampling, variation, fade, lod) in inserted_grass.iter() {
+        let mut blade_uniform =
+            ShaderBladeUniform::new(height, variation.copied().unwrap_or_default());
+        if let Some(fade) = fade {
+            blade_uniform = blade_uniform.with_fade(fade);
+        }
+        if let Some((lod, dithered)) = lod {
+            let blade_count = gpu_dithered
+                .get(dithered)
+                .map_or(0, |buffer| buffer.instances);
+            blade_uniform = blade_uniform.with_lod(lod, blade_count);
+        }
+        let texture = match height {
+            WarblerHeight::Uniform(_) => None,
+            WarblerHeight::Texture {
+                texture: heights_texture,
+                ..
+            } => Some(images.get(heights_texture).unwrap_or(&fallback_img.d2)),
+        };
+        let texture_key = texture.map(|tex| {
+            let binding =
+                TextureBinding::new(tex.texture_format, sampling.copied().unwrap_or_default());
+            (tex.texture_view.id(), binding)
+        });
+        let bind_group = cache.get_or_create(entity, (blade_uniform, texture_key), |_| {
+            let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: "grass blade height buffer".into(),
+                contents: bytemuck::bytes_of(&blade_uniform),
+                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+            });
+            let buffer_binding = BindingResource::Buffer(BufferBinding {
+                buffer: &buffer,
+                offset: 0,
+                size: NonZeroU64::new(mem::size_of::<ShaderBladeUniform>() as u64),
+            });
+            match (texture, texture_key) {
+                (Some(tex), Some((_, binding))) => {
+                    let layout = pipeline.heights_texture_layouts.get(binding);
 
-                let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-                    label: "grass blade height buffer".into(),
-                    contents: bytemuck::bytes_of(&ShaderHeightUniform::from(height)),
-                    usage: BufferUsages::VERTEX | BufferUsages::COPY_DST | BufferUsages::UNIFORM,
-                });
-                let bind_group_descriptor = BindGroupDescriptor {
-                    label: Some("grass blade height bind group"),
-                    layout: &layout,
-                    entries: &[BindGroupEntry {
-                        binding: 0,
-                        resource: BindingResource::Buffer(BufferBinding {
-                            buffer: &buffer,
-                            offset: 0,
-                            size: NonZeroU64::new(mem::size_of::<ShaderHeightUniform>() as u64),
-                        }),
-                    }],
-                };
-                let bind_group = render_device.create_bind_group(&bind_group_descriptor);
+                    let mut entries = vec![
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(&tex.texture_view),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: buffer_binding,
+                        },
+                    ];
+                    if binding == TextureBinding::Filtered {
+                        entries.push(BindGroupEntry {
+                            binding: 2,
+                            resource: BindingResource::Sampler(&pipeline.map_sampler),
+                        });
+                    }
+                    let bind_group_descriptor = BindGroupDescriptor {
+                        label: Some("grass height map bind group"),
+                        layout,
+                        entries: &entries,
+                    };
+                    render_device.create_bind_group(&bind_group_descriptor)
+                }
+                _ => {
+                    let layout = pipeline.uniform_height_layout.clone();
+
+                    let bind_group_descriptor = BindGroupDescriptor {
+                        label: Some("grass blade height bind group"),
+                        layout: &layout,
+                        entries: &[BindGroupEntry {
+                            binding: 0,
+                            resource: buffer_binding,
+                        }],
+                    };
+                    render_device.create_bind_group(&bind_group_descriptor)
+                }
+            }
+        });
+        match texture_key {
+            None => {
                 commands
                     .entity(entity)
                     .insert(BindGroupBuffer::<WarblerHeight>::new(bind_group))
                     .insert(UniformHeightFlag);
             }
-            WarblerHeight::Texture(heights_texture) => {
-                let layout = pipeline.heights_texture_layout.clone();
-
-                let tex = if let Some(tex) = images.get(&heights_texture) {
-                    &tex.texture_view
-                } else {
-                    &fallback_img.d2.texture_view
-                };
-
-                let bind_group_descriptor = BindGroupDescriptor {
-                    label: Some("grass height map bind group"),
-                    layout: &layout,
-                    entries: &[BindGroupEntry {
-                        binding: 0,
-                        resource: BindingResource::TextureView(tex),
-                    }],
-                };
-
-                let bind_group = render_device.create_bind_group(&bind_group_descriptor);
-                commands
-                    .entity(entity)
-                    .insert(BindGroupBuffer::<WarblerHeight>::new(bind_group));
+            Some((_, binding)) => {
+                commands.entity(entity).insert((
+                    BindGroupBuffer::<WarblerHeight>::new(bind_group),
+                    MapTextureBinding::<WarblerHeight>::new(binding),
+                ));
             }
         };
     }
@@ -102,65 +172,79 @@ pub(crate) fn prepare_grass_color(
     mut commands: Commands,
     pipeline: Res<GrassPipeline>,
     render_device: Res<RenderDevice>,
-    inserted_grass: Query<(Entity, &GrassColor)>,
+    mut cache: Local<ChunkCache<ShaderColorUniform>>,
+    inserted_grass: Query<(Entity, &GrassColor, Option<&GrassLighting>)>,
 ) {
-    for (entity, color) in inserted_grass.iter() {
-        let layout = pipeline.color_layout.clone();
+    cache.next_frame();
+    for (entity, color, lighting) in inserted_grass.iter() {
+        let color_uniform = ShaderColorUniform::new(color, lighting.copied().unwrap_or_default());
+        let bind_group = cache.get_or_create(entity, color_uniform, |color_uniform| {
+            let layout = pipeline.color_layout.clone();
 
-        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: "grass color buffer".into(),
-            contents: bytemuck::bytes_of(&ShaderColorUniform::from(color)),
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+            let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: "grass color buffer".into(),
+                contents: bytemuck::bytes_of(color_uniform),
+                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+            });
+            let bind_group_descriptor = BindGroupDescriptor {
+                label: Some("grass color bind group"),
+                layout: &layout,
+                entries: &[BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &buffer,
+                        offset: 0,
+                        size: NonZeroU64::new(mem::size_of::<ShaderColorUniform>() as u64),
+                    }),
+                }],
+            };
+            render_device.create_bind_group(&bind_group_descriptor)
         });
-        let bind_group_descriptor = BindGroupDescriptor {
-            label: Some("grass color bind group"),
-            layout: &layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: BindingResource::Buffer(BufferBinding {
-                    buffer: &buffer,
-                    offset: 0,
-                    size: NonZeroU64::new(mem::size_of::<ShaderColorUniform>() as u64),
-                }),
-            }],
-        };
-        let bind_group = render_device.create_bind_group(&bind_group_descriptor);
         commands
             .entity(entity)
             .insert(BindGroupBuffer::<GrassColor>::new(bind_group));
     }
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub(crate) fn prepare_y_map_buffer(
     mut commands: Commands,
     render_device: Res<RenderDevice>,
     pipeline: Res<GrassPipeline>,
     fallback_img: Res<FallbackImage>,
     images: Res<RenderAssets<Image>>,
-    inserted_grass: Query<(Entity, &YMap, &Aabb)>,
+    trample_maps: Res<GpuTrampleMaps>,
+    mut cache: Local<ChunkCache<(ShaderAabb, TextureViewId, TextureBinding, TextureViewId)>>,
+    inserted_grass: Query<(Entity, &YMap, &Aabb, Option<&MapSampling>)>,
 ) {
-    let layout = pipeline.y_map_layout.clone();
+    cache.next_frame();
+    for (entity, y_map, aabb, sampling) in inserted_grass.iter() {
+        let y_map_texture = images.get(&y_map.y_map).unwrap_or(&fallback_img.d2);
+        let binding = TextureBinding::new(
+            y_map_texture.texture_format,
+            sampling.copied().unwrap_or_default(),
+        );
+        let shader_aabb = ShaderAabb::from(Vec3::from(aabb.half_extents.mul(2.)));
+        let trample_texture = trample_maps.view(entity);
+        let key = (
+            shader_aabb,
+            y_map_texture.texture_view.id(),
+            binding,
+            trample_texture.id(),
+        );
+        let bind_group = cache.get_or_create(entity, key, |_| {
+            let layout = pipeline.y_map_layouts.get(binding);
 
-    for (entity, y_map, aabb) in inserted_grass.iter() {
-        let y_map_texture = if let Some(tex) = images.get(&y_map.y_map) {
-            &tex.texture_view
-        } else {
-            &fallback_img.d2.texture_view
-        };
+            let aabb_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("aabb buffer"),
+                contents: bytemuck::bytes_of(&shader_aabb),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            });
 
-        let aabb_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: Some("aabb buffer"),
-            contents: bytemuck::bytes_of(&ShaderAabb::from(Vec3::from(aabb.half_extents.mul(2.)))),
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-        });
-
-        let bind_group_descriptor = BindGroupDescriptor {
-            label: Some("grass y-map bind group"),
-            layout: &layout,
-            entries: &[
+            let mut entries = vec![
                 BindGroupEntry {
                     binding: 0,
-                    resource: BindingResource::TextureView(y_map_texture),
+                    resource: BindingResource::TextureView(&y_map_texture.texture_view),
                 },
                 BindGroupEntry {
                     binding: 1,
@@ -170,13 +254,28 @@ pub(crate) fn prepare_y_map_buffer(
                         size: None,
                     }),
                 },
-            ],
-        };
-
-        let bind_group = render_device.create_bind_group(&bind_group_descriptor);
-        commands
-            .entity(entity)
-            .insert(BindGroupBuffer::<YMap>::new(bind_group));
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(trample_texture),
+                },
+            ];
+            if binding == TextureBinding::Filtered {
+                entries.push(BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Sampler(&pipeline.map_sampler),
+                });
+            }
+            let bind_group_descriptor = BindGroupDescriptor {
+                label: Some("grass y-map bind group"),
+                layout,
+                entries: &entries,
+            };
+            render_device.create_bind_group(&bind_group_descriptor)
+        });
+        commands.entity(entity).insert((
+            BindGroupBuffer::<YMap>::new(bind_group),
+            MapTextureBinding::<YMap>::new(binding),
+        ));
     }
 }
 pub(crate) fn prepare_normal_map_buffer(
@@ -185,51 +284,106 @@ pub(crate) fn prepare_normal_map_buffer(
     pipeline: Res<GrassPipeline>,
     fallback_img: Res<FallbackImage>,
     images: Res<RenderAssets<Image>>,
-    inserted_grass: Query<(Entity, &NormalMap)>,
+    mut cache: Local<ChunkCache<(TextureViewId, TextureBinding)>>,
+    inserted_grass: Query<(Entity, &NormalMap, Option<&MapSampling>)>,
 ) {
-    let layout = pipeline.normal_map_layout.clone();
+    cache.next_frame();
+    for (entity, normal_map, sampling) in inserted_grass.iter() {
+        let normal_map_texture = images
+            .get(&normal_map.normal_map)
+            .unwrap_or(&fallback_img.d2);
+        let binding = TextureBinding::new(
+            normal_map_texture.texture_format,
+            sampling.copied().unwrap_or_default(),
+        );
+        let key = (normal_map_texture.texture_view.id(), binding);
+        let bind_group = cache.get_or_create(entity, key, |_| {
+            let layout = pipeline.normal_map_layouts.get(binding);
 
-    for (entity, normal_map) in inserted_grass.iter() {
-        let normal_map_texture = if let Some(tex) = images.get(&normal_map.normal_map) {
-            &tex.texture_view
-        } else {
-            &fallback_img.d2.texture_view
-        };
-
-        let bind_group_descriptor = BindGroupDescriptor {
-            label: Some("grass normal-map bind group"),
-            layout: &layout,
-            entries: &[BindGroupEntry {
+            let mut entries = vec![BindGroupEntry {
                 binding: 0,
-                resource: BindingResource::TextureView(normal_map_texture),
-            }],
-        };
+                resource: BindingResource::TextureView(&normal_map_texture.texture_view),
+            }];
+            if binding == TextureBinding::Filtered {
+                entries.push(BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&pipeline.map_sampler),
+                });
+            }
+            let bind_group_descriptor = BindGroupDescriptor {
+                label: Some("grass normal-map bind group"),
+                layout,
+                entries: &entries,
+            };
+            render_device.create_bind_group(&bind_group_descriptor)
+        });
+        commands.entity(entity).insert((
+            BindGroupBuffer::<NormalMap>::new(bind_group),
+            MapTextureBinding::<NormalMap>::new(binding),
+        ));
+    }
+}
+/// The [`WindZone`](crate::wind::WindZone)s of the current frame and the uniform buffer they are written to
+#[derive(Resource)]
+pub(crate) struct GpuWindZones {
+    pub zones: ShaderWindZones,
+    pub buffer: Buffer,
+    /// The zones currently stored in the buffer
+    written: Option<ShaderWindZones>,
+}
+impl FromWorld for GpuWindZones {
+    fn from_world(world: &mut World) -> Self {
+        let buffer = world
+            .resource::<RenderDevice>()
+            .create_buffer(&BufferDescriptor {
+                label: Some("grass wind zone buffer"),
+                size: mem::size_of::<ShaderWindZones>() as u64,
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+        GpuWindZones {
+            zones: ShaderWindZones::default(),
+            buffer,
+            written: None,
+        }
+    }
+}
 
-        let bind_group = render_device.create_bind_group(&bind_group_descriptor);
-        commands
-            .entity(entity)
-            .insert(BindGroupBuffer::<NormalMap>::new(bind_group));
+/// Writes the extracted wind zones to their buffer if they changed
+pub(crate) fn prepare_wind_zones(
+    mut wind_zones: ResMut<GpuWindZones>,
+    render_queue: Res<RenderQueue>,
+) {
+    if wind_zones.written == Some(wind_zones.zones) {
+        return;
     }
+    render_queue.write_buffer(&wind_zones.buffer, 0, bytemuck::bytes_of(&wind_zones.zones));
+    wind_zones.written = Some(wind_zones.zones);
 }
+
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn prepare_uniform_buffers(
     pipeline: Res<GrassPipeline>,
     region_config: Res<GrassConfiguration>,
     noise_config: Res<GrassNoiseTexture>,
+    wind_zones: Res<GpuWindZones>,
+    displacers: Res<GpuGrassDisplacers>,
     fallback_img: Res<FallbackImage>,
     render_device: Res<RenderDevice>,
     mut uniform_buffer: ResMut<UniformBuffer>,
     images: Res<RenderAssets<Image>>,
-    mut last_texture_id: Local<Option<TextureViewId>>,
+    mut last_ids: Local<Option<(TextureViewId, BufferId)>>,
 ) {
     let texture = &images
         .get(&noise_config.0)
         .unwrap_or(&fallback_img.d2)
         .texture_view;
-    if !region_config.is_changed() && Some(texture.id()) == *last_texture_id {
+    // the buffer of the displacers grows with the amount of displaced chunks
+    let ids = (texture.id(), displacers.buffer.id());
+    if !region_config.is_changed() && Some(ids) == *last_ids {
         return;
     }
-    *last_texture_id = Some(texture.id());
+    *last_ids = Some(ids);
 
     let shader_config = ShaderRegionConfiguration::from(region_config.as_ref());
     let config_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
@@ -255,6 +409,18 @@ pub(crate) fn prepare_uniform_buffers(
                 binding: 1,
                 resource: BindingResource::TextureView(texture),
             },
+            BindGroupEntry {
+                binding: 2,
+                resource: wind_zones.buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: &displacers.buffer,
+                    offset: 0,
+                    size: Some(ShaderDisplacers::SIZE),
+                }),
+            },
         ],
     };
     let bind_group = render_device.create_bind_group(&bind_group_descriptor);
@@ -265,22 +431,38 @@ pub(crate) fn prepare_uniform_buffers(
 #[repr(C)]
 struct ShaderRegionConfiguration {
     wind: Vec2,
+    yaw_variation: f32,
+    wind_noise_scale: f32,
+    wind_scroll_speed: f32,
+    gust_frequency: f32,
+    gust_amplitude: f32,
+    turbulence: f32,
+    sway_stiffness: f32,
     /// Wasm requires shader uniforms to be aligned to 16 bytes
-    _wasm_padding: Vec2,
+    _wasm_padding: f32,
+    _wasm_padding_2: Vec2,
 }
 
 impl From<&GrassConfiguration> for ShaderRegionConfiguration {
     fn from(config: &GrassConfiguration) -> Self {
         Self {
             wind: config.wind,
-            _wasm_padding: Vec2::ZERO,
+            yaw_variation: config.yaw_variation,
+            wind_noise_scale: config.wind_noise_scale,
+            wind_scroll_speed: config.wind_scroll_speed,
+            gust_frequency: config.gust_frequency,
+            gust_amplitude: config.gust_amplitude,
+            turbulence: config.turbulence,
+            sway_stiffness: config.sway_stiffness,
+            _wasm_padding: 0.,
+            _wasm_padding_2: Vec2::ZERO,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
 #[repr(C)]
-struct ShaderAabb {
+pub(crate) struct ShaderAabb {
     vect: Vec3,
     /// Wasm requires shader uniforms to be aligned to 16 bytes
     _wasm_padding: f32,
@@ -295,33 +477,92 @@ impl From<Vec3> for ShaderAabb {
     }
 }
 
-#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
 #[repr(C)]
-struct ShaderColorUniform {
+pub(crate) struct ShaderColorUniform {
     main_color: Vec4,
     bottom_color: Vec4,
+    perceptual_roughness: f32,
+    reflectance: f32,
+    /// Wasm requires shader uniforms to be aligned to 16 bytes
+    _wasm_padding: Vec2,
 }
-impl From<&GrassColor> for ShaderColorUniform {
-    fn from(config: &GrassColor) -> Self {
-        Self {
-            main_color: config.main_color.into(),
-            bottom_color: config.bottom_color.into(),
+impl ShaderColorUniform {
+    pub(crate) fn new(config: &GrassColor, lighting: GrassLighting) -> Self {
+        match lighting {
+            GrassLighting::Unlit => Self {
+                main_color: config.main_color.into(),
+                bottom_color: config.bottom_color.into(),
+                perceptual_roughness: 1.,
+                reflectance: 0.,
+                _wasm_padding: Vec2::ZERO,
+            },
+            // the lighting is calculated in linear space
+            GrassLighting::Lit {
+                perceptual_roughness,
+                reflectance,
+            } => Self {
+                main_color: config.main_color.as_linear_rgba_f32().into(),
+                bottom_color: config.bottom_color.as_linear_rgba_f32().into(),
+                perceptual_roughness,
+                reflectance,
+                _wasm_padding: Vec2::ZERO,
+            },
         }
     }
 }
-#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
 #[repr(C)]
-struct ShaderHeightUniform {
+pub(crate) struct ShaderBladeUniform {
+    /// The minimum and maximum factor of the blade height
+    height_variation: Vec2,
+    /// The minimum and maximum factor of the blade width
+    width_variation: Vec2,
+    /// The minimum and maximum height of the blades if they have a height texture
+    height_range: Vec2,
+    /// The height of the blades if they have a uniform height
     height: f32,
+    /// The distance over which the blades dropped by a level of detail shrink
+    lod_transition: f32,
+    /// The distances at which the blades start and end to fade out
+    fade: Vec2,
+    /// The amount of blades of the chunk, used to derive the rank of a blade from its index
+    blade_count: f32,
     /// Wasm requires shader uniforms to be aligned to 16 bytes
-    _wasm_padding: Vec3,
+    _wasm_padding: f32,
+    /// The distances of the closest levels of detail
+    lod_distances: Vec4,
+    /// The fraction of the blades drawn by the closest levels of detail
+    lod_densities: Vec4,
 }
 
-impl From<f32> for ShaderHeightUniform {
-    fn from(height: f32) -> Self {
+impl ShaderBladeUniform {
+    pub(crate) fn new(height: &WarblerHeight, variation: BladeSizeVariation) -> Self {
+        let (height, height_range) = match height {
+            WarblerHeight::Uniform(height) => (*height, Vec2::ZERO),
+            WarblerHeight::Texture { min, max, .. } => (0., Vec2::new(*min, *max)),
+        };
         Self {
+            height_variation: Vec2::new(variation.min_height, variation.max_height),
+            width_variation: Vec2::new(variation.min_width, variation.max_width),
+            height_range,
             height,
-            _wasm_padding: Vec3::ZERO,
+            lod_transition: 0.,
+            fade: Vec2::splat(f32::MAX),
+            blade_count: 1.,
+            _wasm_padding: 0.,
+            lod_distances: Vec4::splat(f32::MAX),
+            lod_densities: Vec4::ONE,
         }
     }
+    pub(crate) fn with_fade(mut self, fade: &GrassFade) -> Self {
+        self.fade = Vec2::new(fade.start, fade.end);
+        self
+    }
+    pub(crate) fn with_lod(mut self, lod: &GrassLod, blade_count: usize) -> Self {
+        (self.lod_distances, self.lod_densities) = shader_levels(Some(lod));
+        self.lod_transition = lod.transition;
+        self.blade_count = blade_count as f32;
+        self
+    }
 }
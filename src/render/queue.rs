@@ -1,16 +1,57 @@
 use bevy::core_pipeline::core_3d::Opaque3d;
-use bevy::pbr::{MeshPipelineKey, MeshUniform};
+use bevy::core_pipeline::prepass::{
+    DepthPrepass, MotionVectorPrepass, NormalPrepass, Opaque3dPrepass,
+};
+use bevy::ecs::query::WorldQuery;
+use bevy::pbr::{
+    CascadesVisibleEntities, CubemapVisibleEntities, ExtractedDirectionalLight,
+    ExtractedPointLight, LightEntity, MeshPipelineKey, MeshUniform, Shadow, ViewLightEntities,
+};
 use bevy::prelude::*;
 use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_phase::{DrawFunctions, RenderPhase};
 use bevy::render::render_resource::{PipelineCache, SpecializedMeshPipelines};
-use bevy::render::view::ExtractedView;
+use bevy::render::view::{ExtractedView, VisibleEntities};
 
+use crate::bundle::{DitheringSpace, GrassFade, GrassLighting, GrassLod, NoPrepass, WarblerHeight};
 use crate::dithering::DitheredBuffer;
+use crate::map::{NormalMap, YMap};
 
-use super::grass_pipeline::{GrassPipeline, GrassRenderKey};
-use super::prepare::UniformHeightFlag;
-use super::GrassDrawCall;
+use super::batch::GrassBatches;
+use super::culling::GrassCulling;
+use super::grass_pipeline::{GrassPipeline, GrassPrepassPipeline, GrassRenderKey};
+use super::lod::GrassLods;
+use super::prepare::{MapTextureBinding, UniformHeightFlag};
+use super::{GrassBatchDrawCall, GrassDrawCall, GrassPrepassDrawCall};
+
+/// The components of a grass chunk which specialize its pipelines
+#[derive(WorldQuery)]
+pub(crate) struct GrassKeyQuery {
+    uniform_height: Option<&'static UniformHeightFlag>,
+    space: Option<&'static DitheringSpace>,
+    lighting: Option<&'static GrassLighting>,
+    fade: Option<&'static GrassFade>,
+    lod: Option<&'static GrassLod>,
+    y_map_binding: Option<&'static MapTextureBinding<YMap>>,
+    normal_map_binding: Option<&'static MapTextureBinding<NormalMap>>,
+    height_binding: Option<&'static MapTextureBinding<WarblerHeight>>,
+}
+impl GrassKeyQueryItem<'_> {
+    pub(crate) fn key(&self, mesh_key: MeshPipelineKey) -> GrassRenderKey {
+        let mut grass_key = GrassRenderKey::from(mesh_key);
+        grass_key.uniform_height = self.uniform_height.is_some();
+        grass_key.world_space = self.space == Some(&DitheringSpace::World);
+        grass_key.lit = matches!(self.lighting, Some(GrassLighting::Lit { .. }));
+        grass_key.distance_fade = self.fade.is_some() || self.lod.is_some();
+        grass_key.y_map = self.y_map_binding.map(|b| b.binding).unwrap_or_default();
+        grass_key.normal_map = self
+            .normal_map_binding
+            .map(|b| b.binding)
+            .unwrap_or_default();
+        grass_key.height = self.height_binding.map(|b| b.binding).unwrap_or_default();
+        grass_key
+    }
+}
 
 #[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub(crate) fn queue_grass_buffers(
@@ -20,33 +61,62 @@ pub(crate) fn queue_grass_buffers(
     mut pipelines: ResMut<SpecializedMeshPipelines<GrassPipeline>>,
     pipeline_cache: Res<PipelineCache>,
     meshes: Res<RenderAssets<Mesh>>,
+    lods: Res<GrassLods>,
+    culling: Res<GrassCulling>,
+    batches: Res<GrassBatches>,
     material_meshes: Query<
-        (
-            Entity,
-            &MeshUniform,
-            &Handle<Mesh>,
-            Option<&UniformHeightFlag>,
-        ),
+        (Entity, &MeshUniform, &Handle<Mesh>, GrassKeyQuery),
         With<Handle<DitheredBuffer>>,
     >,
-    mut views: Query<(&ExtractedView, &mut RenderPhase<Opaque3d>)>,
+    mut views: Query<(Entity, &ExtractedView, &mut RenderPhase<Opaque3d>)>,
 ) {
     let draw_custom = opaque_3d_draw_functions
         .read()
         .get_id::<GrassDrawCall>()
         .unwrap();
+    let draw_batch = opaque_3d_draw_functions
+        .read()
+        .get_id::<GrassBatchDrawCall>()
+        .unwrap();
 
     let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
 
-    for (view, mut opaque_phase) in &mut views {
+    for (view_entity, view, mut opaque_phase) in &mut views {
         let view_key = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
         let rangefinder = view.rangefinder3d();
-        for (entity, mesh_uniform, mesh_handle, has_uniform_height) in material_meshes.iter() {
+        for (entity, mesh_handle, grass_key) in batches.view_batches(view_entity) {
+            let (Ok((_, mesh_uniform, ..)), Some(mesh)) =
+                (material_meshes.get(entity), meshes.get(mesh_handle))
+            else {
+                continue;
+            };
+            let mut grass_key = grass_key.clone();
+            grass_key.mesh_key |=
+                view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+            grass_key.batched = true;
+            let pipeline = pipelines
+                .specialize(&pipeline_cache, &grass_pipeline, grass_key, &mesh.layout)
+                .unwrap();
+            opaque_phase.add(Opaque3d {
+                entity,
+                pipeline,
+                draw_function: draw_batch,
+                distance: rangefinder.distance(&mesh_uniform.transform),
+            });
+        }
+        for (entity, mesh_uniform, mesh_handle, grass) in material_meshes.iter() {
+            if batches.contains(view_entity, entity) {
+                continue;
+            }
+            // the level of detail might hide the chunk or swap its mesh
+            let Some(mesh_handle) = lods.mesh(view_entity, entity, mesh_handle) else {
+                continue;
+            };
             if let Some(mesh) = meshes.get(mesh_handle) {
                 let mesh_key =
                     view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
-                let mut grass_key = GrassRenderKey::from(mesh_key);
-                grass_key.uniform_height = has_uniform_height.is_some();
+                let mut grass_key = grass.key(mesh_key);
+                grass_key.gpu_culling = culling.get(view_entity, entity).is_some();
                 let pipeline = pipelines
                     .specialize(&pipeline_cache, &grass_pipeline, grass_key, &mesh.layout)
                     .unwrap();
@@ -60,3 +130,165 @@ pub(crate) fn queue_grass_buffers(
         }
     }
 }
+
+/// Queues the grass chunks visible to a light into its shadow phase
+///
+/// Chunks with a [`NotShadowCaster`](bevy::pbr::NotShadowCaster) component are never visible to lights.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub(crate) fn queue_grass_shadows(
+    shadow_draw_functions: Res<DrawFunctions<Shadow>>,
+    prepass_pipeline: Res<GrassPrepassPipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<GrassPrepassPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    lods: Res<GrassLods>,
+    casting_grass: Query<(&Handle<Mesh>, GrassKeyQuery), With<Handle<DitheredBuffer>>>,
+    view_lights: Query<(Entity, &ViewLightEntities)>,
+    mut view_light_shadow_phases: Query<(&LightEntity, &mut RenderPhase<Shadow>)>,
+    point_light_entities: Query<&CubemapVisibleEntities, With<ExtractedPointLight>>,
+    directional_light_entities: Query<&CascadesVisibleEntities, With<ExtractedDirectionalLight>>,
+    spot_light_entities: Query<&VisibleEntities, With<ExtractedPointLight>>,
+) {
+    let draw_shadow = shadow_draw_functions
+        .read()
+        .get_id::<GrassPrepassDrawCall>()
+        .unwrap();
+    for (view_entity, view_lights) in &view_lights {
+        for view_light_entity in view_lights.lights.iter().copied() {
+            let Ok((light_entity, mut shadow_phase)) =
+                view_light_shadow_phases.get_mut(view_light_entity)
+            else {
+                continue;
+            };
+            let is_directional_light = matches!(light_entity, LightEntity::Directional { .. });
+            let visible_entities = match light_entity {
+                LightEntity::Directional {
+                    light_entity,
+                    cascade_index,
+                } => directional_light_entities
+                    .get(*light_entity)
+                    .ok()
+                    .and_then(|cascades| cascades.entities.get(&view_entity))
+                    .and_then(|cascades| cascades.get(*cascade_index)),
+                LightEntity::Point {
+                    light_entity,
+                    face_index,
+                } => point_light_entities
+                    .get(*light_entity)
+                    .ok()
+                    .map(|faces| faces.get(*face_index)),
+                LightEntity::Spot { light_entity } => spot_light_entities.get(*light_entity).ok(),
+            };
+            let Some(visible_entities) = visible_entities else {
+                continue;
+            };
+            for entity in visible_entities.iter().copied() {
+                let Ok((mesh_handle, grass)) = casting_grass.get(entity) else {
+                    continue;
+                };
+                let Some(mesh_handle) = lods.mesh(view_light_entity, entity, mesh_handle) else {
+                    continue;
+                };
+                let Some(mesh) = meshes.get(mesh_handle) else {
+                    continue;
+                };
+                let mut mesh_key =
+                    MeshPipelineKey::from_primitive_topology(mesh.primitive_topology)
+                        | MeshPipelineKey::DEPTH_PREPASS;
+                if is_directional_light {
+                    mesh_key |= MeshPipelineKey::DEPTH_CLAMP_ORTHO;
+                }
+                let mut grass_key = grass.key(mesh_key);
+                // the distance to a light doesn't match the one to the cameras,
+                // so the shadows are cast by the blades in their full size
+                grass_key.distance_fade = false;
+                let pipeline = pipelines
+                    .specialize(&pipeline_cache, &prepass_pipeline, grass_key, &mesh.layout)
+                    .unwrap();
+                shadow_phase.add(Shadow {
+                    draw_function: draw_shadow,
+                    pipeline,
+                    entity,
+                    distance: 0.0,
+                });
+            }
+        }
+    }
+}
+
+/// Queues the visible grass chunks into the prepass of the views with a depth, normal or motion vector prepass
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub(crate) fn queue_grass_prepass(
+    prepass_draw_functions: Res<DrawFunctions<Opaque3dPrepass>>,
+    prepass_pipeline: Res<GrassPrepassPipeline>,
+    msaa: Res<Msaa>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<GrassPrepassPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    lods: Res<GrassLods>,
+    culling: Res<GrassCulling>,
+    grass_chunks: Query<
+        (&MeshUniform, &Handle<Mesh>, GrassKeyQuery),
+        (With<Handle<DitheredBuffer>>, Without<NoPrepass>),
+    >,
+    mut views: Query<(
+        Entity,
+        &ExtractedView,
+        &VisibleEntities,
+        &mut RenderPhase<Opaque3dPrepass>,
+        Option<&DepthPrepass>,
+        Option<&NormalPrepass>,
+        Option<&MotionVectorPrepass>,
+    )>,
+) {
+    let draw_prepass = prepass_draw_functions
+        .read()
+        .get_id::<GrassPrepassDrawCall>()
+        .unwrap();
+    for (
+        view_entity,
+        view,
+        visible_entities,
+        mut prepass_phase,
+        depth_prepass,
+        normal_prepass,
+        motion_vector_prepass,
+    ) in &mut views
+    {
+        let mut view_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
+        if depth_prepass.is_some() {
+            view_key |= MeshPipelineKey::DEPTH_PREPASS;
+        }
+        if normal_prepass.is_some() {
+            view_key |= MeshPipelineKey::NORMAL_PREPASS;
+        }
+        if motion_vector_prepass.is_some() {
+            view_key |= MeshPipelineKey::MOTION_VECTOR_PREPASS;
+        }
+        let rangefinder = view.rangefinder3d();
+        for entity in visible_entities.entities.iter().copied() {
+            let Ok((mesh_uniform, mesh_handle, grass)) = grass_chunks.get(entity) else {
+                continue;
+            };
+            let Some(mesh_handle) = lods.mesh(view_entity, entity, mesh_handle) else {
+                continue;
+            };
+            let Some(mesh) = meshes.get(mesh_handle) else {
+                continue;
+            };
+            let mesh_key =
+                view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+            let mut grass_key = grass.key(mesh_key);
+            grass_key.gpu_culling = culling.get(view_entity, entity).is_some();
+            let pipeline_id = pipelines
+                .specialize(&pipeline_cache, &prepass_pipeline, grass_key, &mesh.layout)
+                .unwrap();
+            prepass_phase.add(Opaque3dPrepass {
+                entity,
+                pipeline_id,
+                draw_function: draw_prepass,
+                distance: rangefinder.distance(&mesh_uniform.transform),
+            });
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Contains the [`GrassSampler`], which allows to query the grass from gameplay code
+use bevy::{
+    asset::Assets,
+    ecs::{
+        entity::Entity,
+        system::{Query, Res, SystemParam},
+    },
+    math::{Vec2, Vec3, Vec3A, Vec3Swizzles, Vec4, Vec4Swizzles},
+    render::{
+        color::SrgbColorSpace, primitives::Aabb, render_resource::TextureFormat, texture::Image,
+    },
+    transform::components::GlobalTransform,
+};
+
+use crate::{
+    bundle::{MapSampling, WarblerHeight},
+    dithering::{channel_reader, luma},
+    map::{DensityMap, NormalMap, YMap},
+    render::grass_pipeline::TextureBinding,
+};
+
+/// The values of the grass at a position, as returned by the [`GrassSampler`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GrassSample {
+    /// The grass chunk containing the position
+    pub chunk: Entity,
+    /// The position on the ground of the chunk in world space.
+    ///
+    /// It lies directly above or below the sampled position, if the chunk isn't rotated
+    pub ground: Vec3,
+    /// The direction the grass blades are aligned to in world space
+    pub normal: Vec3,
+    /// The height of the grass blades in world units
+    ///
+    /// The random [`BladeSizeVariation`](crate::bundle::BladeSizeVariation) of the single blades is not included
+    pub height: f32,
+    /// The value of the [`DensityMap`] ranging from 0 to 1
+    pub density: f32,
+}
+
+/// A [`SystemParam`] sampling the [`YMap`], [`NormalMap`], [`DensityMap`] and [`WarblerHeight`]
+/// of the grass chunks in the same way the grass shader does.
+///
+/// # Example
+/// ```rust
+/// use bevy::prelude::*;
+/// use warbler_grass::prelude::*;
+///
+/// fn place_on_grass(mut props: Query<&mut Transform, With<Handle<Mesh>>>, grass: GrassSampler) {
+///     for mut transform in &mut props {
+///         if let Some(sample) = grass.sample(transform.translation) {
+///             transform.translation.y = sample.ground.y + sample.height;
+///         }
+///     }
+/// }
+/// # bevy::ecs::system::assert_is_system(place_on_grass);
+/// ```
+#[derive(SystemParam)]
+pub struct GrassSampler<'w, 's> {
+    #[allow(clippy::type_complexity)]
+    chunks: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static GlobalTransform,
+            &'static Aabb,
+            &'static YMap,
+            &'static NormalMap,
+            &'static DensityMap,
+            &'static WarblerHeight,
+            Option<&'static MapSampling>,
+        ),
+    >,
+    images: Res<'w, Assets<Image>>,
+}
+impl<'w, 's> GrassSampler<'w, 's> {
+    /// Samples the grass chunk covering the world `position` on the x,z plane of the chunk
+    ///
+    /// Returns `None` if no chunk covers the position or its images aren't loaded yet
+    pub fn sample(&self, position: Vec3) -> Option<GrassSample> {
+        self.chunks
+            .iter()
+            .filter(|(_, transform, aabb, ..)| chunk_contains(transform, aabb, position))
+            .find_map(|(chunk, ..)| self.sample_chunk(chunk, position))
+    }
+    /// Samples the grass chunk `chunk` at the world `position`
+    ///
+    /// The position doesn't need to lie inside of the chunk.
+    /// Returns `None` if the entity is no grass chunk or its images aren't loaded yet
+    pub fn sample_chunk(&self, chunk: Entity, position: Vec3) -> Option<GrassSample> {
+        let (_, transform, aabb, y_map, normal_map, density_map, height, sampling) =
+            self.chunks.get(chunk).ok()?;
+        let sampling = sampling.copied().unwrap_or_default();
+        let size = aabb.half_extents.xz() * 2.;
+        let local = transform.affine().inverse().transform_point3(position).xz();
+
+        let y_map = self.images.get(&y_map.y_map)?;
+        let y = sample_map(y_map, local, size, sampling)?.x * aabb.half_extents.y * 2.;
+
+        let normal_map = self.images.get(&normal_map.normal_map)?;
+        let normal = sample_map(normal_map, local, size, sampling)?.truncate();
+        let normal = Vec3::new(normal.x.sqrt(), normal.y.sqrt(), normal.z.sqrt());
+        let normal = (normal * 2. - Vec3::ONE).normalize_or_zero();
+        // the blades are scaled by the transform of the chunk
+        let blade = Vec3::from(transform.affine().matrix3 * Vec3A::from(normal));
+
+        let height = match height {
+            WarblerHeight::Uniform(height) => *height,
+            WarblerHeight::Texture { texture, min, max } => {
+                let texture = self.images.get(texture)?;
+                let value = sample_map(texture, local, size, sampling)?.x;
+                min + (max - min) * value
+            }
+        };
+
+        let density_map = self.images.get(&density_map.density_map)?;
+        let density = sample_density(density_map, local / size)?;
+
+        Some(GrassSample {
+            chunk,
+            ground: transform.transform_point(Vec3::new(local.x, y, local.y)),
+            normal: blade.normalize_or_zero(),
+            height: height * blade.length(),
+            density,
+        })
+    }
+}
+
+/// Returns true if the `position` lies inside of the chunk on its x,z plane
+fn chunk_contains(transform: &GlobalTransform, aabb: &Aabb, position: Vec3) -> bool {
+    let size = aabb.half_extents.xz() * 2.;
+    let local = transform.affine().inverse().transform_point3(position).xz();
+    local.cmpge(Vec2::ZERO).all() && local.cmple(size).all()
+}
+
+/// Samples the `image` covering the field of the size `size` at `position` like the grass shader
+pub(crate) fn sample_map(
+    image: &Image,
+    position: Vec2,
+    size: Vec2,
+    sampling: MapSampling,
+) -> Option<Vec4> {
+    let format = image.texture_descriptor.format;
+    let dimensions = image.size();
+    let position = (position / size).abs() * dimensions;
+    match TextureBinding::new(format, sampling) {
+        TextureBinding::Filtered => {
+            // texels are centered in their pixel
+            let position = position - 0.5;
+            let min = position.floor();
+            let t = position - min;
+            let texel = |x: f32, y: f32| shader_texel(image, Vec2::new(x, y));
+            let top = texel(min.x, min.y)?.lerp(texel(min.x + 1., min.y)?, t.x);
+            let bottom = texel(min.x, min.y + 1.)?.lerp(texel(min.x + 1., min.y + 1.)?, t.x);
+            Some(top.lerp(bottom, t.y))
+        }
+        TextureBinding::Float | TextureBinding::Uint => shader_texel(image, position),
+    }
+}
+
+/// Samples the gray scale value of the density map at the normalized `position` like the dithering
+fn sample_density(image: &Image, position: Vec2) -> Option<f32> {
+    let texel = texel(image, position.abs() * image.size())?;
+    let format = image.texture_descriptor.format;
+    match format.block_size(None)? / channel_size(format)? as u32 {
+        1 | 2 => Some(texel.x),
+        _ => Some(luma(texel.x, texel.y, texel.z)),
+    }
+}
+
+/// Loads the texel at `position` and converts srgb colors to linear space, like the gpu does
+pub(crate) fn shader_texel(image: &Image, position: Vec2) -> Option<Vec4> {
+    let texel = texel(image, position)?;
+    if !image.texture_descriptor.format.is_srgb() {
+        return Some(texel);
+    }
+    let [r, g, b, a] = texel.to_array();
+    Some(Vec4::new(
+        r.nonlinear_to_linear_srgb(),
+        g.nonlinear_to_linear_srgb(),
+        b.nonlinear_to_linear_srgb(),
+        a,
+    ))
+}
+
+/// Loads the normalized texel at `position`, which is clamped to the border of the `image`
+///
+/// Missing channels are filled in like on the gpu
+fn texel(image: &Image, position: Vec2) -> Option<Vec4> {
+    let format = image.texture_descriptor.format;
+    let (width, height) = (
+        image.texture_descriptor.size.width,
+        image.texture_descriptor.size.height,
+    );
+    let x = (position.x.max(0.) as u32).min(width.checked_sub(1)?);
+    let y = (position.y.max(0.) as u32).min(height.checked_sub(1)?);
+    let pixel_size = format.block_size(None)? as usize;
+    let channel_size = channel_size(format)?;
+    let index = (y * width + x) as usize * pixel_size;
+    let pixel = image.data.get(index..index + pixel_size)?;
+
+    let read: fn(&[u8]) -> f32 = match channel_reader(format) {
+        Some((_, read)) => read,
+        None => |bytes| bytes[0] as f32 / 255.,
+    };
+    let mut texel = Vec4::new(0., 0., 0., 1.);
+    for (i, channel) in pixel.chunks_exact(channel_size).enumerate() {
+        texel[i] = read(channel);
+    }
+    if matches!(
+        format,
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb
+    ) {
+        texel = texel.zyxw();
+    }
+    Some(texel)
+}
+
+/// Returns the size of one channel of the `format` in bytes
+fn channel_size(format: TextureFormat) -> Option<usize> {
+    match format {
+        TextureFormat::R8Unorm
+        | TextureFormat::Rg8Unorm
+        | TextureFormat::Rgba8Unorm
+        | TextureFormat::Rgba8UnormSrgb
+        | TextureFormat::Bgra8Unorm
+        | TextureFormat::Bgra8UnormSrgb => Some(1),
+        _ => channel_reader(format).map(|(size, _)| size),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+    use bevy::prelude::*;
+    use bevy::render::primitives::Aabb;
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    use super::GrassSampler;
+    use crate::prelude::*;
+
+    /// A 2x1 image with the values `left` and `right`
+    fn image(left: f32, right: f32) -> Image {
+        let size = Extent3d {
+            width: 2,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        let data = [left, right].iter().flat_map(|v| v.to_le_bytes()).collect();
+        Image::new(size, TextureDimension::D2, data, TextureFormat::R32Float)
+    }
+    #[test]
+    fn sample_grass() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .add_asset::<Image>();
+        let world = &mut app.world;
+        let mut images = world.resource_mut::<Assets<Image>>();
+        let y_map = images.add(image(0., 1.));
+        let density_map = images.add(image(0.25, 0.75));
+        let mut r16 = image(0., 0.);
+        r16.texture_descriptor.format = TextureFormat::R16Unorm;
+        r16.data = [0_u16, u16::MAX]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let height = images.add(r16);
+
+        let transform = Transform::from_xyz(10., 1., 0.);
+        let chunk = world
+            .spawn(WarblersBundle {
+                y_map: y_map.into(),
+                density_map: density_map.into(),
+                height: WarblerHeight::Texture {
+                    texture: height,
+                    min: 1.,
+                    max: 3.,
+                },
+                aabb: Aabb::from_min_max(Vec3::ZERO, Vec3::new(4., 2., 4.)),
+                ..default()
+            })
+            .insert(GlobalTransform::from(transform))
+            .id();
+        // the normal map is stored in linear space
+        let normal_map = Image::new(
+            Extent3d::default(),
+            TextureDimension::D2,
+            [0.25_f32, 1., 0.25, 1.]
+                .iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect(),
+            TextureFormat::Rgba32Float,
+        );
+        let normal_map = world.resource_mut::<Assets<Image>>().add(normal_map);
+        world.entity_mut(chunk).insert(NormalMap::from(normal_map));
+
+        let mut state = SystemState::<GrassSampler>::new(world);
+        let sampler = state.get(world);
+        assert!(sampler.sample(Vec3::new(9., 0., 1.)).is_none());
+        assert!(sampler.sample(Vec3::new(11., 0., 5.)).is_none());
+
+        let left = sampler.sample(Vec3::new(11., 5., 1.)).unwrap();
+        assert_eq!(left.chunk, chunk);
+        assert_eq!(left.ground, Vec3::new(11., 1., 1.));
+        assert_eq!(left.density, 0.25);
+        assert!((left.height - 1.).abs() < 0.001);
+
+        let right = sampler.sample(Vec3::new(13., 0., 1.)).unwrap();
+        assert_eq!(right.ground, Vec3::new(13., 3., 1.));
+        assert_eq!(right.density, 0.75);
+        assert!((right.height - 3.).abs() < 0.001);
+        assert!(right.normal.abs_diff_eq(Vec3::Y, 0.01));
+
+        // linear sampling interpolates between the pixels
+        world.entity_mut(chunk).insert(MapSampling::Linear);
+        let sampler = state.get(world);
+        let between = sampler.sample(Vec3::new(12., 0., 1.)).unwrap();
+        // R32Float textures can't be filtered and use the nearest pixel
+        assert_eq!(between.ground.y, 1. + 2.);
+        assert!((between.height - 2.).abs() < 0.001);
+        // the density map is always sampled like the dithering does
+        assert_eq!(between.density, 0.75);
+    }
+}
@@ -1,29 +1,63 @@
 use bevy::{
     app::Plugin,
     asset::{load_internal_asset, Assets, HandleUntyped},
-    core_pipeline::core_3d::Opaque3d,
+    core_pipeline::{core_3d::Opaque3d, prepass::Opaque3dPrepass},
+    pbr::{PrepassViewBindGroup, Shadow},
     prelude::*,
     reflect::TypeUuid,
     render::{
         extract_component::ExtractComponentPlugin,
         extract_resource::ExtractResourcePlugin,
+        main_graph::node::CAMERA_DRIVER,
         mesh::{Indices, Mesh},
-        render_asset::RenderAssetPlugin,
+        render_graph::RenderGraph,
         render_phase::AddRenderCommand,
         render_resource::{
-            Extent3d, PrimitiveTopology, Shader, SpecializedMeshPipelines, TextureDescriptor,
-            TextureDimension, TextureFormat, TextureUsages,
+            BufferUsages, Extent3d, PrimitiveTopology, Shader, SpecializedMeshPipelines,
+            TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
         },
+        renderer::{RenderAdapterInfo, RenderDevice},
         texture::{BevyDefault, FallbackImage, ImageSampler, TextureFormatPixelInfo},
         Render, RenderApp, RenderSet,
     },
+    transform::TransformSystem,
 };
 
 use crate::{
-    dithering::{add_dither_to_density, DitheredBuffer},
-    map::{NormalMap, YMap},
-    prelude::{GrassColor, WarblerHeight},
-    render::{self, cache::UniformBuffer, extract, grass_pipeline::GrassPipeline, prepare, queue},
+    displacement::{remove_trample_map, trample_grass},
+    dithering::{
+        add_dither_to_density, extract_dithered_buffers, insert_dithered_buffer,
+        prepare_dithered_buffers, redither_dirty_regions, remove_dithered_buffer, DitherCache,
+        DitherPatches, DitheredBuffer, ExtractedDitheredBuffers, GpuDitheredBuffers,
+    },
+    map::{allow_map_copies, NormalMap, YMap},
+    prelude::{
+        BladeSizeVariation, DitheringSpace, GpuCulling, GrassColor, GrassFade, GrassLighting,
+        GrassLod, MapSampling, NoPrepass, WarblerHeight,
+    },
+    render::{
+        self,
+        batch::{
+            batch_grass_chunks, batching_supported, extract_copyable_images, CopyableImages,
+            GrassBatchNode, GrassBatches,
+        },
+        cache::UniformBuffer,
+        culling::{
+            culling_supported, prepare_grass_culling, GrassCulling, GrassCullingNode,
+            GrassCullingPipeline,
+        },
+        displacement::{
+            extract_grass_displacers, extract_trample_maps, prepare_grass_displacers,
+            prepare_trample_maps, ExtractedDisplacers, ExtractedTrampleMaps, GpuGrassDisplacers,
+            GpuTrampleMaps,
+        },
+        extract,
+        grass_pipeline::{GrassPipeline, GrassPrepassPipeline},
+        lod::{self, GrassLods},
+        prepare::{self, GpuWindZones},
+        queue,
+    },
+    wind::tick_wind_impulses,
     GrassConfiguration, GrassNoiseTexture,
 };
 
@@ -31,6 +65,10 @@ use crate::{
 pub(crate) const GRASS_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 2_263_343_952_151_597_127);
 
+/// A raw handle which points to the compute shader culling the blades of chunks with a [`GpuCulling`] component.
+pub(crate) const GRASS_CULLING_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 7_415_309_845_128_690_231);
+
 /// A raw handle to the default mesh used for grass.
 ///
 /// The [`WarblersPlugin`] adds the corresponding mesh to the world.
@@ -58,6 +96,12 @@ impl Plugin for WarblersPlugin {
             "render/assets/grass_shader.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(
+            app,
+            GRASS_CULLING_SHADER_HANDLE,
+            "render/assets/grass_culling.wgsl",
+            Shader::from_wgsl
+        );
 
         // Load default grass blade mesh
         let mut meshes = app.world.resource_mut::<Assets<Mesh>>();
@@ -67,9 +111,30 @@ impl Plugin for WarblersPlugin {
         let mut images = app.world.resource_mut::<Assets<Image>>();
         images.set_untracked(DEFAULT_NORMAL_MAP_HANDLE, default_normal_map());
 
-        app.add_systems(Update, add_dither_to_density)
-            .add_asset::<DitheredBuffer>()
-            .add_plugins(RenderAssetPlugin::<DitheredBuffer>::default());
+        app.add_systems(
+            PostUpdate,
+            (
+                (
+                    add_dither_to_density,
+                    // the jobs of the entities need to be known before polling them
+                    apply_deferred,
+                    insert_dithered_buffer,
+                    apply_deferred,
+                    redither_dirty_regions,
+                )
+                    .chain()
+                    // chunks dithered in world space need their current position
+                    .after(TransformSystem::TransformPropagate),
+                remove_dithered_buffer,
+                // the trails are left at the current positions of the displacers
+                trample_grass.after(TransformSystem::TransformPropagate),
+                remove_trample_map,
+            ),
+        )
+        .add_systems(Update, tick_wind_impulses)
+        .add_asset::<DitheredBuffer>()
+        .init_resource::<DitherCache>()
+        .init_resource::<DitherPatches>();
         // Init resources
         app.init_resource::<GrassConfiguration>()
             .register_type::<GrassConfiguration>()
@@ -82,23 +147,53 @@ impl Plugin for WarblersPlugin {
             ExtractComponentPlugin::<NormalMap>::default(),
             ExtractComponentPlugin::<WarblerHeight>::default(),
             ExtractComponentPlugin::<GrassColor>::default(),
+            ExtractComponentPlugin::<DitheringSpace>::default(),
+            ExtractComponentPlugin::<MapSampling>::default(),
+            ExtractComponentPlugin::<BladeSizeVariation>::default(),
+            ExtractComponentPlugin::<GrassLighting>::default(),
+            ExtractComponentPlugin::<NoPrepass>::default(),
+            ExtractComponentPlugin::<GrassLod>::default(),
+            ExtractComponentPlugin::<GrassFade>::default(),
+            ExtractComponentPlugin::<GpuCulling>::default(),
         ));
         // Init render app
         app.sub_app_mut(RenderApp)
             .add_render_command::<Opaque3d, render::GrassDrawCall>()
+            .add_render_command::<Opaque3d, render::GrassBatchDrawCall>()
             .init_resource::<SpecializedMeshPipelines<GrassPipeline>>()
+            .init_resource::<ExtractedDitheredBuffers>()
+            .init_resource::<GpuDitheredBuffers>()
+            .init_resource::<GrassLods>()
+            .init_resource::<GrassCulling>()
+            .init_resource::<GrassBatches>()
+            .init_resource::<ExtractedDisplacers>()
+            .init_resource::<ExtractedTrampleMaps>()
             .add_systems(
                 ExtractSchedule,
-                (extract::extract_grass, extract::extract_aabb),
+                (
+                    extract::extract_grass,
+                    extract::extract_aabb,
+                    extract::extract_wind_zones,
+                    extract_grass_displacers,
+                    extract_trample_maps,
+                    extract_dithered_buffers,
+                ),
             )
             .add_systems(
                 Render,
                 (
-                    prepare::prepare_uniform_buffers,
-                    prepare::prepare_height_buffer,
+                    prepare_dithered_buffers,
+                    prepare::prepare_wind_zones,
+                    prepare_grass_displacers,
+                    // the bind group is recreated if the buffer of the displacers grows
+                    prepare::prepare_uniform_buffers.after(prepare_grass_displacers),
+                    // the blade count of the chunks is needed for their level of detail
+                    prepare::prepare_height_buffer.after(prepare_dithered_buffers),
                     prepare::prepare_grass_color,
-                    prepare::prepare_y_map_buffer,
+                    prepare_trample_maps,
+                    prepare::prepare_y_map_buffer.after(prepare_trample_maps),
                     prepare::prepare_normal_map_buffer,
+                    lod::select_grass_lods,
                 )
                     .in_set(RenderSet::Prepare),
             )
@@ -113,7 +208,66 @@ impl Plugin for WarblersPlugin {
         render_app
             .init_resource::<FallbackImage>()
             .init_resource::<GrassPipeline>()
+            .init_resource::<GpuWindZones>()
+            .init_resource::<GpuGrassDisplacers>()
+            .init_resource::<GpuTrampleMaps>()
             .init_resource::<UniformBuffer>();
+
+        // The shadows and the prepass of the grass use the view bind group of bevy's prepass,
+        // which only exists if the prepass of the `StandardMaterial` is enabled
+        if render_app.world.contains_resource::<PrepassViewBindGroup>() {
+            render_app
+                .add_render_command::<Shadow, render::GrassPrepassDrawCall>()
+                .add_render_command::<Opaque3dPrepass, render::GrassPrepassDrawCall>()
+                .init_resource::<GrassPrepassPipeline>()
+                .init_resource::<SpecializedMeshPipelines<GrassPrepassPipeline>>()
+                .add_systems(
+                    Render,
+                    (queue::queue_grass_shadows, queue::queue_grass_prepass)
+                        .in_set(RenderSet::Queue),
+                );
+        }
+
+        // Chunks with a `GpuCulling` component are drawn without culling if compute shaders aren't supported
+        if culling_supported(render_app.world.resource::<RenderDevice>()) {
+            // the culling shader reads the blades from the dithered buffers
+            render_app.world.resource_mut::<GpuDitheredBuffers>().usage |= BufferUsages::STORAGE;
+            render_app
+                .init_resource::<GrassCullingPipeline>()
+                .add_systems(
+                    Render,
+                    prepare_grass_culling
+                        .in_set(RenderSet::Prepare)
+                        .after(lod::select_grass_lods)
+                        .after(prepare_dithered_buffers)
+                        .after(prepare_grass_displacers)
+                        .after(prepare_trample_maps),
+                );
+            let mut graph = render_app.world.resource_mut::<RenderGraph>();
+            graph.add_node(GrassCullingNode::NAME, GrassCullingNode);
+            graph.add_node_edge(GrassCullingNode::NAME, CAMERA_DRIVER);
+        }
+
+        // Chunks sharing a mesh and the format and size of their maps are drawn together if storage buffers are supported
+        if batching_supported(
+            render_app.world.resource::<RenderDevice>(),
+            render_app.world.resource::<RenderAdapterInfo>(),
+        ) {
+            render_app
+                .init_resource::<CopyableImages>()
+                .add_systems(ExtractSchedule, extract_copyable_images)
+                .add_systems(
+                    Render,
+                    batch_grass_chunks
+                        .in_set(RenderSet::Queue)
+                        .before(queue::queue_grass_buffers),
+                );
+            let mut graph = render_app.world.resource_mut::<RenderGraph>();
+            graph.add_node(GrassBatchNode::NAME, GrassBatchNode);
+            graph.add_node_edge(GrassBatchNode::NAME, CAMERA_DRIVER);
+            // the maps of the chunks are copied into the texture arrays of the batches
+            app.add_systems(PostUpdate, allow_map_copies);
+        }
     }
 }
 
@@ -139,7 +293,10 @@ fn default_normal_map() -> Image {
             label: None,
             mip_level_count: 1,
             sample_count: 1,
-            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            // copied into the texture arrays of batched chunks
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::COPY_SRC,
             view_formats: &[],
         },
         sampler_descriptor: ImageSampler::Default,
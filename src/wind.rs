@@ -0,0 +1,561 @@
+//! Contains the [`WindZone`] component adding local wind to the grass
+//! and the [`GrassWind`], which calculates the wind displacement of the grass blades on the cpu
+use std::f32::consts::{SQRT_2, TAU};
+
+use bevy::{
+    asset::Assets,
+    ecs::{
+        component::Component,
+        entity::Entity,
+        system::{Commands, Query, Res, SystemParam},
+    },
+    hierarchy::DespawnRecursiveExt,
+    math::{Mat4, UVec2, Vec2, Vec3, Vec3Swizzles},
+    render::texture::Image,
+    time::Time,
+    transform::components::GlobalTransform,
+};
+use bytemuck::{Pod, Zeroable};
+
+use crate::{sampler::shader_texel, GrassConfiguration, GrassNoiseTexture};
+
+/// The size of the swirls of the turbulence relative to the wind noise
+const TURBULENCE_ZOOM: f32 = 4.;
+/// The maximum amount of [`WindZone`]s affecting the grass.
+///
+/// Further zones are ignored
+pub const MAX_WIND_ZONES: usize = 16;
+
+/// A [`Component`] adding local wind to the grass around its entity.
+///
+/// The wind of all zones at a position is added to the global [`GrassConfiguration::wind`].
+/// Use a [`WindZone::Directional`] with the negated global wind to shelter an area from it.
+/// At most [`MAX_WIND_ZONES`] zones affect the grass.
+///
+/// # Example
+/// ```rust
+/// use bevy::prelude::*;
+/// use warbler_grass::prelude::*;
+///
+/// fn explode(mut commands: Commands) {
+///     commands.spawn((
+///         // the shockwave of an explosion, whose entity is despawned after a second
+///         WindZone::impulse(20., 30., 5., 1.).despawn_on_end(),
+///         TransformBundle::from_transform(Transform::from_xyz(10., 0., 10.)),
+///     ));
+/// }
+/// # bevy::ecs::system::assert_is_system(explode);
+/// ```
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub enum WindZone {
+    /// Adds the `wind` to the grass rooted inside of a box with the `half_extents` around the entity.
+    ///
+    /// The box is rotated and scaled with the entity.
+    /// The wind fades in over a distance of `blend` from the border of the box in the space of the entity
+    Directional {
+        wind: Vec2,
+        half_extents: Vec3,
+        blend: f32,
+    },
+    /// Blows the grass away from the entity on the x,z plane, like the downwash of a helicopter.
+    ///
+    /// A negative `strength` pulls the grass towards the entity.
+    /// The wind weakens with the distance to the entity and vanishes at the `radius`,
+    /// where `falloff` is the exponent of the curve. A falloff of 0 keeps the strength constant
+    Radial {
+        strength: f32,
+        radius: f32,
+        falloff: f32,
+    },
+    /// A shockwave blowing the grass away from the entity.
+    ///
+    /// It expands as a ring with the `width` from the entity to the `radius` during its `duration` in seconds,
+    /// while its `strength` fades out. The component is removed after the `duration`,
+    /// the whole entity is despawned if `despawn_on_end` is set
+    Impulse {
+        strength: f32,
+        radius: f32,
+        width: f32,
+        duration: f32,
+        /// The seconds since the start of the impulse
+        elapsed: f32,
+        despawn_on_end: bool,
+    },
+}
+impl WindZone {
+    /// Creates a [`WindZone::Impulse`] starting now
+    ///
+    /// Only the component is removed after the `duration`, see [`WindZone::despawn_on_end`]
+    pub fn impulse(strength: f32, radius: f32, width: f32, duration: f32) -> Self {
+        WindZone::Impulse {
+            strength,
+            radius,
+            width,
+            duration,
+            elapsed: 0.,
+            despawn_on_end: false,
+        }
+    }
+    /// Despawns the entity of a [`WindZone::Impulse`] with its children after its duration,
+    /// for entities which are only spawned for the impulse. Other zones are returned unchanged
+    pub fn despawn_on_end(mut self) -> Self {
+        if let WindZone::Impulse { despawn_on_end, .. } = &mut self {
+            *despawn_on_end = true;
+        }
+        self
+    }
+}
+
+/// Advances the [`WindZone::Impulse`]s and removes them or their entity after their duration
+pub(crate) fn tick_wind_impulses(
+    mut commands: Commands,
+    mut zones: Query<(Entity, &mut WindZone)>,
+    time: Res<Time>,
+) {
+    for (entity, mut zone) in &mut zones {
+        if let WindZone::Impulse {
+            elapsed,
+            duration,
+            despawn_on_end,
+            ..
+        } = zone.as_mut()
+        {
+            *elapsed += time.delta_seconds();
+            if *elapsed < *duration {
+                continue;
+            }
+            if *despawn_on_end {
+                commands.entity(entity).despawn_recursive();
+            } else {
+                commands.entity(entity).remove::<WindZone>();
+            }
+        }
+    }
+}
+
+const DIRECTIONAL_ZONE: u32 = 0;
+const RADIAL_ZONE: u32 = 1;
+const RING_ZONE: u32 = 2;
+
+/// A [`WindZone`] as used by the grass shader
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub(crate) struct ShaderWindZone {
+    /// Transforms world positions into the space of a directional zone
+    inverse_model: Mat4,
+    center: Vec3,
+    kind: u32,
+    wind: Vec2,
+    strength: f32,
+    radius: f32,
+    half_extents: Vec3,
+    /// The blend of a directional zone, the falloff of a radial zone or the width of a ring
+    falloff: f32,
+}
+impl ShaderWindZone {
+    pub(crate) fn new(zone: &WindZone, transform: &GlobalTransform) -> Self {
+        let mut shader_zone = ShaderWindZone {
+            inverse_model: transform.compute_matrix().inverse(),
+            center: transform.translation(),
+            ..Zeroable::zeroed()
+        };
+        match *zone {
+            WindZone::Directional {
+                wind,
+                half_extents,
+                blend,
+            } => {
+                shader_zone.kind = DIRECTIONAL_ZONE;
+                shader_zone.wind = wind;
+                shader_zone.half_extents = half_extents;
+                shader_zone.falloff = blend;
+            }
+            WindZone::Radial {
+                strength,
+                radius,
+                falloff,
+            } => {
+                shader_zone.kind = RADIAL_ZONE;
+                shader_zone.strength = strength;
+                shader_zone.radius = radius;
+                shader_zone.falloff = falloff;
+            }
+            WindZone::Impulse {
+                strength,
+                radius,
+                width,
+                duration,
+                elapsed,
+                ..
+            } => {
+                let progress = (elapsed / duration.max(f32::EPSILON)).clamp(0., 1.);
+                shader_zone.kind = RING_ZONE;
+                shader_zone.strength = strength * (1. - progress);
+                shader_zone.radius = radius * progress;
+                shader_zone.falloff = width;
+            }
+        }
+        shader_zone
+    }
+    /// Returns the wind of the zone at the world `position` like the grass shader
+    pub(crate) fn wind(&self, position: Vec3) -> Vec2 {
+        if self.kind == DIRECTIONAL_ZONE {
+            let local = self.inverse_model.transform_point3(position);
+            let border = (self.half_extents - local.abs()).min_element();
+            return self.wind * (border / self.falloff.max(1e-4)).clamp(0., 1.);
+        }
+        let offset = (position - self.center).xz();
+        let distance = offset.length();
+        let weight = match self.kind {
+            RADIAL_ZONE if distance < self.radius => {
+                (1. - distance / self.radius).powf(self.falloff)
+            }
+            RING_ZONE => (1. - (distance - self.radius).abs() / self.falloff.max(1e-4)).max(0.),
+            _ => 0.,
+        };
+        if distance < 1e-4 {
+            return Vec2::ZERO;
+        }
+        offset / distance * self.strength * weight
+    }
+    /// The maximum strength of the wind of the zone
+    fn max_wind(&self) -> f32 {
+        self.wind.length() + self.strength.abs()
+    }
+}
+
+/// The [`WindZone`]s affecting the grass as used by the grass shader
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub(crate) struct ShaderWindZones {
+    count: u32,
+    /// Wasm requires shader uniforms to be aligned to 16 bytes
+    _wasm_padding: u32,
+    _wasm_padding_2: UVec2,
+    zones: [ShaderWindZone; MAX_WIND_ZONES],
+}
+impl Default for ShaderWindZones {
+    fn default() -> Self {
+        Zeroable::zeroed()
+    }
+}
+impl FromIterator<ShaderWindZone> for ShaderWindZones {
+    /// Collects the first [`MAX_WIND_ZONES`] zones
+    fn from_iter<T: IntoIterator<Item = ShaderWindZone>>(iter: T) -> Self {
+        let mut zones = ShaderWindZones::default();
+        for (zone, shader_zone) in zones.zones.iter_mut().zip(iter) {
+            *zone = shader_zone;
+            zones.count += 1;
+        }
+        zones
+    }
+}
+impl ShaderWindZones {
+    /// Returns the wind of all zones at the world `position`
+    pub(crate) fn wind(&self, position: Vec3) -> Vec2 {
+        self.zones().iter().map(|zone| zone.wind(position)).sum()
+    }
+    /// The maximum strength of the wind of all zones at any position
+    pub(crate) fn max_wind(&self) -> f32 {
+        self.zones().iter().map(ShaderWindZone::max_wind).sum()
+    }
+    fn zones(&self) -> &[ShaderWindZone] {
+        &self.zones[..self.count as usize]
+    }
+}
+
+/// A [`SystemParam`] calculating the wind displacement of the grass blades in the same way the grass shader does.
+///
+/// The wind is sampled in world space, so it can be used to let other objects sway along with the grass.
+///
+/// # Example
+/// ```rust
+/// use bevy::prelude::*;
+/// use warbler_grass::wind::GrassWind;
+///
+/// #[derive(Component)]
+/// struct Flower;
+///
+/// fn sway_flowers(mut flowers: Query<&mut Transform, With<Flower>>, wind: GrassWind) {
+///     for mut transform in &mut flowers {
+///         let root = transform.translation;
+///         if let Some(offset) = wind.displacement(root, 1.) {
+///             transform.rotation = Quat::from_rotation_arc(Vec3::Y, (Vec3::Y + offset).normalize());
+///         }
+///     }
+/// }
+/// # bevy::ecs::system::assert_is_system(sway_flowers);
+/// ```
+#[derive(SystemParam)]
+pub struct GrassWind<'w, 's> {
+    config: Res<'w, GrassConfiguration>,
+    noise: Res<'w, GrassNoiseTexture>,
+    images: Res<'w, Assets<Image>>,
+    time: Res<'w, Time>,
+    zones: Query<'w, 's, (&'static WindZone, &'static GlobalTransform)>,
+}
+impl<'w, 's> GrassWind<'w, 's> {
+    /// Returns the wind at the world `position`, which is the global wind plus the wind of all [`WindZone`]s
+    pub fn wind(&self, position: Vec3) -> Vec2 {
+        let zones: ShaderWindZones = self
+            .zones
+            .iter()
+            .map(|(zone, transform)| ShaderWindZone::new(zone, transform))
+            .collect();
+        self.config.wind + zones.wind(position)
+    }
+    /// Returns the offset of the blades rooted at the world `position` on the x,z plane
+    ///
+    /// Returns `None` if the [`GrassNoiseTexture`] isn't loaded
+    pub fn offset(&self, position: Vec3) -> Option<Vec2> {
+        let noise = self.images.get(&self.noise.0)?;
+        // the shader uses the wrapped time of bevy's globals
+        let time = self.time.elapsed_seconds_wrapped();
+        let wind = self.wind(position);
+        wind_offset(noise, &self.config, wind, position.xz(), time)
+    }
+    /// Returns the displacement of a vertex at `vertex_height` in the grass mesh
+    /// of a blade rooted at the world `position`
+    ///
+    /// The bottom of the blade at a height of 0 isn't moved by the wind.
+    /// Returns `None` if the [`GrassNoiseTexture`] isn't loaded
+    pub fn displacement(&self, position: Vec3, vertex_height: f32) -> Option<Vec3> {
+        let strength = wind_strength(vertex_height, self.config.sway_stiffness);
+        let offset = self.offset(position)? * strength;
+        Some(Vec3::new(offset.x, 0., offset.y))
+    }
+}
+
+/// Returns the offset of the blades rooted at the world `position` on the x,z plane at the `time` in seconds
+///
+/// The `wind` is the wind at the position, including the wind of the [`WindZone`]s.
+/// The `noise` texture is tiled over the world and moves in the direction of the global wind of the `config`.
+/// Returns `None` if the `noise` texture is empty or its format isn't supported
+pub fn wind_offset(
+    noise: &Image,
+    config: &GrassConfiguration,
+    wind: Vec2,
+    position: Vec2,
+    time: f32,
+) -> Option<Vec2> {
+    let size = noise.size();
+    let velocity = config.wind * config.wind_scroll_speed;
+    let texture_position = position * config.wind_noise_scale - velocity * time;
+    let texel = |position: Vec2| {
+        let texel = shader_texel(noise, position.rem_euclid(size).floor())?;
+        Some(texel.truncate().truncate())
+    };
+    let mut offset = texel(texture_position)? * wind;
+
+    // the swirls move the blades in all directions
+    let swirl = texel(texture_position * TURBULENCE_ZOOM)? * 2. - Vec2::ONE;
+    offset += swirl * config.turbulence * wind.length();
+
+    // the gusts travel over the grass with the noise
+    let travel = texture_position.dot(velocity) / velocity.length_squared().max(1e-6);
+    let phase = -TAU * config.gust_frequency * travel;
+    let gust = config.gust_amplitude * (0.5 + 0.5 * phase.sin());
+    Some(offset * (1. + gust))
+}
+
+/// Returns how strongly the wind moves a vertex at `vertex_height` in the grass mesh
+///
+/// The bottom of the blade stays in place, the `stiffness` is the exponent of the curve
+pub fn wind_strength(vertex_height: f32, stiffness: f32) -> f32 {
+    (vertex_height.max(0.).powf(stiffness) + 1.).ln()
+}
+
+/// The maximum distance a `wind` with the given strength can move a vertex at a height of 1 in the grass mesh
+pub(crate) fn max_wind_offset(config: &GrassConfiguration, wind: f32) -> f32 {
+    let swirl = config.turbulence.abs() * SQRT_2;
+    let gust = config.gust_amplitude.max(0.);
+    wind * (1. + swirl) * (1. + gust)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::ecs::{schedule::Schedule, world::World};
+    use bevy::math::{Quat, Vec2, Vec3};
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+    use bevy::render::texture::Image;
+    use bevy::time::Time;
+    use bevy::transform::components::{GlobalTransform, Transform};
+
+    use super::{ShaderWindZone, ShaderWindZones, WindZone, MAX_WIND_ZONES};
+    use crate::GrassConfiguration;
+
+    /// A noise texture with the width `values.len()` and a height of 1
+    fn noise(values: &[u8]) -> Image {
+        let size = Extent3d {
+            width: values.len() as u32,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        let data = values.iter().flat_map(|v| [*v, *v, 0, 255]).collect();
+        Image::new(size, TextureDimension::D2, data, TextureFormat::Rgba8Unorm)
+    }
+    #[test]
+    fn wind_offset() {
+        let noise = noise(&[0, 51, 102, 255]);
+        let config = GrassConfiguration {
+            wind: Vec2::new(2., 0.),
+            ..Default::default()
+        };
+        let pixel = 1. / config.wind_noise_scale;
+        let offset = |config: &GrassConfiguration, x: f32, time: f32| {
+            super::wind_offset(&noise, config, config.wind, Vec2::new(x, 0.), time).unwrap()
+        };
+
+        assert_eq!(offset(&config, 0., 0.), Vec2::ZERO);
+        let expected = Vec2::new(0.8, 0.);
+        assert!(offset(&config, pixel * 2.5, 0.).abs_diff_eq(expected, 1e-5));
+        // the noise is tiled over the world, also for negative positions
+        assert_eq!(
+            offset(&config, pixel * 6.5, 0.),
+            offset(&config, pixel * 2.5, 0.)
+        );
+        assert_eq!(
+            offset(&config, -pixel * 1.5, 0.),
+            offset(&config, pixel * 2.5, 0.)
+        );
+
+        // a gust moves in the direction of the wind
+        let gusty = GrassConfiguration {
+            gust_amplitude: 1.,
+            gust_frequency: 0.3,
+            ..config.clone()
+        };
+        let travelled = config.wind.x * config.wind_scroll_speed / config.wind_noise_scale;
+        for x in [-3.1, 0.01, 0.05, 7.3] {
+            assert_eq!(offset(&config, x + travelled, 1.), offset(&config, x, 0.));
+            let moved = offset(&gusty, x + travelled, 1.);
+            assert!(moved.abs_diff_eq(offset(&gusty, x, 0.), 1e-3));
+        }
+        // the gusts strengthen the wind at most by the amplitude
+        let gusts: Vec<f32> = (0..100)
+            .map(|i| i as f32 * 0.1)
+            .filter(|time| offset(&config, 1., *time).x > 0.)
+            .map(|time| offset(&gusty, 1., time).x / offset(&config, 1., time).x)
+            .collect();
+        let strongest = gusts.iter().copied().fold(f32::MIN, f32::max);
+        let weakest = gusts.iter().copied().fold(f32::MAX, f32::min);
+        assert!(strongest > 1.99 && strongest <= 2. + 1e-5);
+        assert!((1. - 1e-5..1.01).contains(&weakest));
+
+        // the turbulence also moves the blades against the wind
+        let turbulent = GrassConfiguration {
+            turbulence: 1.,
+            ..config.clone()
+        };
+        assert!(offset(&turbulent, 0., 0.).x < 0.);
+
+        let calm = GrassConfiguration {
+            wind: Vec2::ZERO,
+            ..gusty
+        };
+        assert_eq!(offset(&calm, 1., 1.), Vec2::ZERO);
+    }
+    #[test]
+    fn wind_strength() {
+        assert_eq!(super::wind_strength(0., 1.), 0.);
+        assert_eq!(super::wind_strength(-0.5, 1.), 0.);
+        assert_eq!(super::wind_strength(1., 1.), 2_f32.ln());
+        // stiffer blades bend less below their tip
+        assert!(super::wind_strength(0.5, 2.) < super::wind_strength(0.5, 1.));
+        assert_eq!(super::wind_strength(1., 2.), 2_f32.ln());
+    }
+    #[test]
+    fn wind_zones() {
+        let transform = |transform: Transform| GlobalTransform::from(transform);
+        // the box of a directional zone is rotated with its entity
+        let rotated = Transform::from_xyz(10., 0., 0.).with_rotation(Quat::from_rotation_y(0.3));
+        let directional = ShaderWindZone::new(
+            &WindZone::Directional {
+                wind: Vec2::new(-1., 0.),
+                half_extents: Vec3::new(4., 2., 4.),
+                blend: 2.,
+            },
+            &transform(rotated),
+        );
+        assert_eq!(directional.wind(Vec3::new(10., 0., 0.)), Vec2::new(-1., 0.));
+        let border = rotated.transform_point(Vec3::new(3., 0., 0.));
+        assert!(directional
+            .wind(border)
+            .abs_diff_eq(Vec2::new(-0.5, 0.), 1e-5));
+        assert_eq!(directional.wind(Vec3::new(10., 3., 0.)), Vec2::ZERO);
+        assert_eq!(directional.wind(Vec3::new(15., 0., 0.)), Vec2::ZERO);
+
+        let radial = ShaderWindZone::new(
+            &WindZone::Radial {
+                strength: 2.,
+                radius: 4.,
+                falloff: 2.,
+            },
+            &transform(Transform::from_xyz(0., 5., 0.)),
+        );
+        // the wind blows away from the center on the x,z plane
+        assert_eq!(radial.wind(Vec3::new(0., 0., 2.)), Vec2::new(0., 0.5));
+        assert_eq!(radial.wind(Vec3::new(-2., 0., 0.)), Vec2::new(-0.5, 0.));
+        assert_eq!(radial.wind(Vec3::new(5., 0., 0.)), Vec2::ZERO);
+        assert_eq!(radial.wind(Vec3::new(0., 0., 0.)), Vec2::ZERO);
+
+        // an impulse expands as a ring and fades out
+        let mut impulse = WindZone::impulse(2., 10., 1., 2.);
+        let ring =
+            |impulse: &WindZone| ShaderWindZone::new(impulse, &transform(Transform::IDENTITY));
+        assert_eq!(ring(&impulse).wind(Vec3::new(5., 0., 0.)), Vec2::ZERO);
+        if let WindZone::Impulse { elapsed, .. } = &mut impulse {
+            *elapsed = 1.;
+        }
+        assert_eq!(
+            ring(&impulse).wind(Vec3::new(5., 0., 0.)),
+            Vec2::new(1., 0.)
+        );
+        assert_eq!(
+            ring(&impulse).wind(Vec3::new(5.5, 0., 0.)),
+            Vec2::new(0.5, 0.)
+        );
+        assert_eq!(ring(&impulse).wind(Vec3::new(3., 0., 0.)), Vec2::ZERO);
+
+        // the wind of the zones is summed up, but only the first zones are used
+        let zones: ShaderWindZones = [directional, radial].into_iter().collect();
+        let wind = zones.wind(Vec3::new(8., 0., 0.));
+        assert_eq!(wind, directional.wind(Vec3::new(8., 0., 0.)));
+        assert_eq!(zones.max_wind(), 3.);
+        let zones: ShaderWindZones = [radial; 20].into_iter().collect();
+        let wind = zones.wind(Vec3::new(0., 0., 2.));
+        assert_eq!(wind, Vec2::new(0., 0.5) * MAX_WIND_ZONES as f32);
+    }
+    #[test]
+    fn tick_wind_impulses() {
+        let mut world = World::new();
+        let mut time = Time::default();
+        let start = time.startup();
+        time.update_with_instant(start);
+        time.update_with_instant(start + Duration::from_secs_f32(0.5));
+        world.insert_resource(time);
+        let impulse = WindZone::impulse(2., 10., 1., 1.);
+        let kept = world.spawn(impulse).id();
+        let despawned = world.spawn(impulse.despawn_on_end()).id();
+        let mut schedule = Schedule::new();
+        schedule.add_systems(super::tick_wind_impulses);
+
+        schedule.run(&mut world);
+        assert!(world.get::<WindZone>(kept).is_some());
+        assert!(world.get::<WindZone>(despawned).is_some());
+
+        // only the entities spawned for the impulse are despawned after its duration
+        schedule.run(&mut world);
+        schedule.run(&mut world);
+        assert!(world.get_entity(kept).is_some());
+        assert!(world.get::<WindZone>(kept).is_none());
+        assert!(world.get_entity(despawned).is_none());
+    }
+    #[test]
+    fn wind_zone_layout_matches_shader() {
+        // the size of the `WindZones` struct in the grass shader
+        assert_eq!(std::mem::size_of::<ShaderWindZones>(), 16 + 16 * 112);
+    }
+}